@@ -0,0 +1,149 @@
+//! Benchmarks for key generation, ballot proofs, and decryption
+use benaloh_cryptosystem::{
+    arithmetics::{self, ClearResidue},
+    keys::KeyPair,
+    proofs::{
+        ballot::{self, BallotProof},
+        consonance::{VerifierContext, VoterProof},
+    },
+    BigInt, GROUPSIZE, RINGSIZE,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use crypto_bigint::modular::runtime_mod::DynResidue;
+
+/// The group size is kept small enough that n = pq fits in LIMBS words
+fn bench_keygen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("keygen");
+    group.sample_size(10);
+    for group_size in [GROUPSIZE, GROUPSIZE + 32] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(group_size),
+            &group_size,
+            |b, &group_size| b.iter(|| KeyPair::keygen(RINGSIZE, group_size, false)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_ballot_proof(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let classes = ballot::zero_or_one(keypair.get_pk().get_r());
+    let one = DynResidue::new(
+        &BigInt::ONE,
+        keypair.get_pk().get_r().to_dyn_residue_params(),
+    );
+    let statement = ClearResidue::random(Some(one), keypair.get_pk());
+
+    let mut group = c.benchmark_group("ballot_proof");
+    group.sample_size(10);
+    group.bench_function("from_statement", |b| {
+        b.iter(|| BallotProof::from_statement(&statement, &classes, keypair.get_pk()))
+    });
+    let proof = BallotProof::from_statement(&statement, &classes, keypair.get_pk());
+    group.bench_function("verify", |b| {
+        b.iter(|| proof.verify(&classes, keypair.get_pk()))
+    });
+    group.finish();
+}
+
+fn bench_decompose(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let ciphertext = ClearResidue::random(None, keypair.get_pk()).clone_val();
+
+    let mut group = c.benchmark_group("decompose");
+    group.sample_size(10);
+    group.bench_function("random_class", |b| {
+        b.iter(|| ClearResidue::decompose(ciphertext.clone_residue(), &keypair))
+    });
+    group.bench_function("random_class_crt", |b| {
+        b.iter(|| ClearResidue::decompose_crt(ciphertext.clone_residue(), &keypair))
+    });
+    group.bench_function("random_class_without_witness", |b| {
+        b.iter(|| keypair.try_decrypt(&ciphertext))
+    });
+    group.finish();
+}
+
+/// The constant-time discrete log should take as long for the largest class as for the smallest
+fn bench_discrete_log_ct(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let pk = keypair.get_pk();
+    let r = pk.get_r().modulus_ref();
+    let n = pk.get_n().modulus_ref();
+    let base = pk.get_y().pow(&keypair.phi_over_r());
+
+    let mut group = c.benchmark_group("discrete_log_ct");
+    group.sample_size(10);
+    for (name, exp) in [
+        ("smallest", BigInt::ONE),
+        ("largest", r.wrapping_sub(&BigInt::ONE)),
+    ] {
+        let target = base.pow(&exp).retrieve();
+        group.bench_function(name, |b| {
+            b.iter(|| arithmetics::discrete_log_ct(&base.retrieve(), &target, r, n))
+        });
+    }
+    group.finish();
+}
+
+/// Verifying many voter proofs with and without the precomputed verifier context
+fn bench_voter_proofs(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let proofs = (0..100)
+        .map(|_| {
+            let statement = ClearResidue::random(None, keypair.get_pk());
+            return VoterProof::from_statement(&statement, keypair.get_pk());
+        })
+        .collect::<Vec<VoterProof>>();
+
+    let mut group = c.benchmark_group("voter_proofs");
+    group.sample_size(10);
+    group.bench_function("verify", |b| {
+        b.iter(|| proofs.iter().all(|proof| proof.verify(&keypair)))
+    });
+    group.bench_function("verifier_context", |b| {
+        b.iter(|| {
+            let context = VerifierContext::new(&keypair);
+            return proofs.iter().all(|proof| context.verify(proof));
+        })
+    });
+    group.finish();
+}
+
+/// Building residues with borrowed parameters against cloning them on every call
+fn bench_params(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let n = keypair.get_pk().get_n();
+
+    let mut group = c.benchmark_group("params");
+    group.bench_function("to_dyn_residue_params", |b| {
+        b.iter(|| DynResidue::new(&BigInt::ONE, n.to_dyn_residue_params()))
+    });
+    group.bench_function("params", |b| {
+        b.iter(|| DynResidue::new(&BigInt::ONE, *n.params()))
+    });
+    group.finish();
+}
+
+/// Reading the cached inverse of y against inverting y on every call
+fn bench_invert_y(c: &mut Criterion) {
+    let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+    let pk = keypair.get_pk();
+
+    let mut group = c.benchmark_group("invert_y");
+    group.bench_function("cached", |b| b.iter(|| *pk.invert_y()));
+    group.bench_function("invert", |b| b.iter(|| pk.get_y().invert()));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_keygen,
+    bench_ballot_proof,
+    bench_decompose,
+    bench_discrete_log_ct,
+    bench_voter_proofs,
+    bench_params,
+    bench_invert_y
+);
+criterion_main!(benches);
@@ -1,20 +1,107 @@
 //! Convenience functions for arithmetics
 use crate::{
-    keys::{KeyPair, PublicKey},
+    keys::{DecryptError, KeyPair, PublicKey},
     BigInt, LIMBS,
 };
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Add, Deref, Mul, Neg},
+};
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
 use crypto_bigint::{
     modular::runtime_mod::{DynResidue, DynResidueParams},
-    rand_core::OsRng,
-    CheckedAdd, Random,
+    rand_core::CryptoRngCore,
+    subtle::{Choice, ConditionallySelectable, ConstantTimeEq, CtOption},
+    CheckedAdd, Encoding, Integer, NonZero, Random,
+};
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake256,
 };
-use std::ops::{Add, Deref, Mul, Neg};
+
+/// Errors that can occur when parsing a big integer or a modulus from text
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ParseError {
+    /// The hex string does not have an even number of digits
+    OddLength,
+
+    /// The hex string contains a character that is not a hex digit
+    InvalidDigit,
+
+    /// The value does not fit in LIMBS words
+    TooLong,
+
+    /// The value is not an odd number and cannot be used as a modulus
+    EvenModulus,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OddLength => write!(f, "hex string has an odd number of digits"),
+            Self::InvalidDigit => write!(f, "hex string contains a non-hex character"),
+            Self::TooLong => write!(f, "value does not fit in {LIMBS} words"),
+            Self::EvenModulus => write!(f, "modulus must be odd"),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Errors that can occur in arithmetic over the residue classes
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ArithmeticError {
+    /// The ring has too many residue classes to enumerate
+    RingTooLarge,
+
+    /// An operand is reduced under a different modulus than the key expects
+    ModulusMismatch,
+
+    /// The witness is not in the multiplicative group Z/n
+    NonInvertibleWitness,
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RingTooLarge => write!(
+                f,
+                "ring modulus has more than {MAX_ENUMERABLE_RING_BITS} bits"
+            ),
+            Self::ModulusMismatch => write!(f, "operand is reduced under the wrong modulus"),
+            Self::NonInvertibleWitness => write!(f, "witness is not invertible"),
+        }
+    }
+}
+
+impl core::error::Error for ArithmeticError {}
+
+/// The largest ring (in bits of r) whose residue classes try_classes will enumerate
+pub const MAX_ENUMERABLE_RING_BITS: usize = 32;
+
+/// The domain tag that the seed of a deterministic witness is prefixed with
+const WITNESS_DOMAIN: &[u8] = b"benaloh/witness/v1";
 
 /// A ring modulus defines the integer ring (mod r). Integer addition and multiplication are
 /// defined. Not all integers are invertible. Ring modulus is usually used as exponents,
 /// such as residue classes
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-pub struct RingModulus(DynResidueParams<LIMBS>);
+///
+/// The modulus is also kept as a NonZero, computed once at construction, for use as a divisor
+#[derive(Debug, Copy, Clone)]
+pub struct RingModulus(DynResidueParams<LIMBS>, NonZero<BigInt>);
+
+/// Two ring moduli are equal if their moduli are equal, whatever else is precomputed alongside
+impl PartialEq for RingModulus {
+    fn eq(&self, other: &Self) -> bool {
+        return self.modulus_ref() == other.modulus_ref();
+    }
+}
+
+impl Eq for RingModulus {}
 
 impl Deref for RingModulus {
     type Target = DynResidueParams<LIMBS>;
@@ -30,27 +117,132 @@ impl RingModulus {
         return self.0.clone();
     }
 
+    /// Borrow the inner dynamic residue parameter, which carries the precomputed Montgomery
+    /// constants alongside the modulus
+    pub fn params(&self) -> &DynResidueParams<LIMBS> {
+        return &self.0;
+    }
+
     /// Clone the inner big integer
     pub fn to_uint(&self) -> BigInt {
-        return self.0.modulus().clone();
+        return self.modulus_ref().clone();
+    }
+
+    /// Borrow the inner big integer, for callers that only read the modulus
+    pub fn modulus_ref(&self) -> &BigInt {
+        return self.0.modulus();
     }
 
     /// Sample a random element in the integer ring
+    #[cfg(feature = "std")]
     pub fn sample(&self) -> DynResidue<LIMBS> {
-        return DynResidue::new(&BigInt::random(&mut OsRng), self.to_dyn_residue_params());
+        return self.sample_with_rng(&mut OsRng);
+    }
+
+    /// Sample a random element in the integer ring using the supplied source of randomness
+    pub fn sample_with_rng(&self, rng: &mut impl CryptoRngCore) -> DynResidue<LIMBS> {
+        return DynResidue::new(&BigInt::random(rng), *self.params());
+    }
+
+    /// Sample a random non-zero element in the integer ring
+    #[cfg(feature = "std")]
+    pub fn sample_nonzero(&self) -> DynResidue<LIMBS> {
+        return self.sample_nonzero_with_rng(&mut OsRng);
+    }
+
+    /// Sample a random non-zero element in the integer ring by rejecting zero, using the supplied
+    /// source of randomness
+    pub fn sample_nonzero_with_rng(&self, rng: &mut impl CryptoRngCore) -> DynResidue<LIMBS> {
+        loop {
+            let sample = self.sample_with_rng(rng);
+            if sample.retrieve() != BigInt::ZERO {
+                return sample;
+            }
+        }
+    }
+
+    /// Return the modulus as a divisor. The modulus of a DynResidueParams is always odd, hence
+    /// never zero
+    pub fn as_nonzero(&self) -> NonZero<BigInt> {
+        return self.1;
     }
 
     pub fn new(modulus: DynResidueParams<LIMBS>) -> Self {
-        return Self(modulus);
+        let nonzero = NonZero::new(*modulus.modulus()).unwrap();
+        return Self(modulus, nonzero);
+    }
+
+    pub fn from_uint(modulus: &BigInt) -> Self {
+        return Self::new(DynResidueParams::new(modulus));
+    }
+
+    /// Parse the modulus from a big-endian hex string, optionally prefixed with "0x"
+    pub fn from_hex(hex: &str) -> Result<Self, ParseError> {
+        return Ok(Self::new(parse_modulus_hex(hex)?));
+    }
+
+    /// Iterate over every residue class 0, 1, ..., r - 1 in order, e.g. to build a decryption
+    /// lookup table. There are r classes, so this is only practical for small rings: a 32-bit
+    /// r already yields billions of classes. See try_classes for a guarded version.
+    pub fn classes(&self) -> impl Iterator<Item = ResidueClass> {
+        let params = *self.params();
+        let r = self.to_uint();
+        let mut next = BigInt::ZERO;
+        return core::iter::from_fn(move || {
+            if next >= r {
+                return None;
+            }
+            let class = ResidueClass::new(DynResidue::new(&next, params));
+            next = next.wrapping_add(&BigInt::ONE);
+            return Some(class);
+        });
+    }
+
+    /// Iterate over every residue class as classes does, unless r is longer than
+    /// MAX_ENUMERABLE_RING_BITS
+    pub fn try_classes(&self) -> Result<impl Iterator<Item = ResidueClass>, ArithmeticError> {
+        if self.modulus_ref().bits() > MAX_ENUMERABLE_RING_BITS {
+            return Err(ArithmeticError::RingTooLarge);
+        }
+        return Ok(self.classes());
+    }
+}
+
+/// Reduce a big-endian integer of any length under the modulus. The bytes are read in chunks
+/// of LIMBS * 8 bytes, most significant first, and folded in by Horner's rule:
+/// acc = acc * (2 ** (LIMBS * 64)) + chunk (mod m), with the leading chunk left-padded.
+fn residue_from_be_bytes(bytes: &[u8], params: DynResidueParams<LIMBS>) -> DynResidue<LIMBS> {
+    // 2 ** (LIMBS * 64) does not fit in a BigInt, but its square root does
+    let shift = DynResidue::new(&BigInt::ONE.shl_vartime(BigInt::BITS / 2), params).square();
+    let lead = match bytes.len() % BigInt::BYTES {
+        0 => BigInt::BYTES.min(bytes.len()),
+        lead => lead,
+    };
+    let (head, tail) = bytes.split_at(lead);
+    let mut padded = [0u8; BigInt::BYTES];
+    padded[BigInt::BYTES - head.len()..].copy_from_slice(head);
+    let mut acc = DynResidue::new(&BigInt::from_be_slice(&padded), params);
+    for chunk in tail.chunks_exact(BigInt::BYTES) {
+        acc = acc * shift + DynResidue::new(&BigInt::from_be_slice(chunk), params);
     }
+    return acc;
 }
 
 /// A group modulus defines the multiplicative group Z/n of invertible elements.
 /// With group modulus, multiplication is the only defined operation. All elements are invertible
 /// so we can sample from them
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct GroupModulus(DynResidueParams<LIMBS>);
 
+/// Two group moduli are equal if their moduli are equal, whatever else is precomputed alongside
+impl PartialEq for GroupModulus {
+    fn eq(&self, other: &Self) -> bool {
+        return self.modulus_ref() == other.modulus_ref();
+    }
+}
+
+impl Eq for GroupModulus {}
+
 impl Deref for GroupModulus {
     type Target = DynResidueParams<LIMBS>;
 
@@ -65,20 +257,49 @@ impl GroupModulus {
         return self.0.clone();
     }
 
+    /// Borrow the inner dynamic residue parameter, which carries the precomputed Montgomery
+    /// constants alongside the modulus
+    pub fn params(&self) -> &DynResidueParams<LIMBS> {
+        return &self.0;
+    }
+
     /// Clone the inner big integer
     pub fn to_uint(&self) -> BigInt {
-        return self.0.modulus().clone();
+        return self.modulus_ref().clone();
+    }
+
+    /// Borrow the inner big integer, for callers that only read the modulus
+    pub fn modulus_ref(&self) -> &BigInt {
+        return self.0.modulus();
     }
 
-    /// Sample a random invertible element
+    /// Sample a random invertible element.
+    ///
+    /// Panics if MAX_SAMPLE_TRIES draws in a row are not invertible, which does not happen under
+    /// a valid modulus but keeps a crafted one from stalling the caller. Use sample_bounded to
+    /// handle a modulus from an untrusted source without panicking.
+    #[cfg(feature = "std")]
     pub fn sample(&self) -> DynResidue<LIMBS> {
-        loop {
-            let val = DynResidue::new(&BigInt::random(&mut OsRng), self.to_dyn_residue_params());
-            let (_, invertible) = val.invert();
-            if invertible.into() {
-                return val;
-            }
+        return self
+            .sample_bounded(MAX_SAMPLE_TRIES)
+            .expect("no invertible element found");
+    }
+
+    /// Sample a random invertible element, or return None if none of the first max_tries draws
+    /// is invertible
+    #[cfg(feature = "std")]
+    pub fn sample_bounded(&self, max_tries: usize) -> Option<DynResidue<LIMBS>> {
+        return sample_invertible_bounded(*self.params(), max_tries);
+    }
+
+    /// Check that the value is an element of this group: it must be reduced under this modulus
+    /// and be invertible
+    pub fn contains(&self, val: &DynResidue<LIMBS>) -> bool {
+        if val.params() != &self.0 {
+            return false;
         }
+        let (_, invertible) = val.invert();
+        return invertible.into();
     }
 
     pub fn new(modulus: DynResidueParams<LIMBS>) -> Self {
@@ -88,6 +309,11 @@ impl GroupModulus {
     pub fn from_uint(modulus: &BigInt) -> Self {
         return Self(DynResidueParams::new(modulus));
     }
+
+    /// Parse the modulus from a big-endian hex string, optionally prefixed with "0x"
+    pub fn from_hex(hex: &str) -> Result<Self, ParseError> {
+        return Ok(Self::new(parse_modulus_hex(hex)?));
+    }
 }
 
 /// A residue class is an element of the integer ring Z/r
@@ -102,6 +328,13 @@ impl PartialEq for ResidueClass {
 
 impl Eq for ResidueClass {}
 
+/// Hash the canonical value instead of the Montgomery form so that equal classes hash equally
+impl Hash for ResidueClass {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.retrieve().hash(state);
+    }
+}
+
 impl Deref for ResidueClass {
     type Target = DynResidue<LIMBS>;
 
@@ -127,16 +360,23 @@ impl ResidueClass {
         return Self::new(DynResidue::new(&BigInt::ZERO, params));
     }
 
+    /// The class of a small integer, reduced mod r
+    pub fn from_u64(v: u64, modulus: &RingModulus) -> Self {
+        return Self::new(DynResidue::new(&BigInt::from_u64(v), *modulus.params()));
+    }
+
     pub fn one(params: DynResidueParams<LIMBS>) -> Self {
         return Self::new(DynResidue::new(&BigInt::ONE, params));
     }
 
+    /// Read a big-endian integer of any length, e.g. a hash digest, and reduce it (mod r)
     pub fn from_be_bytes(bytes: &[u8], modulus: &RingModulus) -> Self {
-        let mut padded = vec![0; BigInt::BYTES - bytes.len()];
-        padded.extend_from_slice(bytes);
-        let val = BigInt::from_be_slice(&padded);
-        let residue = DynResidue::new(&val, modulus.to_dyn_residue_params());
-        return Self::new(residue);
+        return Self::new(residue_from_be_bytes(bytes, *modulus.params()));
+    }
+
+    /// The canonical value of the residue class in big-endian, always LIMBS * 8 bytes wide
+    pub fn to_be_bytes(&self) -> [u8; LIMBS * 8] {
+        return self.0.retrieve().to_be_bytes();
     }
 
     pub fn get_residue(&self) -> &DynResidue<LIMBS> {
@@ -146,6 +386,47 @@ impl ResidueClass {
     pub fn clone_residue(&self) -> DynResidue<LIMBS> {
         return self.0.clone();
     }
+
+    /// Re-interpret the canonical value of the class under a ring at least as large as this
+    /// one, e.g. to add up the tallies of sub-elections that ran over different rings. This
+    /// only makes sense if the value has not wrapped around (mod r), i.e. the true sum is
+    /// smaller than both moduli.
+    ///
+    /// Returns None if the new modulus is smaller than the current one
+    pub fn lift_to(&self, new_modulus: &RingModulus) -> Option<ResidueClass> {
+        if new_modulus.modulus_ref() < self.0.params().modulus() {
+            return None;
+        }
+        return Some(Self::new(DynResidue::new(
+            &self.0.retrieve(),
+            *new_modulus.params(),
+        )));
+    }
+
+    /// Return the residue class as a machine integer if it fits in 64 bits
+    pub fn as_u64(&self) -> Option<u64> {
+        let val = self.0.retrieve();
+        if val.bits() > u64::BITS as usize {
+            return None;
+        }
+        let bytes = val.to_be_bytes();
+        let low_bytes: [u8; 8] = bytes[BigInt::BYTES - 8..].try_into().unwrap();
+        return Some(u64::from_be_bytes(low_bytes));
+    }
+}
+
+/// The conversion trusts the caller to supply a residue under the ring modulus r; nothing in the
+/// residue itself records whether its modulus is r or n
+impl From<DynResidue<LIMBS>> for ResidueClass {
+    fn from(class: DynResidue<LIMBS>) -> Self {
+        return Self::new(class);
+    }
+}
+
+impl From<ResidueClass> for DynResidue<LIMBS> {
+    fn from(class: ResidueClass) -> Self {
+        return class.0;
+    }
 }
 
 impl Mul<ResidueClass> for ResidueClass {
@@ -165,6 +446,55 @@ impl Add<ResidueClass> for ResidueClass {
     }
 }
 
+/// Select between two residue classes without branching on the choice
+impl ConditionallySelectable for ResidueClass {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        return Self(DynResidue::conditional_select(&a.0, &b.0, choice));
+    }
+}
+
+/// A residue class of a ring whose modulus r fits in 16 bits, stored as a u16 instead of a
+/// LIMBS-word integer. Serialized ballot proofs over such a ring write their classes this way.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub struct SmallResidueClass(u16);
+
+impl SmallResidueClass {
+    /// The width of the serialized form
+    pub const BYTES: usize = 2;
+
+    /// Whether the residue classes of the ring modulus r fit in a SmallResidueClass
+    pub fn fits(r: &BigInt) -> bool {
+        return r.bits() <= u16::BITS as usize;
+    }
+
+    /// Compact the residue class, or return None if its ring modulus does not fit in 16 bits
+    pub fn from_class(rc: &ResidueClass) -> Option<Self> {
+        if !Self::fits(rc.params().modulus()) {
+            return None;
+        }
+        let val = rc.as_u64().expect("the class is less than r");
+        return Some(Self(val as u16));
+    }
+
+    /// Expand into a residue class of the ring. The value is reduced (mod r) if it is not
+    /// already less than r.
+    pub fn to_class(&self, r: &RingModulus) -> ResidueClass {
+        return ResidueClass::from_u64(self.0 as u64, r);
+    }
+
+    pub fn get(&self) -> u16 {
+        return self.0;
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; Self::BYTES] {
+        return self.0.to_be_bytes();
+    }
+
+    pub fn from_be_bytes(bytes: [u8; Self::BYTES]) -> Self {
+        return Self(u16::from_be_bytes(bytes));
+    }
+}
+
 /// An opaque residue is an element of the multiplicative group Z/n with no further information
 /// such as the decomposition. Ciphertexts are opaque residues
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -178,6 +508,28 @@ impl Deref for OpaqueResidue {
     }
 }
 
+/// Hash the canonical value instead of the Montgomery form so that equal residues hash equally
+impl Hash for OpaqueResidue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.retrieve().hash(state);
+    }
+}
+
+/// The conversion trusts the caller to supply a residue under the group modulus n. A residue
+/// under any other modulus (such as a residue class under r) converts without complaint and
+/// silently produces garbage when combined with genuine ciphertexts.
+impl From<DynResidue<LIMBS>> for OpaqueResidue {
+    fn from(residue: DynResidue<LIMBS>) -> Self {
+        return Self::new(residue);
+    }
+}
+
+impl From<OpaqueResidue> for DynResidue<LIMBS> {
+    fn from(residue: OpaqueResidue) -> Self {
+        return residue.0;
+    }
+}
+
 impl Mul<OpaqueResidue> for OpaqueResidue {
     type Output = OpaqueResidue;
 
@@ -186,11 +538,44 @@ impl Mul<OpaqueResidue> for OpaqueResidue {
     }
 }
 
+/// Select between two ciphertexts without branching on the choice, e.g. for an oblivious lookup
+impl ConditionallySelectable for OpaqueResidue {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        return Self(DynResidue::conditional_select(&a.0, &b.0, choice));
+    }
+}
+
 impl OpaqueResidue {
     pub fn new(residue: DynResidue<LIMBS>) -> Self {
         return Self(residue);
     }
 
+    /// The multiplicative identity of Z/n, e.g. the starting point of a product of ballots
+    pub fn one(params: DynResidueParams<LIMBS>) -> Self {
+        return Self::new(DynResidue::one(params));
+    }
+
+    /// The residue of a small integer, reduced mod n
+    pub fn from_u64(v: u64, modulus: &GroupModulus) -> Self {
+        return Self::new(DynResidue::new(&BigInt::from_u64(v), *modulus.params()));
+    }
+
+    /// Read a big-endian integer of any length and reduce it (mod n)
+    pub fn from_be_bytes(bytes: &[u8], modulus: &GroupModulus) -> Self {
+        return Self::new(residue_from_be_bytes(bytes, *modulus.params()));
+    }
+
+    /// The canonical value of the residue in big-endian, always LIMBS * 8 bytes wide. This is
+    /// the encoding under which residues are hashed into proof challenges
+    pub fn to_be_bytes(&self) -> [u8; LIMBS * 8] {
+        return self.0.retrieve().to_be_bytes();
+    }
+
+    /// Return true iff the residue is the multiplicative identity
+    pub fn is_one(&self) -> bool {
+        return self.0.retrieve() == BigInt::ONE;
+    }
+
     pub fn get_residue(&self) -> &DynResidue<LIMBS> {
         return &self.0;
     }
@@ -203,6 +588,19 @@ impl OpaqueResidue {
         return Self::new(self.0.pow(&exponent.retrieve()));
     }
 
+    /// Multiply the ciphertext by (factor ** r). The product is a fresh-looking ciphertext in
+    /// the same residue class, and anyone who knows the factor can link the two.
+    pub fn rerandomize(&self, factor: &OpaqueResidue, pk: &PublicKey) -> Self {
+        let rth_power = factor.get_residue().pow(pk.get_r().modulus());
+        return *self * Self::new(rth_power);
+    }
+
+    /// Return true iff the residue is invertible, i.e. it is relatively prime to the modulus
+    pub fn is_invertible(&self) -> bool {
+        let (_, invertible) = self.0.invert();
+        return invertible.into();
+    }
+
     /// A wrapper around DynResidue::invert. Will exhibit undefined behavior if not invertible
     pub fn invert(&self) -> Self {
         let (inverse, _) = self.0.invert();
@@ -210,6 +608,58 @@ impl OpaqueResidue {
     }
 }
 
+/// The witness x in the decomposition w = (y ** c) * (x ** r) of a clear residue. A witness is
+/// an element of Z/n just like a ciphertext, but it is secret, and using one where the other is
+/// expected is always a bug, so the two are kept apart by type.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Witness(OpaqueResidue);
+
+impl Mul<Witness> for Witness {
+    type Output = Witness;
+
+    fn mul(self, rhs: Witness) -> Self::Output {
+        return Self(self.0 * rhs.0);
+    }
+}
+
+impl Witness {
+    pub fn new(residue: DynResidue<LIMBS>) -> Self {
+        return Self(OpaqueResidue::new(residue));
+    }
+
+    pub fn get_residue(&self) -> &DynResidue<LIMBS> {
+        return self.0.get_residue();
+    }
+
+    pub fn clone_residue(&self) -> DynResidue<LIMBS> {
+        return self.0.clone_residue();
+    }
+
+    pub fn pow(&self, exponent: &ResidueClass) -> Self {
+        return Self(self.0.pow(exponent));
+    }
+
+    /// Return true iff the witness is invertible, which every witness of a valid ciphertext is
+    pub fn is_invertible(&self) -> bool {
+        return self.0.is_invertible();
+    }
+
+    /// A wrapper around DynResidue::invert. Will exhibit undefined behavior if not invertible
+    pub fn invert(&self) -> Self {
+        return Self(self.0.invert());
+    }
+
+    /// The r-th residue x ** r that the witness contributes to the value
+    pub fn rth_power(&self, r: &RingModulus) -> OpaqueResidue {
+        return OpaqueResidue::new(self.get_residue().pow(r.modulus()));
+    }
+
+    /// Check that the witness is an r-th root of the residue
+    pub fn is_rth_root_of(&self, residue: &OpaqueResidue, r: &RingModulus) -> bool {
+        return self.rth_power(r) == *residue;
+    }
+}
+
 /// A clear residue contains the value and its decomposition into the residue class and witness
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct ClearResidue {
@@ -221,7 +671,7 @@ pub struct ClearResidue {
 
     /// The r-th root of val * (y ** -rc); the "x" in w = (y ** c) * (x ** r).
     /// An invertible integer under (mod n)
-    witness: OpaqueResidue,
+    witness: Witness,
 
     /// A copy of the ambient primes (r, n, y)
     /// TODO: convert this into a reference to reduce copying
@@ -244,7 +694,7 @@ impl ClearResidue {
     pub fn new(
         val: OpaqueResidue,
         rc: ResidueClass,
-        witness: OpaqueResidue,
+        witness: Witness,
         ambience: &PublicKey,
     ) -> Self {
         let ambience = ambience.clone();
@@ -260,29 +710,75 @@ impl ClearResidue {
         return self.get_rc().retrieve() == BigInt::ZERO;
     }
 
+    /// Check that the decomposition matches the value, i.e. val = (y ** rc) * (witness ** r)
+    pub fn is_consistent(&self) -> bool {
+        let composed = Self::compose(
+            self.clone_rc().clone_residue(),
+            self.clone_witness().clone_residue(),
+            self.get_ambience(),
+        );
+        return composed.get_val() == self.get_val();
+    }
+
+    /// Check the decomposition that a voter reveals when spoiling a ballot: the ballot is a valid
+    /// ciphertext under the given public key and is (y ** rc) * (witness ** r), so anyone can
+    /// confirm that the spoiled ballot encrypts the claimed vote rc
+    pub fn verify_spoiled(&self, pk: &PublicKey) -> bool {
+        return self.get_ambience() == pk
+            && pk.is_valid_ciphertext(self.get_val())
+            && self.is_consistent();
+    }
+
+    /// Return an equivalent residue whose residue class is re-reduced (mod r) and whose witness
+    /// is re-derived to be consistent with the value and the class.
+    ///
+    /// Adding residue classes in the ring Z/r drops the carry: if the classes sum to rc + kr,
+    /// then the witness is missing a factor of y ** k. The carry k is recovered by solving
+    /// (y ** r) ** k = val * (y ** -rc) * (witness ** -r) with discrete_log_ct, so the time it
+    /// takes does not reveal the carry. Returns None if the value is not (y ** rc) * (witness ** r)
+    /// for any carry.
+    pub fn canonicalize(&self) -> Option<Self> {
+        let ambience = self.get_ambience();
+        let r = ambience.get_r();
+        let rc = ResidueClass::new(DynResidue::new(&self.get_rc().retrieve(), *r.params()));
+        let unexplained = self.clone_val()
+            * ambience.invert_y().pow(&rc)
+            * self.get_witness().invert().rth_power(r);
+        let carry = discrete_log_ct(
+            &ambience.get_y().pow(r.modulus()).retrieve(),
+            &unexplained.retrieve(),
+            r.modulus(),
+            ambience.get_n().modulus(),
+        )?;
+        let carry = ResidueClass::new(DynResidue::new(&carry, *r.params()));
+        let witness = self.clone_witness() * Witness::new(*ambience.get_y()).pow(&carry);
+
+        let canonical = Self::new(self.clone_val(), rc, witness, ambience);
+        if !canonical.is_consistent() {
+            return None;
+        }
+        return Some(canonical);
+    }
+
     /// Decompose an opaque value into its residual representation (c, x)
     /// Such decomposition is equivalent to decrypting a ciphertext, hence the requirement for a
     /// complete keypair instead of just the public key
     ///
     /// The decomposition takes advantage of the fact that raising a r-th residue to the power of
     /// (phi/n) gives 1 (mod n) by Euler's theorem. From here, we can use a brute-force discrete
-    /// log algorithm to find the value of the residue class. Finally, onec the residue class is
-    /// found, we can recover the witness.
+    /// log algorithm to find the value of the residue class; this is discrete_log_ct, as in
+    /// KeyPair::try_decrypt, so the time it takes does not depend on the class. Finally, onec the
+    /// residue class is found, we can recover the witness.
+    ///
+    /// Panics if the value is malformed; see try_decompose
     pub fn decompose(val: DynResidue<LIMBS>, keypair: &KeyPair) -> Self {
-        let phi_over_r = keypair.phi_over_r();
-        let y_to_phi_over_r = keypair.get_pk().get_y().pow(&phi_over_r).retrieve();
-        let val_to_phi_over_r = val.pow(&phi_over_r).retrieve();
-        let rc = discrete_log(
-            &y_to_phi_over_r,
-            &val_to_phi_over_r,
-            keypair.get_pk().get_r().modulus(),
-            keypair.get_pk().get_n().modulus(),
-        )
-        .unwrap();
-        let rc = ResidueClass::new(DynResidue::new(
-            &rc,
-            keypair.get_pk().get_r().to_dyn_residue_params(),
-        ));
+        return Self::try_decompose(val, keypair).expect("failed to decompose");
+    }
+
+    /// Decompose an opaque value as decompose does, or return an error if the value is not an
+    /// element of Z/n or is not (y ** rc) * (witness ** r) for any rc and witness
+    pub fn try_decompose(val: DynResidue<LIMBS>, keypair: &KeyPair) -> Result<Self, DecryptError> {
+        let rc = keypair.try_decrypt(&OpaqueResidue::new(val))?;
         let witness = keypair.get_pk().invert_y().pow(&rc);
         let witness = OpaqueResidue::new(val).mul(witness);
         let witness = rth_root(
@@ -290,10 +786,90 @@ impl ClearResidue {
             keypair.get_pk().get_r().modulus(),
             keypair.get_sk().get_phi(),
         )
-        .unwrap();
-        let witness = OpaqueResidue::new(witness);
+        .ok_or(DecryptError::MalformedCiphertext)?;
+        let witness = Witness::new(witness);
+
+        let decomposition = Self::new(OpaqueResidue::new(val), rc, witness, keypair.get_pk());
+        debug_assert!(
+            decomposition.is_consistent(),
+            "the decomposition does not recompose to the value"
+        );
+        return Ok(decomposition);
+    }
+
+    /// Decompose an opaque value as try_decompose does, then recompose (y ** rc) * (witness ** r)
+    /// and return an error if it is not the value. This catches a wrong answer from the discrete
+    /// log or the r-th root in release builds too, at the cost of one more exponentiation.
+    pub fn decompose_checked(
+        val: DynResidue<LIMBS>,
+        keypair: &KeyPair,
+    ) -> Result<Self, DecryptError> {
+        return Self::try_decompose(val, keypair)?.into_consistent();
+    }
+
+    /// Pass the decomposition through if it is consistent
+    fn into_consistent(self) -> Result<Self, DecryptError> {
+        if !self.is_consistent() {
+            return Err(DecryptError::InconsistentDecomposition);
+        }
+        return Ok(self);
+    }
+
+    /// Decompose an opaque value the same way as decompose, but perform the exponentiations
+    /// (mod p) and (mod q) and recombine them with the Chinese remainder theorem. This requires
+    /// the key pair to retain the prime factors of n; without them, this falls back to
+    /// decompose. The result is always identical to that of decompose.
+    ///
+    /// Panics if the value is malformed; see try_decompose_crt
+    pub fn decompose_crt(val: DynResidue<LIMBS>, keypair: &KeyPair) -> Self {
+        return Self::try_decompose_crt(val, keypair).expect("failed to decompose");
+    }
+
+    /// Decompose an opaque value as decompose_crt does, or return an error under the same
+    /// conditions as try_decompose. The class is found with discrete_log_ct, as in decompose.
+    pub fn try_decompose_crt(
+        val: DynResidue<LIMBS>,
+        keypair: &KeyPair,
+    ) -> Result<Self, DecryptError> {
+        let (p, q) = match keypair.get_factors() {
+            Some(factors) => factors,
+            None => return Self::try_decompose(val, keypair),
+        };
+        if !keypair
+            .get_pk()
+            .is_valid_ciphertext(&OpaqueResidue::new(val))
+        {
+            return Err(DecryptError::MalformedCiphertext);
+        }
+        let r = keypair.get_pk().get_r().modulus();
+        let phi_over_r = keypair.phi_over_r();
+        let y_to_phi_over_r = pow_crt(keypair.get_pk().get_y(), &phi_over_r, p, q).retrieve();
+        let val_to_phi_over_r = pow_crt(&val, &phi_over_r, p, q).retrieve();
+        let rc = discrete_log_ct(
+            &y_to_phi_over_r,
+            &val_to_phi_over_r,
+            r,
+            keypair.get_pk().get_n().modulus(),
+        )
+        .ok_or(DecryptError::MalformedCiphertext)?;
+        let rc = ResidueClass::new(DynResidue::new(&rc, *keypair.get_pk().get_r().params()));
+
+        // The witness is the r-th root of val * (y ** -rc), using the same root exponent as
+        // rth_root
+        let z = OpaqueResidue::new(val).mul(keypair.get_pk().invert_y().pow(&rc));
+        let (root_exp, _) = r.inv_mod(&phi_over_r);
+        let witness = pow_crt(&z, &root_exp, p, q);
+        if witness.pow_bounded_exp(r, r.bits()) != *z {
+            return Err(DecryptError::MalformedCiphertext);
+        }
+        let witness = Witness::new(witness);
 
-        return Self::new(OpaqueResidue::new(val), rc, witness, keypair.get_pk());
+        return Ok(Self::new(
+            OpaqueResidue::new(val),
+            rc,
+            witness,
+            keypair.get_pk(),
+        ));
     }
 
     /// Construct a higher residue from its decomposition
@@ -302,14 +878,97 @@ impl ClearResidue {
         witness: DynResidue<LIMBS>,
         ambience: &PublicKey,
     ) -> Self {
-        let z = witness // z is (x ** r)
-            .pow(ambience.get_r().modulus());
-        let val = OpaqueResidue::new(ambience.get_y().pow(&rc.retrieve()).mul(&z));
+        let y_to_rc = OpaqueResidue::new(ambience.get_y().pow(&rc.retrieve()));
+        return Self::compose_with(y_to_rc, rc, witness, ambience);
+    }
+
+    /// Construct a higher residue from its decomposition as compose does, but check the inputs
+    /// first: the class must be reduced under r, and the witness must be an element of the
+    /// multiplicative group Z/n
+    pub fn compose_typed(
+        rc: ResidueClass,
+        witness: Witness,
+        ambience: &PublicKey,
+    ) -> Result<Self, ArithmeticError> {
+        if rc.params() != ambience.get_r().params() {
+            return Err(ArithmeticError::ModulusMismatch);
+        }
+        if witness.get_residue().params() != ambience.get_n().params() {
+            return Err(ArithmeticError::ModulusMismatch);
+        }
+        if !witness.is_invertible() {
+            return Err(ArithmeticError::NonInvertibleWitness);
+        }
+        return Ok(Self::compose(
+            rc.clone_residue(),
+            witness.clone_residue(),
+            ambience,
+        ));
+    }
+
+    /// Construct a higher residue from its decomposition as compose does, taking y ** rc from
+    /// the precomputed powers if rc is one of their classes
+    #[cfg(feature = "alloc")]
+    pub fn compose_with_powers(
+        rc: DynResidue<LIMBS>,
+        witness: DynResidue<LIMBS>,
+        powers: &ClassPowers,
+    ) -> Self {
+        let ambience = powers.get_pk();
+        let y_to_rc = match powers.get(&ResidueClass::new(rc)) {
+            Some(y_to_rc) => *y_to_rc,
+            None => OpaqueResidue::new(ambience.get_y().pow(&rc.retrieve())),
+        };
+        return Self::compose_with(y_to_rc, rc, witness, ambience);
+    }
+
+    fn compose_with(
+        y_to_rc: OpaqueResidue,
+        rc: DynResidue<LIMBS>,
+        witness: DynResidue<LIMBS>,
+        ambience: &PublicKey,
+    ) -> Self {
+        let witness = Witness::new(witness);
+        let z = witness.rth_power(ambience.get_r()); // z is (x ** r)
+        let val = y_to_rc * z;
         let rc = ResidueClass::new(rc);
-        let witness = OpaqueResidue::new(witness);
         return Self::new(val, rc, witness, ambience);
     }
 
+    /// Encrypt a residue class using the supplied witness x instead of a freshly sampled one,
+    /// which makes the ciphertext reproducible (e.g. for test vectors). This is compose, except
+    /// that a witness outside of the multiplicative group is rejected: such a witness would
+    /// produce a ciphertext that cannot be decrypted.
+    pub fn encrypt_with_witness(
+        class: DynResidue<LIMBS>,
+        witness: DynResidue<LIMBS>,
+        pk: &PublicKey,
+    ) -> Option<Self> {
+        if !Witness::new(witness).is_invertible() {
+            return None;
+        }
+        return Some(Self::compose(class, witness, pk));
+    }
+
+    /// Encrypt a residue class with a witness derived deterministically from the seed, so that
+    /// revealing the seed lets anyone recompute the witness and check the ciphertext. The seed
+    /// is expanded with SHAKE256 into a stream of LIMBS-word integers, and the first one that is
+    /// invertible (mod n) is the witness.
+    pub fn encrypt_deterministic(class: DynResidue<LIMBS>, seed: &[u8], pk: &PublicKey) -> Self {
+        let mut shake = Shake256::default();
+        shake.update(WITNESS_DOMAIN);
+        shake.update(seed);
+        let mut reader = shake.finalize_xof();
+        let mut bytes = [0u8; BigInt::BYTES];
+        loop {
+            reader.read(&mut bytes);
+            let witness = DynResidue::new(&BigInt::from_be_slice(&bytes), *pk.get_n().params());
+            if let Some(encryption) = Self::encrypt_with_witness(class, witness, pk) {
+                return encryption;
+            }
+        }
+    }
+
     /// Raise a residue to the power of the residue class
     pub fn pow(&self, exponent: &ResidueClass) -> Self {
         let val = self.get_val().pow(exponent);
@@ -319,6 +978,42 @@ impl ClearResidue {
         return Self::new(val, rc, witness, self.get_ambience());
     }
 
+    /// Square the residue; see pow_u64
+    pub fn square(&self) -> Self {
+        return self.pow_u64(2);
+    }
+
+    /// Raise a residue to a small power. The class c * e is reduced (mod r), and the carry
+    /// floor(c * e / r) is moved into the witness as y ** carry, so unlike pow the result is
+    /// consistent without canonicalizing it.
+    pub fn pow_u64(&self, e: u64) -> Self {
+        let ambience = self.get_ambience();
+        let r = ambience.get_r();
+        let exponent = BigInt::from_u64(e);
+        let product = self
+            .get_rc()
+            .retrieve()
+            .resize::<{ LIMBS + 1 }>()
+            .wrapping_mul(&exponent.resize::<{ LIMBS + 1 }>());
+        let modulus =
+            NonZero::new(r.modulus().resize::<{ LIMBS + 1 }>()).expect("r is a non-zero modulus");
+        let (carry, class) = product.div_rem(&modulus);
+
+        let val = self
+            .get_val()
+            .get_residue()
+            .pow_bounded_exp(&exponent, u64::BITS as usize);
+        let witness = self
+            .get_witness()
+            .get_residue()
+            .pow_bounded_exp(&exponent, u64::BITS as usize)
+            * ambience
+                .get_y()
+                .pow_bounded_exp(&carry.resize::<LIMBS>(), u64::BITS as usize);
+        let rc = ResidueClass::new(DynResidue::new(&class.resize(), *r.params()));
+        return Self::new(OpaqueResidue::new(val), rc, Witness::new(witness), ambience);
+    }
+
     /// Return a reference to the element itself
     pub fn get_val(&self) -> &OpaqueResidue {
         return &self.val;
@@ -329,6 +1024,17 @@ impl ClearResidue {
         return self.val.clone();
     }
 
+    /// Forget the decomposition and keep only the ciphertext, which is what gets published when
+    /// the residue class and the witness must stay secret
+    pub fn into_opaque(self) -> OpaqueResidue {
+        return self.val;
+    }
+
+    /// View the ciphertext without its decomposition
+    pub fn as_opaque(&self) -> &OpaqueResidue {
+        return &self.val;
+    }
+
     /// Return a reference to the residue class
     pub fn get_rc(&self) -> &ResidueClass {
         return &self.rc;
@@ -339,13 +1045,19 @@ impl ClearResidue {
         return self.rc.clone();
     }
 
+    /// Return the residue class as a machine integer if it fits in 64 bits, which is usually
+    /// the case when the residue class is a vote or a candidate index
+    pub fn class_as_u64(&self) -> Option<u64> {
+        return self.rc.as_u64();
+    }
+
     /// Return a reference to the witness
-    pub fn get_witness(&self) -> &OpaqueResidue {
+    pub fn get_witness(&self) -> &Witness {
         return &self.witness;
     }
 
     /// Clone the witness
-    pub fn clone_witness(&self) -> OpaqueResidue {
+    pub fn clone_witness(&self) -> Witness {
         return self.witness.clone();
     }
 
@@ -355,14 +1067,95 @@ impl ClearResidue {
     }
 
     /// Generate a random member of Z_n, including its decomposition
+    #[cfg(feature = "std")]
     pub fn random(class: Option<DynResidue<LIMBS>>, ambience: &PublicKey) -> Self {
+        return Self::random_with_rng(class, ambience, &mut OsRng);
+    }
+
+    /// Generate a random member of Z_n using the supplied source of randomness
+    pub fn random_with_rng(
+        class: Option<DynResidue<LIMBS>>,
+        ambience: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
         let c = match class {
             Some(class) => class,
-            None => ambience.get_r().sample(),
+            None => ambience.get_r().sample_with_rng(rng),
         };
-        let x = ambience.sample_invertible();
+        let x = ambience.sample_invertible_with_rng(rng);
         return Self::compose(c, x, ambience);
     }
+
+    /// Generate a random member of Z_n, or return None if none of the first MAX_SAMPLE_TRIES
+    /// draws of the witness is invertible, e.g. under a public key from an untrusted source
+    #[cfg(feature = "std")]
+    pub fn try_random(class: Option<DynResidue<LIMBS>>, ambience: &PublicKey) -> Option<Self> {
+        return Self::try_random_with_rng(class, ambience, &mut OsRng);
+    }
+
+    /// Generate a random member of Z_n as try_random does, using the supplied source of
+    /// randomness. The same rng gives the same residue as random_with_rng whenever this
+    /// succeeds.
+    pub fn try_random_with_rng(
+        class: Option<DynResidue<LIMBS>>,
+        ambience: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Option<Self> {
+        let c = match class {
+            Some(class) => class,
+            None => ambience.get_r().sample_with_rng(rng),
+        };
+        let x = ambience.sample_invertible_bounded_with_rng(MAX_SAMPLE_TRIES, rng)?;
+        return Some(Self::compose(c, x, ambience));
+    }
+
+    /// Generate a random member of the given residue class as random_with_rng does, with y ** c
+    /// taken from the precomputed powers. The same rng gives the same residue either way.
+    #[cfg(feature = "alloc")]
+    pub fn random_with_powers(
+        class: DynResidue<LIMBS>,
+        powers: &ClassPowers,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let x = powers.get_pk().sample_invertible_with_rng(rng);
+        return Self::compose_with_powers(class, x, powers);
+    }
+}
+
+/// The powers y ** c for a small set of residue classes c, such as the classes of a ballot
+/// proof. Proof generation encrypts thousands of residues in the same few classes, so computing
+/// each y ** c once saves an exponentiation for every one of them.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct ClassPowers {
+    pk: PublicKey,
+    powers: Vec<(ResidueClass, OpaqueResidue)>,
+}
+
+#[cfg(feature = "alloc")]
+impl ClassPowers {
+    pub fn new(classes: &[ResidueClass], pk: &PublicKey) -> Self {
+        let mut powers: Vec<(ResidueClass, OpaqueResidue)> = Vec::with_capacity(classes.len());
+        for class in classes {
+            if powers.iter().all(|(cached, _)| cached != class) {
+                powers.push((*class, OpaqueResidue::new(*pk.get_y()).pow(class)));
+            }
+        }
+        return Self { pk: *pk, powers };
+    }
+
+    pub fn get_pk(&self) -> &PublicKey {
+        return &self.pk;
+    }
+
+    /// Return y ** class if the class is one of the precomputed classes
+    pub fn get(&self, class: &ResidueClass) -> Option<&OpaqueResidue> {
+        return self
+            .powers
+            .iter()
+            .find(|(cached, _)| cached == class)
+            .map(|(_, power)| power);
+    }
 }
 
 /// Find the r-th root of z under (mod n). If the root exists, return a root, else return None
@@ -371,9 +1164,10 @@ impl ClearResidue {
 /// Ar + B(phi/r) = 1
 ///
 /// Note that this relationship only holds if the PublicKey is perfectly consonant.
-/// Also note that this can also be used to check that something is an r-th residue
+/// Also note that this can also be used to check that something is an r-th residue, though
+/// KeyPair::is_rth_residue does so without computing the root
 pub fn rth_root(z: DynResidue<LIMBS>, r: &BigInt, phi: &BigInt) -> Option<DynResidue<LIMBS>> {
-    let phi_over_r = phi.checked_div(&r).unwrap();
+    let phi_over_r = phi.checked_div(r).unwrap();
     let (root_exp, r_invertible) = r.inv_mod(&phi_over_r);
     let r_invertible: bool = r_invertible.into();
     if !r_invertible {
@@ -386,10 +1180,81 @@ pub fn rth_root(z: DynResidue<LIMBS>, r: &BigInt, phi: &BigInt) -> Option<DynRes
     return None;
 }
 
+/// Raise an invertible element of Z/n to some power by exponentiating (mod p) and (mod q)
+/// separately, then recombining the two results with the Chinese remainder theorem. Because the
+/// base is invertible, the exponent can first be reduced (mod p - 1) and (mod q - 1) by Fermat's
+/// little theorem, which is where most of the savings come from.
+fn pow_crt(
+    base: &DynResidue<LIMBS>,
+    exponent: &BigInt,
+    p: &BigInt,
+    q: &BigInt,
+) -> DynResidue<LIMBS> {
+    let pow_mod_prime = |prime: &BigInt| {
+        let order = NonZero::new(prime.wrapping_sub(&BigInt::ONE)).unwrap();
+        let exponent = exponent % order;
+        let base = DynResidue::new(&base.retrieve(), DynResidueParams::new(prime));
+        return base.pow_bounded_exp(&exponent, exponent.bits());
+    };
+    let val_mod_p = pow_mod_prime(p);
+    let val_mod_q = pow_mod_prime(q);
+
+    // x = val_mod_p + p * ((val_mod_q - val_mod_p) * (p ** -1) mod q), which is less than n
+    let q_params = *val_mod_q.params();
+    let (p_inv, _) = DynResidue::new(p, q_params).invert();
+    let h = (val_mod_q - DynResidue::new(&val_mod_p.retrieve(), q_params)) * p_inv;
+    let x = val_mod_p
+        .retrieve()
+        .wrapping_add(&p.wrapping_mul(&h.retrieve()));
+    return DynResidue::new(&x, *base.params());
+}
+
+/// Parse a big-endian hex string into a big integer. A leading "0x" is tolerated, but the
+/// number of hex digits must be even and the value must fit in LIMBS words.
+pub fn parse_hex(hex: &str) -> Result<BigInt, ParseError> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if !hex.len().is_multiple_of(2) {
+        return Err(ParseError::OddLength);
+    }
+    if !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(ParseError::InvalidDigit);
+    }
+    if hex.len() / 2 > BigInt::BYTES {
+        return Err(ParseError::TooLong);
+    }
+
+    let mut bytes = [0u8; BigInt::BYTES];
+    let offset = BigInt::BYTES - hex.len() / 2;
+    for (i, byte) in bytes[offset..].iter_mut().enumerate() {
+        // all digits are ASCII hex digits, so parsing cannot fail
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+    }
+    return Ok(BigInt::from_be_slice(&bytes));
+}
+
+/// Parse a hex string into the parameters of a modulus, which must be odd
+fn parse_modulus_hex(hex: &str) -> Result<DynResidueParams<LIMBS>, ParseError> {
+    let modulus = parse_hex(hex)?;
+    if !bool::from(modulus.is_odd()) {
+        return Err(ParseError::EvenModulus);
+    }
+    return Ok(DynResidueParams::new(&modulus));
+}
+
 /// Sample a random element from the multiplicative group Z/n
+#[cfg(feature = "std")]
 pub fn sample_invertible(modulus: DynResidueParams<LIMBS>) -> DynResidue<LIMBS> {
+    return sample_invertible_with_rng(modulus, &mut OsRng);
+}
+
+/// Sample a random element from the multiplicative group Z/n using the supplied source of
+/// randomness
+pub fn sample_invertible_with_rng(
+    modulus: DynResidueParams<LIMBS>,
+    rng: &mut impl CryptoRngCore,
+) -> DynResidue<LIMBS> {
     loop {
-        let val = DynResidue::new(&BigInt::random(&mut OsRng), modulus);
+        let val = DynResidue::new(&BigInt::random(rng), modulus);
         let (_, invertible) = val.invert();
         if invertible.into() {
             return val;
@@ -397,8 +1262,43 @@ pub fn sample_invertible(modulus: DynResidueParams<LIMBS>) -> DynResidue<LIMBS>
     }
 }
 
+/// The number of draws GroupModulus::sample makes before giving up. If the smallest prime factor
+/// of n has k bits, a draw is not invertible with probability less than 2 ** (2 - k), so under a
+/// valid modulus all of the draws fail with probability less than 2 ** (128 * (2 - k)).
+pub const MAX_SAMPLE_TRIES: usize = 128;
+
+/// Sample a random element from the multiplicative group Z/n, or return None if none of the
+/// first max_tries draws is invertible
+#[cfg(feature = "std")]
+pub fn sample_invertible_bounded(
+    modulus: DynResidueParams<LIMBS>,
+    max_tries: usize,
+) -> Option<DynResidue<LIMBS>> {
+    return sample_invertible_bounded_with_rng(modulus, max_tries, &mut OsRng);
+}
+
+/// Sample a random element from the multiplicative group Z/n as sample_invertible_bounded does,
+/// using the supplied source of randomness
+pub fn sample_invertible_bounded_with_rng(
+    modulus: DynResidueParams<LIMBS>,
+    max_tries: usize,
+    rng: &mut impl CryptoRngCore,
+) -> Option<DynResidue<LIMBS>> {
+    for _ in 0..max_tries {
+        let val = DynResidue::new(&BigInt::random(rng), modulus);
+        let (_, invertible) = val.invert();
+        if invertible.into() {
+            return Some(val);
+        }
+    }
+    return None;
+}
+
 /// Brute-force discrete log given that the base has small order under the modulus.
 /// If no discrete log can be found, return None;
+///
+/// This returns as soon as the exponent is found, so the running time reveals the magnitude of
+/// the exponent. Use discrete_log_ct when the exponent is secret, such as when decrypting.
 pub fn discrete_log(
     base: &BigInt,
     target: &BigInt,
@@ -419,9 +1319,39 @@ pub fn discrete_log(
     return None;
 }
 
+/// Brute-force discrete log that always walks through every exponent in 0..order, so that the
+/// running time depends only on the order and not on the exponent. The powers of the base are
+/// accumulated with one multiplication per step, and the exponent is picked out with
+/// constant-time selects instead of an early return.
+pub fn discrete_log_ct(
+    base: &BigInt,
+    target: &BigInt,
+    order: &BigInt,
+    modulus: &BigInt,
+) -> Option<BigInt> {
+    let modulus = DynResidueParams::new(modulus);
+    let base = DynResidue::new(base, modulus);
+    let target = DynResidue::new(target, modulus).retrieve();
+    let mut power = DynResidue::one(modulus);
+    let mut exp = BigInt::ZERO;
+    let mut log = BigInt::ZERO;
+    let mut found = Choice::from(0);
+
+    // the order is public, so comparing against it does not leak anything
+    while exp < *order {
+        let is_match = power.retrieve().ct_eq(&target) & !found;
+        log = BigInt::conditional_select(&log, &exp, is_match);
+        found |= is_match;
+        power *= base;
+        exp = exp.wrapping_add(&BigInt::ONE);
+    }
+    return Option::from(CtOption::new(log, found));
+}
+
 #[cfg(test)]
 mod tests {
-    use crypto_bigint::{rand_core::OsRng, NonZero, RandomMod};
+    use crypto_bigint::{rand_core::OsRng, RandomMod};
+    use std::collections::HashSet;
 
     use super::*;
     const RINGSIZE: usize = 16;
@@ -447,10 +1377,7 @@ mod tests {
 
         // y^e for 1 <= e < r is never an r-th residue
         for _ in 1..100 {
-            let e = BigInt::random_mod(
-                &mut OsRng,
-                &NonZero::new(keypair.get_pk().get_r().modulus().clone()).unwrap(),
-            );
+            let e = BigInt::random_mod(&mut OsRng, &keypair.get_pk().get_r().as_nonzero());
             if e == BigInt::ZERO {
                 continue;
             }
@@ -463,4 +1390,699 @@ mod tests {
             assert!(nonroot.is_none());
         }
     }
+
+    /// r - 1 is the largest residue class of the ring
+    fn largest_class(r: &RingModulus) -> BigInt {
+        return r.modulus_ref().wrapping_sub(&BigInt::ONE);
+    }
+
+    #[test]
+    fn test_class_as_u64() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let residue = ClearResidue::random(
+            Some(DynResidue::new(&BigInt::from_u8(5), r)),
+            keypair.get_pk(),
+        );
+        assert_eq!(residue.class_as_u64(), Some(5));
+
+        // the largest class of a 16-bit ring always fits
+        let largest = (-ResidueClass::one(r)).as_u64().unwrap();
+        assert_eq!(
+            BigInt::from_u64(largest),
+            largest_class(keypair.get_pk().get_r())
+        );
+
+        // the ring is large enough to hold classes beyond 64 bits
+        let large_ring = RingModulus::from_hex("0x0100000000000000000000000f").unwrap();
+        let params = large_ring.to_dyn_residue_params();
+        let u64_max = ResidueClass::new(DynResidue::new(&BigInt::from_u64(u64::MAX), params));
+        assert_eq!(u64_max.as_u64(), Some(u64::MAX));
+        let beyond = u64_max + ResidueClass::one(params);
+        assert_eq!(beyond.as_u64(), None);
+        assert_eq!((-ResidueClass::one(params)).as_u64(), None);
+    }
+
+    /// Residues that are equal by value should collapse into a single entry of a hash set
+    #[test]
+    fn test_hash_residues() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let n = keypair.get_pk().get_n().to_dyn_residue_params();
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let seven = BigInt::from_u8(7);
+
+        let opaque: HashSet<OpaqueResidue> = [
+            OpaqueResidue::new(DynResidue::new(&seven, n)),
+            OpaqueResidue::new(DynResidue::new(&seven, n)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(opaque.len(), 1);
+
+        let classes: HashSet<ResidueClass> = [
+            ResidueClass::new(DynResidue::new(&seven, r)),
+            ResidueClass::new(DynResidue::new(&seven, r)),
+            ResidueClass::one(r),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(classes.len(), 2);
+    }
+
+    #[test]
+    fn test_opaque_one() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let n = keypair.get_pk().get_n().to_dyn_residue_params();
+        let one = OpaqueResidue::one(n);
+        assert!(one.is_one());
+
+        let residue = OpaqueResidue::new(keypair.get_pk().sample_invertible());
+        assert_eq!(one * residue, residue);
+        assert_eq!(residue * one, residue);
+        assert!((residue * residue.invert()).is_one());
+    }
+
+    #[test]
+    fn test_compose_typed() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let rc = ResidueClass::from_u64(3, pk.get_r());
+        let witness = Witness::new(pk.sample_invertible());
+        let composed = ClearResidue::compose_typed(rc, witness, pk).unwrap();
+        assert_eq!(
+            composed,
+            ClearResidue::compose(rc.clone_residue(), witness.clone_residue(), pk)
+        );
+        assert_eq!(keypair.decrypt(composed.get_val()), rc);
+
+        // the class and the witness swapped, or reduced under some other modulus
+        let swapped_rc = ResidueClass::new(witness.clone_residue());
+        let swapped_witness = Witness::new(rc.clone_residue());
+        assert_eq!(
+            ClearResidue::compose_typed(swapped_rc, swapped_witness, pk),
+            Err(ArithmeticError::ModulusMismatch)
+        );
+        let other_ring = RingModulus::from_hex("0d").unwrap();
+        assert_eq!(
+            ClearResidue::compose_typed(ResidueClass::from_u64(3, &other_ring), witness, pk),
+            Err(ArithmeticError::ModulusMismatch)
+        );
+        assert_eq!(
+            ClearResidue::compose_typed(
+                rc,
+                Witness::new(DynResidue::zero(*pk.get_n().params())),
+                pk
+            ),
+            Err(ArithmeticError::NonInvertibleWitness)
+        );
+    }
+
+    #[test]
+    fn test_from_u64() {
+        let r = RingModulus::from_hex("0d").unwrap();
+        assert_eq!(ResidueClass::from_u64(5, &r).retrieve(), BigInt::from_u8(5));
+        assert_eq!(
+            ResidueClass::from_u64(13, &r),
+            ResidueClass::zero(*r.params())
+        );
+        assert_eq!(
+            ResidueClass::from_u64(u64::MAX, &r).as_u64(),
+            Some(u64::MAX % 13)
+        );
+
+        // n = 7 * 11
+        let n = GroupModulus::from_uint(&BigInt::from_u8(77));
+        assert!(OpaqueResidue::from_u64(1, &n).is_one());
+        assert!(OpaqueResidue::from_u64(78, &n).is_one());
+        assert_eq!(
+            OpaqueResidue::from_u64(100, &n).retrieve(),
+            BigInt::from_u8(23)
+        );
+    }
+
+    #[test]
+    fn test_from_conversions() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let class = DynResidue::new(&BigInt::from_u8(7), r);
+        let rc: ResidueClass = class.into();
+        assert_eq!(rc, ResidueClass::new(class));
+        let roundtrip: DynResidue<LIMBS> = rc.into();
+        assert_eq!(roundtrip, class);
+
+        let residue = keypair.get_pk().sample_invertible();
+        let opaque: OpaqueResidue = residue.into();
+        assert_eq!(opaque, OpaqueResidue::new(residue));
+        let roundtrip: DynResidue<LIMBS> = opaque.into();
+        assert_eq!(roundtrip, residue);
+    }
+
+    #[test]
+    fn test_into_opaque() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let clear = ClearResidue::random(None, keypair.get_pk());
+        let val = clear.clone_val();
+        assert_eq!(*clear.as_opaque(), val);
+        assert_eq!(clear.into_opaque(), val);
+    }
+
+    #[test]
+    fn test_encrypt_with_witness() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let class = DynResidue::new(&BigInt::from_u8(3), pk.get_r().to_dyn_residue_params());
+        let witness = pk.sample_invertible();
+        let first = ClearResidue::encrypt_with_witness(class, witness, pk).unwrap();
+        let second = ClearResidue::encrypt_with_witness(class, witness, pk).unwrap();
+        assert_eq!(first.get_val(), second.get_val());
+        assert_eq!(first, ClearResidue::compose(class, witness, pk));
+
+        let zero = DynResidue::new(&BigInt::ZERO, pk.get_n().to_dyn_residue_params());
+        assert!(ClearResidue::encrypt_with_witness(class, zero, pk).is_none());
+    }
+
+    #[test]
+    fn test_encrypt_deterministic() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let class = DynResidue::new(&BigInt::ONE, pk.get_r().to_dyn_residue_params());
+        let first = ClearResidue::encrypt_deterministic(class, b"seed", pk);
+        let second = ClearResidue::encrypt_deterministic(class, b"seed", pk);
+        let other = ClearResidue::encrypt_deterministic(class, b"other seed", pk);
+        assert_eq!(first, second);
+        assert_ne!(first.get_val(), other.get_val());
+        assert!(first.is_consistent());
+        assert_eq!(keypair.decrypt(first.get_val()), *first.get_rc());
+    }
+
+    /// Multiplying two residues whose classes wrap around r loses the carry in the witness;
+    /// canonicalizing recovers it while preserving the class and the value
+    #[test]
+    fn test_canonicalize() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let r = pk.get_r().to_dyn_residue_params();
+
+        let fresh = ClearResidue::random(None, pk);
+        assert!(fresh.is_consistent());
+        assert_eq!(fresh.canonicalize(), Some(fresh));
+
+        let largest = DynResidue::new(&largest_class(pk.get_r()), r);
+        let two = DynResidue::new(&BigInt::from_u8(2), r);
+        let wrapped = ClearResidue::random(Some(largest), pk) * ClearResidue::random(Some(two), pk);
+        assert!(!wrapped.is_consistent());
+        let canonical = wrapped.canonicalize().unwrap();
+        assert!(canonical.is_consistent());
+        assert_eq!(canonical.get_rc().retrieve(), BigInt::ONE);
+        assert_eq!(canonical.get_val(), wrapped.get_val());
+
+        // a witness that is off by more than a power of y cannot be explained by any carry
+        let tampered = ClearResidue::new(
+            canonical.clone_val(),
+            canonical.clone_rc(),
+            canonical.clone_witness() * canonical.clone_witness(),
+            pk,
+        );
+        assert_eq!(tampered.canonicalize(), None);
+    }
+
+    /// Decomposing with the Chinese remainder theorem should give exactly the same result as
+    /// decomposing (mod n)
+    #[test]
+    fn test_decompose_crt() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        assert!(keypair.get_factors().is_some());
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        for class in 0..32u64 {
+            let class = DynResidue::new(&BigInt::from_u64(class), r);
+            let ct = ClearResidue::random(Some(class), keypair.get_pk())
+                .clone_val()
+                .clone_residue();
+            let crt = ClearResidue::decompose_crt(ct, &keypair);
+            assert_eq!(crt, ClearResidue::decompose(ct, &keypair));
+            assert_eq!(crt.get_rc().retrieve(), class.retrieve());
+        }
+
+        // a value outside of the multiplicative group is an error instead of a panic
+        let (p, _) = keypair.get_factors().unwrap();
+        let malformed = DynResidue::new(p, keypair.get_pk().get_n().to_dyn_residue_params());
+        assert_eq!(
+            ClearResidue::try_decompose_crt(malformed, &keypair),
+            Err(DecryptError::MalformedCiphertext)
+        );
+    }
+
+    /// Over a small ring, many draws never produce zero but do cover every non-zero element
+    #[test]
+    fn test_sample_nonzero() {
+        let r = RingModulus::from_hex("0x07").unwrap();
+        let samples: HashSet<BigInt> = (0..1000).map(|_| r.sample_nonzero().retrieve()).collect();
+        assert!(!samples.contains(&BigInt::ZERO));
+        assert_eq!(samples.len(), 6);
+    }
+
+    #[test]
+    fn test_ring_modulus_as_nonzero() {
+        let r = RingModulus::from_hex("0xfff1").unwrap();
+        assert_eq!(*r.as_nonzero(), r.to_uint());
+        assert_eq!(r.as_nonzero(), r.as_nonzero());
+    }
+
+    /// The constant-time discrete log finds the same exponent as the early-exit one, from the
+    /// smallest class to the largest. How long it takes for each is measured in benches/.
+    #[test]
+    fn test_discrete_log_ct() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let r = pk.get_r().modulus_ref();
+        let n = pk.get_n().modulus_ref();
+        let base = pk.get_y().pow(&keypair.phi_over_r());
+        for exp in [
+            BigInt::ZERO,
+            BigInt::ONE,
+            BigInt::from_u8(5),
+            largest_class(pk.get_r()),
+        ] {
+            let target = base.pow(&exp).retrieve();
+            assert_eq!(discrete_log_ct(&base.retrieve(), &target, r, n), Some(exp));
+            assert_eq!(
+                discrete_log_ct(&base.retrieve(), &target, r, n),
+                discrete_log(&base.retrieve(), &target, r, n)
+            );
+        }
+
+        // 2 is (with overwhelming probability) not a power of y ** (phi / r)
+        let outside = BigInt::from_u8(2);
+        assert_eq!(discrete_log_ct(&base.retrieve(), &outside, r, n), None);
+    }
+
+    /// Taking y ** c from the precomputed powers gives the same residue as computing it, both
+    /// for a precomputed class and for a class that falls back to the exponentiation
+    #[test]
+    fn test_class_powers() {
+        use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let params = pk.get_r().to_dyn_residue_params();
+        let classes = [0, 1, 1].map(|c| ResidueClass::from_u64(c, pk.get_r()));
+        let powers = ClassPowers::new(&classes, pk);
+        assert_eq!(powers.powers.len(), 2);
+
+        for class in [0u8, 1, 5] {
+            let class = DynResidue::new(&BigInt::from_u8(class), params);
+            let uncached = ClearResidue::random_with_rng(
+                Some(class),
+                pk,
+                &mut ChaCha20Rng::seed_from_u64(868),
+            );
+            let cached = ClearResidue::random_with_powers(
+                class,
+                &powers,
+                &mut ChaCha20Rng::seed_from_u64(868),
+            );
+            assert_eq!(cached, uncached);
+            assert!(cached.is_consistent());
+        }
+    }
+
+    #[test]
+    fn test_be_bytes_round_trip() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let ct = ClearResidue::random(None, pk).into_opaque();
+        let bytes = ct.to_be_bytes();
+        assert_eq!(bytes.len(), LIMBS * 8);
+        assert_eq!(OpaqueResidue::from_be_bytes(&bytes, pk.get_n()), ct);
+
+        let rc = ResidueClass::new(pk.get_r().sample());
+        let bytes = rc.to_be_bytes();
+        assert_eq!(bytes.len(), LIMBS * 8);
+        assert_eq!(ResidueClass::from_be_bytes(&bytes, pk.get_r()), rc);
+
+        // shorter inputs are left-padded
+        assert_eq!(
+            ResidueClass::from_be_bytes(&[0x01, 0x00], pk.get_r()).retrieve(),
+            BigInt::from_u16(256)
+        );
+        assert_eq!(
+            ResidueClass::from_be_bytes(&[], pk.get_r()),
+            ResidueClass::zero(*pk.get_r().params())
+        );
+    }
+
+    /// Inputs longer than a BigInt are reduced as a whole, not truncated
+    #[test]
+    fn test_from_be_bytes_wide() {
+        use crypto_bigint::Uint;
+        use rand_chacha::{
+            rand_core::{RngCore, SeedableRng},
+            ChaCha20Rng,
+        };
+
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let (r, n) = (keypair.get_pk().get_r(), keypair.get_pk().get_n());
+        let mut rng = ChaCha20Rng::seed_from_u64(890);
+        for len in [BigInt::BYTES + 1, 2 * BigInt::BYTES - 5, 2 * BigInt::BYTES] {
+            let mut bytes = vec![0u8; len];
+            rng.fill_bytes(&mut bytes);
+
+            // reduce the same integer as a double-width Uint
+            let mut padded = [0u8; 2 * BigInt::BYTES];
+            padded[2 * BigInt::BYTES - len..].copy_from_slice(&bytes);
+            let wide = Uint::<{ 2 * LIMBS }>::from_be_slice(&padded);
+            let reduce = |modulus: &BigInt| {
+                let mut modulus_bytes = [0u8; 2 * BigInt::BYTES];
+                modulus_bytes[BigInt::BYTES..].copy_from_slice(&modulus.to_be_bytes());
+                let modulus = NonZero::new(Uint::from_be_slice(&modulus_bytes)).unwrap();
+                let rem: [u8; 2 * BigInt::BYTES] = wide.rem(&modulus).to_be_bytes();
+                return BigInt::from_be_slice(&rem[BigInt::BYTES..]);
+            };
+
+            assert_eq!(
+                ResidueClass::from_be_bytes(&bytes, r).retrieve(),
+                reduce(r.modulus_ref())
+            );
+            assert_eq!(
+                OpaqueResidue::from_be_bytes(&bytes, n).retrieve(),
+                reduce(n.modulus_ref())
+            );
+        }
+
+        // leading zeros do not change the value
+        let rc = ResidueClass::new(r.sample());
+        let mut bytes = vec![0u8; 3 * BigInt::BYTES];
+        bytes[2 * BigInt::BYTES..].copy_from_slice(&rc.to_be_bytes());
+        assert_eq!(ResidueClass::from_be_bytes(&bytes, r), rc);
+    }
+
+    #[test]
+    fn test_lift_to() {
+        let small = RingModulus::from_uint(&BigInt::from_u8(13));
+        let large = RingModulus::from_uint(&BigInt::from_u16(65521));
+        let seven = ResidueClass::new(DynResidue::new(
+            &BigInt::from_u8(7),
+            small.to_dyn_residue_params(),
+        ));
+        let nine = ResidueClass::new(DynResidue::new(
+            &BigInt::from_u8(9),
+            large.to_dyn_residue_params(),
+        ));
+        let lifted = seven.lift_to(&large).unwrap();
+        assert_eq!(lifted.retrieve(), BigInt::from_u8(7));
+        assert_eq!(lifted.params().modulus(), large.modulus_ref());
+        // 7 + 9 wraps around in the small ring but not in the large one
+        assert_eq!((lifted + nine).retrieve(), BigInt::from_u8(16));
+        assert_eq!(seven.lift_to(&small), Some(seven));
+    }
+
+    #[test]
+    fn test_lift_to_smaller_ring() {
+        let small = RingModulus::from_uint(&BigInt::from_u8(13));
+        let large = RingModulus::from_uint(&BigInt::from_u16(65521));
+        assert_eq!(
+            ResidueClass::one(large.to_dyn_residue_params()).lift_to(&small),
+            None
+        );
+    }
+
+    /// Borrowing the parameters must give the same residues as cloning them
+    #[test]
+    fn test_params_ref() {
+        use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        assert_eq!(*pk.get_r().params(), pk.get_r().to_dyn_residue_params());
+        assert_eq!(*pk.get_n().params(), pk.get_n().to_dyn_residue_params());
+
+        let seeded = |seed| ChaCha20Rng::seed_from_u64(seed);
+        let sample = pk.get_r().sample_with_rng(&mut seeded(7));
+        let expected = DynResidue::new(
+            &BigInt::random(&mut seeded(7)),
+            pk.get_r().to_dyn_residue_params(),
+        );
+        assert_eq!(sample, expected);
+
+        let class = pk.get_r().sample();
+        let ct = ClearResidue::random_with_rng(Some(class), pk, &mut seeded(11));
+        let x = sample_invertible_with_rng(pk.get_n().to_dyn_residue_params(), &mut seeded(11));
+        assert_eq!(ct, ClearResidue::compose(class, x, pk));
+        assert_eq!(
+            ClearResidue::try_random_with_rng(Some(class), pk, &mut seeded(11)),
+            Some(ct)
+        );
+    }
+
+    #[test]
+    fn test_witness() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let r = pk.get_r();
+        let a = ClearResidue::random(None, pk);
+        let b = ClearResidue::random(None, pk);
+        let (x, z) = (a.clone_witness(), b.clone_witness());
+
+        assert_eq!(
+            (x * z).clone_residue(),
+            x.get_residue().mul(z.get_residue())
+        );
+        let exponent = ResidueClass::new(r.sample());
+        assert_eq!(
+            x.pow(&exponent).clone_residue(),
+            x.get_residue().pow(&exponent.retrieve())
+        );
+        assert_eq!(
+            (x * x.invert()).clone_residue(),
+            DynResidue::one(*pk.get_n().params())
+        );
+        assert!(x.is_invertible());
+
+        // The value divided by y ** c is the r-th power of the witness
+        let rth_residue = *a.get_val() * pk.invert_y().pow(a.get_rc());
+        assert_eq!(x.rth_power(r), rth_residue);
+        assert!(x.is_rth_root_of(&rth_residue, r));
+        assert!(!z.is_rth_root_of(&rth_residue, r));
+
+        // The class of the product may wrap around r, so only the canonical form is consistent
+        let product = a.clone() * b.clone();
+        assert_eq!(product.clone_witness(), x * z);
+        assert!(product.canonicalize().unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_modulus_equality() {
+        let r = BigInt::from_u16(65521);
+        assert_eq!(
+            RingModulus::new(DynResidueParams::new(&r)),
+            RingModulus::from_uint(&r)
+        );
+        assert_eq!(
+            RingModulus::from_uint(&r),
+            RingModulus::from_hex("fff1").unwrap()
+        );
+        assert_ne!(
+            RingModulus::from_uint(&r),
+            RingModulus::from_uint(&BigInt::from_u16(65519))
+        );
+
+        let n = BigInt::from_u64(0xb4a1_c3e5_0f7d_9a3b);
+        assert_eq!(
+            GroupModulus::new(DynResidueParams::new(&n)),
+            GroupModulus::from_uint(&n)
+        );
+        assert_ne!(
+            GroupModulus::from_uint(&n),
+            GroupModulus::from_uint(&BigInt::from_u8(77))
+        );
+    }
+
+    #[test]
+    fn test_group_contains() {
+        // n = 7 * 11
+        let group = GroupModulus::from_uint(&BigInt::from_u8(77));
+        let invertible = DynResidue::new(&BigInt::from_u8(2), group.to_dyn_residue_params());
+        assert!(group.contains(&invertible));
+        let multiple_of_p = DynResidue::new(&BigInt::from_u8(14), group.to_dyn_residue_params());
+        assert!(!group.contains(&multiple_of_p));
+        let other = DynResidueParams::new(&BigInt::from_u8(79));
+        assert!(!group.contains(&DynResidue::new(&BigInt::from_u8(2), other)));
+    }
+
+    /// A decomposition that does not recompose to its value, such as one with a faulty witness,
+    /// is reported instead of being returned
+    #[test]
+    fn test_decompose_checked() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let ct = ClearResidue::random(None, pk);
+        let decomposed =
+            ClearResidue::decompose_checked(ct.get_val().clone_residue(), &keypair).unwrap();
+        assert_eq!(decomposed.get_val(), ct.get_val());
+        assert_eq!(decomposed.get_rc(), ct.get_rc());
+        assert!(decomposed.is_consistent());
+
+        let faulty_witness = ct.clone_witness() * Witness::new(pk.sample_invertible());
+        let faulty = ClearResidue::new(ct.clone_val(), ct.clone_rc(), faulty_witness, pk);
+        assert_eq!(
+            faulty.into_consistent(),
+            Err(DecryptError::InconsistentDecomposition)
+        );
+    }
+
+    #[test]
+    fn test_small_residue_class() {
+        let r = RingModulus::from_hex("fff1").unwrap();
+        for val in [0, 1, 0xfff0] {
+            let class = ResidueClass::from_u64(val, &r);
+            let small = SmallResidueClass::from_class(&class).unwrap();
+            assert_eq!(small.get() as u64, val);
+            assert_eq!(small.to_class(&r), class);
+            assert_eq!(SmallResidueClass::from_be_bytes(small.to_be_bytes()), small);
+        }
+
+        let large = RingModulus::from_hex("010001").unwrap();
+        assert!(!SmallResidueClass::fits(large.modulus_ref()));
+        assert_eq!(
+            SmallResidueClass::from_class(&ResidueClass::from_u64(1, &large)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_conditional_select() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let a = ClearResidue::random(None, pk).clone_val();
+        let b = ClearResidue::random(None, pk).clone_val();
+        assert_eq!(
+            OpaqueResidue::conditional_select(&a, &b, Choice::from(1)),
+            b
+        );
+        assert_eq!(
+            OpaqueResidue::conditional_select(&a, &b, Choice::from(0)),
+            a
+        );
+
+        let zero = ResidueClass::from_u64(0, pk.get_r());
+        let one = ResidueClass::from_u64(1, pk.get_r());
+        assert_eq!(
+            ResidueClass::conditional_select(&zero, &one, Choice::from(1)),
+            one
+        );
+        assert_eq!(
+            ResidueClass::conditional_select(&zero, &one, Choice::from(0)),
+            zero
+        );
+
+        let mut swapped = a;
+        swapped.conditional_assign(&b, Choice::from(1));
+        assert_eq!(swapped, b);
+    }
+
+    #[test]
+    fn test_square_and_pow_u64() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let r = pk.get_r();
+        // the second class wraps around r when squared
+        for class in [
+            ResidueClass::from_u64(3, r),
+            -ResidueClass::one(*r.params()),
+        ] {
+            let residue = ClearResidue::random(Some(class.clone_residue()), pk);
+            let squared = residue.square();
+            assert_eq!(squared, residue.pow_u64(2));
+            assert_eq!(
+                Some(squared.clone()),
+                (residue.clone() * residue.clone()).canonicalize()
+            );
+            assert!(squared.is_consistent());
+            assert_eq!(squared.clone_rc(), class.clone() + class.clone());
+
+            let cubed = residue.pow_u64(3);
+            assert!(cubed.is_consistent());
+            assert_eq!(
+                cubed.get_val(),
+                &(squared.clone_val() * residue.clone_val())
+            );
+            assert!(residue.pow_u64(1) == residue);
+            assert_eq!(residue.pow_u64(0).get_val().retrieve(), BigInt::ONE);
+        }
+    }
+
+    #[test]
+    fn test_sample_invertible_bounded() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let n = keypair.get_pk().get_n();
+        let sample = sample_invertible_bounded(*n.params(), 1).unwrap();
+        assert!(n.contains(&sample));
+        assert_eq!(sample_invertible_bounded(*n.params(), 0), None);
+
+        // n = 7 * 11, where about a quarter of the draws are not invertible
+        let group = GroupModulus::from_uint(&BigInt::from_u8(77));
+        for _ in 0..100 {
+            assert!(group.contains(&group.sample()));
+        }
+        assert!(group.contains(&group.sample_bounded(MAX_SAMPLE_TRIES).unwrap()));
+        assert_eq!(group.sample_bounded(0), None);
+    }
+
+    #[test]
+    fn test_classes() {
+        let r = RingModulus::from_hex("0d").unwrap();
+        let classes = r.classes().map(|rc| rc.retrieve()).collect::<Vec<BigInt>>();
+        assert_eq!(classes.len(), 13);
+        assert_eq!(classes.iter().collect::<HashSet<_>>().len(), 13);
+        assert_eq!(classes[0], BigInt::ZERO);
+        assert_eq!(classes[12], BigInt::from_u8(12));
+        assert_eq!(r.try_classes().unwrap().count(), 13);
+
+        let large = RingModulus::from_hex("1fffffffffffffff").unwrap();
+        assert_eq!(
+            large.try_classes().err(),
+            Some(ArithmeticError::RingTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_modulus_ref() {
+        let r = RingModulus::from_hex("0xfff1").unwrap();
+        assert_eq!(*r.modulus_ref(), r.to_uint());
+        let n = GroupModulus::from_hex("0xc5").unwrap();
+        assert_eq!(*n.modulus_ref(), n.to_uint());
+    }
+
+    #[test]
+    fn test_moduli_from_hex() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let r = keypair.get_pk().get_r();
+        let n = keypair.get_pk().get_n();
+        assert_eq!(
+            RingModulus::from_hex(&format!("{:x}", r.to_uint())),
+            Ok(r.clone())
+        );
+        assert_eq!(
+            GroupModulus::from_hex(&format!("0x{:x}", n.to_uint())),
+            Ok(n.clone())
+        );
+        assert_eq!(
+            RingModulus::from_hex("0x010001").unwrap().to_uint(),
+            BigInt::from_u32(65537)
+        );
+    }
+
+    #[test]
+    fn test_moduli_from_bad_hex() {
+        assert_eq!(RingModulus::from_hex("10001"), Err(ParseError::OddLength));
+        assert_eq!(
+            GroupModulus::from_hex("0xzz01"),
+            Err(ParseError::InvalidDigit)
+        );
+        assert_eq!(
+            GroupModulus::from_hex(&"01".repeat(BigInt::BYTES + 1)),
+            Err(ParseError::TooLong)
+        );
+        assert_eq!(RingModulus::from_hex("0100"), Err(ParseError::EvenModulus));
+    }
 }
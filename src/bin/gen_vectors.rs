@@ -0,0 +1,105 @@
+//! Generate test vectors for cross-checking other implementations of the cryptosystem
+//!
+//! Usage: gen_vectors <seed> [count] > vectors.json
+//!
+//! Every random choice is drawn from a ChaCha20 stream seeded with the seed, so the same seed
+//! always produces the same bytes. Each vector is a fresh key pair with a ballot (an encryption
+//! of 0 or 1) and the proof of its validity. Integers are written as fixed-width big-endian hex,
+//! and the ballot proof is the hex of BallotProof::to_bytes.
+
+use benaloh_cryptosystem::{
+    arithmetics::{ClearResidue, ResidueClass},
+    keys::KeyPair,
+    proofs::ballot::{self, BallotProof},
+    BigInt, GROUPSIZE, RINGSIZE,
+};
+use crypto_bigint::{rand_core::CryptoRngCore, Encoding};
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+use std::fmt::Write;
+
+const DEFAULT_COUNT: usize = 4;
+
+/// Write the bytes as lowercase hex
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 * bytes.len());
+    for byte in bytes {
+        write!(hex, "{byte:02x}").expect("writing to a string cannot fail");
+    }
+    return hex;
+}
+
+/// Write the integer as a JSON string of fixed-width hex
+fn uint_to_json(val: &BigInt) -> String {
+    return format!("\"{}\"", to_hex(&val.to_be_bytes()));
+}
+
+/// Generate one vector as a JSON object
+fn generate_vector(rng: &mut impl CryptoRngCore) -> String {
+    let keypair = KeyPair::keygen_with_rng(RINGSIZE, GROUPSIZE, false, rng);
+    let pk = keypair.get_pk();
+    let classes = ballot::zero_or_one(pk.get_r());
+    let plaintext = rng.next_u32() % 2;
+    let vote = ResidueClass::from_u64(plaintext as u64, pk.get_r());
+    let ciphertext = ClearResidue::random_with_rng(Some(vote.clone_residue()), pk, rng);
+    let proof = BallotProof::from_statement_with_rng(&ciphertext, &classes, pk, rng);
+
+    let fields = [
+        ("r", uint_to_json(pk.get_r().modulus_ref())),
+        ("n", uint_to_json(pk.get_n().modulus_ref())),
+        ("y", uint_to_json(&pk.get_y().retrieve())),
+        ("phi", uint_to_json(keypair.get_sk().get_phi())),
+        ("plaintext", plaintext.to_string()),
+        (
+            "witness",
+            uint_to_json(&ciphertext.get_witness().get_residue().retrieve()),
+        ),
+        ("ciphertext", uint_to_json(&ciphertext.get_val().retrieve())),
+        ("ballot_proof", format!("\"{}\"", to_hex(&proof.to_bytes()))),
+    ];
+    let fields = fields
+        .iter()
+        .map(|(name, val)| format!("\"{name}\": {val}"))
+        .collect::<Vec<String>>();
+    return format!("    {{{}}}", fields.join(", "));
+}
+
+/// Generate count vectors from the seed as a JSON document
+fn generate(seed: u64, count: usize) -> String {
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+    let vectors = (0..count)
+        .map(|_| generate_vector(&mut rng))
+        .collect::<Vec<String>>();
+    return format!(
+        "{{\n  \"seed\": {seed},\n  \"ring_size\": {RINGSIZE},\n  \"group_size\": {GROUPSIZE},\n  \"vectors\": [\n{}\n  ]\n}}\n",
+        vectors.join(",\n")
+    );
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = match args.get(1).map(|seed| seed.parse::<u64>()) {
+        Some(Ok(seed)) => seed,
+        _ => {
+            eprintln!("Usage: gen_vectors <seed> [count]");
+            std::process::exit(1);
+        }
+    };
+    let count = match args.get(2) {
+        Some(count) => count.parse::<usize>().expect("count must be an integer"),
+        None => DEFAULT_COUNT,
+    };
+    print!("{}", generate(seed, count));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_reproducible() {
+        let first = generate(904, 2);
+        assert_eq!(first, generate(904, 2));
+        assert_ne!(first, generate(905, 2));
+        assert_eq!(first.matches("\"ballot_proof\"").count(), 2);
+    }
+}
@@ -1,13 +1,12 @@
 //! A sample election procedure
 
 use benaloh_cryptosystem::{
-    arithmetics::{ClearResidue, OpaqueResidue},
+    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass},
     keys::KeyPair,
     proofs, BigInt, GROUPSIZE, LIMBS, RINGSIZE,
 };
 use crypto_bigint::modular::runtime_mod::DynResidue;
-use crypto_bigint::rand_core::OsRng;
-use crypto_bigint::{NonZero, RandomMod};
+use crypto_bigint::rand_core::{OsRng, RngCore};
 
 const PARAMS_CHALLENGE_ROUNDS: usize = 10;
 const VOTERS: usize = 1000;
@@ -25,48 +24,45 @@ fn keygen(ring_size: usize, group_size: usize, safe_prime: bool) -> KeyPair {
 /// randomly generated. The government then uses the secret key to decrypt the challenge and
 /// produces the residue class
 fn challenge_consonance(rounds: usize, keypair: &KeyPair) {
-    for round in 0..rounds {
-        print!("Consonance challenge round {round}/{rounds}...  ");
-        let challenge = proofs::consonance::ClearChallenge::generate(keypair.get_pk(), 1);
-        let opaque = challenge.obscure();
-        let gov_proof = proofs::consonance::GovernmentProof::respond(&opaque, &keypair);
-        challenge.verify_gov_proof(&gov_proof);
-        println!("Challenge successful!");
+    println!("Running {rounds} consonance challenge rounds");
+    let government = |challenge: &proofs::consonance::OpaqueChallenge| {
+        return proofs::consonance::GovernmentProof::respond(challenge, keypair);
+    };
+    let confidence = keypair.get_pk().consonance_confidence(&government, rounds);
+    if confidence < 1.0 {
+        panic!("The government failed to identify the residue classes of the challenge");
     }
+    println!("Challenge successful!");
 }
 
 /// Generate the ballots. Each ballot is a random encryption of 0 or 1.
-/// At each ballot, a proof of ballot's validity is generated and verified.
+/// The proof of each ballot's validity is then verified.
 /// The true ballot count is also kept for verification purpose.
 fn generate_ballots(keypair: &KeyPair, count: usize) -> (Vec<OpaqueResidue>, DynResidue<LIMBS>) {
-    let r = keypair.get_pk().get_r().to_dyn_residue_params();
-    let mut ballots: Vec<OpaqueResidue> = vec![]; // the set of ballots
-                                                  // The true tally count, used to verify that the decryption is correct later
+    let r = *keypair.get_pk().get_r().params();
+    // The true tally count, used to verify that the decryption is correct later
     let mut true_tally = DynResidue::new(&BigInt::ZERO, r);
-    println!("Generating {count} ballots");
-    for i in 0..count {
-        let two = NonZero::new(BigInt::from_u8(2)).unwrap();
-        let vote = DynResidue::new(
-            &BigInt::random_mod(&mut OsRng, &two),
-            keypair.get_pk().get_r().to_dyn_residue_params(),
-        );
-        let ballot = ClearResidue::random(Some(vote), keypair.get_pk());
+    let mut votes: Vec<ResidueClass> = vec![];
+    for _ in 0..count {
+        let vote = DynResidue::new(&BigInt::from_u32(OsRng.next_u32() % 2), r);
+        true_tally = true_tally.add(&vote);
+        votes.push(ResidueClass::new(vote));
+    }
 
-        let proof = proofs::ballot::BallotProof::from_statement(
-            &ballot,
-            &proofs::ballot::zero_or_one(&keypair.get_pk().get_r()),
-            keypair.get_pk(),
-        );
-        if !proof.verify() {
+    println!("Generating {count} ballots");
+    let classes = proofs::ballot::zero_or_one(keypair.get_pk().get_r());
+    let generated = proofs::ballot::generate_ballots(&votes, keypair.get_pk(), &classes);
+    let mut ballots: Vec<OpaqueResidue> = vec![]; // the set of ballots
+    for (i, (ballot, proof)) in generated.into_iter().enumerate() {
+        if !proof.verify(&classes, keypair.get_pk()) {
             panic!("Ballot's residue class cannot be validated");
         }
 
         if (i + 1) % (count / 10) == 0 {
-            println!("{}/{} ballots generated and verified", i + 1, count);
+            println!("{}/{} ballots verified", i + 1, count);
         }
 
-        ballots.push(ballot.clone_val());
-        true_tally = true_tally.add(&vote);
+        ballots.push(ballot);
     }
     println!("{count} ballots generated and verified");
 
@@ -77,20 +73,19 @@ fn generate_ballots(keypair: &KeyPair, count: usize) -> (Vec<OpaqueResidue>, Dyn
 /// proof is released and verified.
 /// Finally, the collected tally is verified against the true tally
 fn tally(keypair: &KeyPair, ballots: &[OpaqueResidue], true_tally: &DynResidue<LIMBS>) {
-    let mut product = DynResidue::new(
-        &BigInt::ONE,
-        keypair.get_pk().get_n().to_dyn_residue_params(),
-    );
+    let mut product = OpaqueResidue::one(*keypair.get_pk().get_n().params());
     for ballot in ballots {
-        product = product.mul(&ballot);
+        product = product * *ballot;
     }
-    let decryption = ClearResidue::decompose(product, &keypair);
-    let statement = ClearResidue::decompose(
-        product.mul(&keypair.get_pk().invert_y().pow(decryption.get_rc())),
-        &keypair,
-    );
-    let proof = proofs::tally::TallyProof::from_statement(statement, 1, keypair.get_pk());
-    if !proof.verify() {
+    let decryption = match ClearResidue::try_decompose(product.clone_residue(), keypair) {
+        Ok(decryption) => decryption,
+        Err(err) => {
+            eprintln!("The tally could not be decrypted: {err}");
+            std::process::exit(1);
+        }
+    };
+    let (_, proof) = proofs::tally::TallyProof::from_aggregate(&product, keypair);
+    if !proof.verify_public(&product, decryption.get_rc(), keypair.get_pk()) {
         panic!("The residue class of the tally failed to be verified");
     } else {
         println!("decryption proof verified");
@@ -0,0 +1,606 @@
+//! Building blocks for running an election on top of the cryptosystem
+use crate::{
+    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass},
+    keys::{KeyPair, PublicKey},
+    proofs::{
+        audit,
+        ballot::{zero_or_one, BallotProof},
+        tally::TallyProof,
+    },
+    BigInt, LIMBS,
+};
+use alloc::{vec, vec::Vec};
+use crypto_bigint::{modular::runtime_mod::DynResidue, rand_core::RngCore};
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+
+pub use crate::proofs::audit::AuditError;
+
+/// A running tally of the ballots. Each ballot is multiplied into the running product as it
+/// arrives, so the individual ciphertexts do not need to be kept around until the end of the
+/// election. By the homomorphic property, the residue class of the product is the sum of the
+/// votes.
+#[derive(Debug, Clone)]
+pub struct Tally {
+    /// The public key under which the ballots are encrypted
+    pk: PublicKey,
+
+    /// The product of all ballots added so far
+    product: OpaqueResidue,
+
+    /// The ballots counted through add_unique, to catch a replayed ciphertext
+    #[cfg(feature = "std")]
+    seen: HashSet<OpaqueResidue>,
+}
+
+impl Tally {
+    /// Start an empty tally, whose running product is the multiplicative identity
+    pub fn new(pk: &PublicKey) -> Self {
+        return Self {
+            pk: pk.clone(),
+            product: OpaqueResidue::one(*pk.get_n().params()),
+            #[cfg(feature = "std")]
+            seen: HashSet::new(),
+        };
+    }
+
+    /// Multiply a ballot into the running product. Return false without touching the running
+    /// product if the ballot is not a valid ciphertext.
+    pub fn add(&mut self, ballot: &OpaqueResidue) -> bool {
+        if !self.pk.is_valid_ciphertext(ballot) {
+            return false;
+        }
+        self.product = self.product * ballot.clone();
+        return true;
+    }
+
+    /// Multiply a ballot into the running product unless the identical ciphertext has already
+    /// been counted through this method. Return false without touching the running product if
+    /// the ballot is a duplicate or is not a valid ciphertext.
+    ///
+    /// Ballots are compared by their canonical value, so this catches a replayed ciphertext but
+    /// not a rerandomized copy of one, which is a different ciphertext of the same vote.
+    #[cfg(feature = "std")]
+    pub fn add_unique(&mut self, ballot: &OpaqueResidue) -> bool {
+        if self.seen.contains(ballot) || !self.add(ballot) {
+            return false;
+        }
+        self.seen.insert(*ballot);
+        return true;
+    }
+
+    /// Multiply a ballot into the running product unless the voter spoiled it, e.g. to audit the
+    /// voting machine. A spoiled ballot is published with its decomposition instead of being
+    /// counted; see ClearResidue::verify_spoiled. Return whether the ballot was counted.
+    pub fn add_if_cast(&mut self, ballot: &OpaqueResidue, spoiled: bool) -> bool {
+        if spoiled {
+            return false;
+        }
+        return self.add(ballot);
+    }
+
+    /// Multiply the running product of another tally into this one, e.g. to aggregate district
+    /// tallies into a national one. Return false without touching the running product if the
+    /// other tally is under a different public key.
+    pub fn merge(&mut self, other: &Tally) -> bool {
+        if self.pk != other.pk {
+            return false;
+        }
+        self.product = self.product * other.product;
+        #[cfg(feature = "std")]
+        self.seen.extend(other.seen.iter().copied());
+        return true;
+    }
+
+    /// Return a reference to the aggregate of all ballots added so far
+    pub fn get_product(&self) -> &OpaqueResidue {
+        return &self.product;
+    }
+
+    /// Decrypt the aggregate of all ballots, which gives the sum of the votes (mod r)
+    pub fn finalize(self, keypair: &KeyPair) -> DynResidue<LIMBS> {
+        let class = keypair
+            .try_decrypt(&self.product)
+            .expect("the product of valid ballots is a valid ciphertext");
+        return class.clone_residue();
+    }
+}
+
+/// The outcome of an election, as confirmed by an audit of its transcript
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct TallyResult {
+    /// The sum of the votes
+    pub tally: ResidueClass,
+
+    /// The number of ballots that were counted
+    pub ballot_count: usize,
+}
+
+impl TallyResult {
+    /// Check that the tally is no more than num_voters * max_per_vote, the largest sum the votes
+    /// can add up to. A larger tally means that the aggregate is corrupted. The check is only
+    /// conclusive if the largest sum is less than r: otherwise the sum may have wrapped around
+    /// (mod r) into the range, so a ring that is too small for the election fails the check.
+    pub fn validate_range(&self, num_voters: u64, max_per_vote: u64) -> bool {
+        let max_tally = BigInt::from_u128(num_voters as u128 * max_per_vote as u128);
+        if max_tally >= *self.tally.params().modulus() {
+            return false;
+        }
+        return self.tally.retrieve() <= max_tally;
+    }
+}
+
+/// The announced outcome of one district of an election whose districts share a public key
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DistrictResult {
+    /// The product of the ballots cast in the district
+    pub aggregate: OpaqueResidue,
+
+    /// The announced sum of the votes in the district
+    pub tally: ResidueClass,
+
+    /// The proof that the aggregate decrypts to the tally
+    pub proof: TallyProof,
+
+    /// The number of ballots cast in the district
+    pub ballot_count: usize,
+}
+
+/// The outcome of an election combined from its districts
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct NationalResult {
+    /// The product of the district aggregates, which decrypts to the national tally
+    pub aggregate: OpaqueResidue,
+
+    /// The sum of the district tallies
+    pub tally: ResidueClass,
+
+    /// The total number of ballots across the districts
+    pub ballot_count: usize,
+}
+
+/// Combine the results of the districts into the national result. Every district tally proof is
+/// verified against its aggregate, and since each ballot is a 0 or a 1, each district tally must
+/// be at most its ballot count. The total ballot count must be less than r, so that the sum of
+/// the district tallies cannot wrap around.
+pub fn combine_districts(
+    districts: &[DistrictResult],
+    pk: &PublicKey,
+) -> Result<NationalResult, AuditError> {
+    let mut aggregate = Tally::new(pk);
+    let mut tally = ResidueClass::zero(*pk.get_r().params());
+    let mut ballot_count: usize = 0;
+    for (i, district) in districts.iter().enumerate() {
+        if !district
+            .proof
+            .verify_public(&district.aggregate, &district.tally, pk)
+            || !aggregate.add(&district.aggregate)
+        {
+            return Err(AuditError::InvalidDistrictProof(i));
+        }
+        let result = TallyResult {
+            tally: district.tally,
+            ballot_count: district.ballot_count,
+        };
+        if !result.validate_range(district.ballot_count as u64, 1) {
+            return Err(AuditError::DistrictOutOfRange(i));
+        }
+        tally = tally + district.tally;
+        ballot_count = ballot_count
+            .checked_add(district.ballot_count)
+            .ok_or(AuditError::TallyOverflow)?;
+    }
+    let national = TallyResult {
+        tally,
+        ballot_count,
+    };
+    if !national.validate_range(ballot_count as u64, 1) {
+        return Err(AuditError::TallyOverflow);
+    }
+    return Ok(NationalResult {
+        aggregate: *aggregate.get_product(),
+        tally,
+        ballot_count,
+    });
+}
+
+/// The public record of an election: the public key, every ballot with its proof, the aggregate
+/// ciphertext, the announced tally, and the proof of the tally. Nothing in the transcript is
+/// secret, so anyone holding it can audit the election without the secret key.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ElectionTranscript {
+    pk: PublicKey,
+    ballots: Vec<OpaqueResidue>,
+    ballot_proofs: Vec<BallotProof>,
+    aggregate: Option<OpaqueResidue>,
+    tally: Option<ResidueClass>,
+    tally_proof: Option<TallyProof>,
+}
+
+impl ElectionTranscript {
+    /// Start an empty transcript for an election under the given public key
+    pub fn new(pk: &PublicKey) -> Self {
+        return Self {
+            pk: pk.clone(),
+            ballots: vec![],
+            ballot_proofs: vec![],
+            aggregate: None,
+            tally: None,
+            tally_proof: None,
+        };
+    }
+
+    /// Record a cast ballot with the proof that it is a valid vote
+    pub fn record_ballot(&mut self, ballot: OpaqueResidue, proof: BallotProof) {
+        self.ballots.push(ballot);
+        self.ballot_proofs.push(proof);
+    }
+
+    /// Record the aggregate ciphertext, the announced tally, and the proof that the aggregate
+    /// decrypts to the tally
+    pub fn record_tally(
+        &mut self,
+        aggregate: OpaqueResidue,
+        tally: ResidueClass,
+        proof: TallyProof,
+    ) {
+        self.aggregate = Some(aggregate);
+        self.tally = Some(tally);
+        self.tally_proof = Some(proof);
+    }
+
+    pub fn get_pk(&self) -> &PublicKey {
+        return &self.pk;
+    }
+
+    pub fn get_ballots(&self) -> &[OpaqueResidue] {
+        return &self.ballots;
+    }
+
+    /// Re-check the entire election from the transcript alone:
+    /// 1. every ballot proof is about its ballot and shows that the ballot is a 0 or a 1
+    /// 2. the aggregate is the product of all ballots
+    /// 3. the tally proof shows that the aggregate decrypts to the announced tally
+    pub fn verify_all(&self) -> Result<TallyResult, AuditError> {
+        let (aggregate, tally, tally_proof) =
+            match (&self.aggregate, &self.tally, &self.tally_proof) {
+                (Some(aggregate), Some(tally), Some(proof)) => (aggregate, tally, proof),
+                _ => return Err(AuditError::MissingTally),
+            };
+
+        let classes = zero_or_one(self.pk.get_r());
+        for (i, (ballot, proof)) in self
+            .ballots
+            .iter()
+            .zip(self.ballot_proofs.iter())
+            .enumerate()
+        {
+            audit::verify_ballot(i, ballot, proof, &classes, &self.pk)?;
+        }
+
+        let mut product = Tally::new(&self.pk);
+        for ballot in self.ballots.iter() {
+            product.add(ballot);
+        }
+        if product.get_product() != aggregate {
+            return Err(AuditError::AggregateMismatch);
+        }
+
+        if !tally_proof.verify_public(aggregate, tally, &self.pk) {
+            return Err(AuditError::InvalidTallyProof);
+        }
+        return Ok(TallyResult {
+            tally: *tally,
+            ballot_count: self.ballots.len(),
+        });
+    }
+}
+
+/// Everything produced by a simulated election
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ElectionResult {
+    /// The key pair of the election authority
+    pub keypair: KeyPair,
+
+    /// The public record of the election
+    pub transcript: ElectionTranscript,
+
+    /// The decrypted sum of the votes
+    pub tally: ResidueClass,
+}
+
+/// Simulate an election from start to finish: generate the key pair, cast a random vote (0 or 1)
+/// for each voter with the proof of its validity, then tally and prove the tally. Every random
+/// choice is drawn from a ChaCha20 stream seeded with the given seed, so an election, including
+/// a failing one, can be replayed exactly from its seed.
+pub fn run_election(
+    seed: u64,
+    voters: usize,
+    ring_size: usize,
+    group_size: usize,
+) -> ElectionResult {
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+    let keypair = KeyPair::keygen_with_rng(ring_size, group_size, false, &mut rng);
+    let pk = keypair.get_pk();
+    let classes = zero_or_one(pk.get_r());
+    let mut transcript = ElectionTranscript::new(pk);
+    let mut product = Tally::new(pk);
+    for _ in 0..voters {
+        let vote = classes[(rng.next_u32() % 2) as usize];
+        let ballot = ClearResidue::random_with_rng(Some(vote.clone_residue()), pk, &mut rng);
+        let proof = BallotProof::from_statement_with_rng(&ballot, &classes, pk, &mut rng);
+        product.add(ballot.as_opaque());
+        transcript.record_ballot(ballot.into_opaque(), proof);
+    }
+
+    let aggregate = *product.get_product();
+    let (result, proof) = TallyProof::from_aggregate_with_rng(&aggregate, &keypair, &mut rng);
+    transcript.record_tally(aggregate, result, proof);
+
+    return ElectionResult {
+        keypair,
+        transcript,
+        tally: result,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arithmetics::RingModulus, proofs::tally, GROUPSIZE, RINGSIZE};
+
+    /// Feeding ballots one at a time should give the same result as tallying the whole set
+    #[test]
+    fn test_incremental_tally() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let ballots = [1u8, 0, 1, 1, 0]
+            .iter()
+            .map(|vote| {
+                let vote = DynResidue::new(&BigInt::from_u8(*vote), r);
+                return ClearResidue::random(Some(vote), keypair.get_pk()).clone_val();
+            })
+            .collect::<Vec<OpaqueResidue>>();
+
+        let mut tally = Tally::new(keypair.get_pk());
+        for ballot in ballots.iter() {
+            assert!(tally.add(ballot));
+        }
+        let incremental = tally.finalize(&keypair);
+
+        let product = ballots[1..]
+            .iter()
+            .fold(ballots[0].clone(), |acc, ballot| acc * ballot.clone());
+        let batch = ClearResidue::decompose(product.clone_residue(), &keypair);
+
+        assert_eq!(incremental.retrieve(), batch.get_rc().retrieve());
+        assert_eq!(incremental.retrieve(), BigInt::from_u8(3));
+    }
+
+    /// Run a small election with the given votes, recording everything in a transcript
+    fn record_election(keypair: &KeyPair, votes: &[u8]) -> ElectionTranscript {
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let mut transcript = ElectionTranscript::new(pk);
+        let mut tally = Tally::new(pk);
+        for vote in votes {
+            let vote = DynResidue::new(&BigInt::from_u8(*vote), pk.get_r().to_dyn_residue_params());
+            let ballot = ClearResidue::random(Some(vote), pk);
+            let proof = BallotProof::from_statement(&ballot, &classes, pk);
+            tally.add(ballot.get_val());
+            transcript.record_ballot(ballot.clone_val(), proof);
+        }
+
+        let aggregate = *tally.get_product();
+        let (result, proof) = TallyProof::from_aggregate(&aggregate, keypair);
+        transcript.record_tally(aggregate, result, proof);
+        return transcript;
+    }
+
+    #[test]
+    fn test_verify_transcript() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let transcript = record_election(&keypair, &[1, 0, 1]);
+        let result = transcript.verify_all().unwrap();
+        assert_eq!(result.tally.retrieve(), BigInt::from_u8(2));
+        assert_eq!(result.ballot_count, 3);
+    }
+
+    /// Dropping a ballot after the tally has been announced is caught by the audit
+    #[test]
+    fn test_verify_tampered_transcript() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let mut transcript = record_election(&keypair, &[1, 0, 1]);
+        transcript.ballots.pop();
+        transcript.ballot_proofs.pop();
+        assert_eq!(transcript.verify_all(), Err(AuditError::AggregateMismatch));
+
+        let transcript = ElectionTranscript::new(keypair.get_pk());
+        assert_eq!(transcript.verify_all(), Err(AuditError::MissingTally));
+    }
+
+    /// A transcript whose tally proof is forged for a made-up tally fails the audit
+    #[test]
+    fn test_verify_forged_transcript() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let mut transcript = record_election(&keypair, &[1, 0, 1]);
+        let aggregate = transcript.aggregate.unwrap();
+        let claimed = ResidueClass::from_u64(3, pk.get_r());
+        let statement = aggregate * pk.invert_y().pow(&claimed);
+        let empty = TallyProof::new(statement, vec![], vec![], vec![]);
+        transcript.record_tally(aggregate, claimed, empty);
+        assert_eq!(transcript.verify_all(), Err(AuditError::InvalidTallyProof));
+
+        let forged = tally::forge(&aggregate, &claimed, pk);
+        transcript.record_tally(aggregate, claimed, forged);
+        assert_eq!(transcript.verify_all(), Err(AuditError::InvalidTallyProof));
+    }
+
+    /// The same seed replays the same election: key pair, ballots, proofs, and tally
+    #[test]
+    fn test_replay_election() {
+        let first = run_election(847, 3, RINGSIZE, GROUPSIZE);
+        let second = run_election(847, 3, RINGSIZE, GROUPSIZE);
+        assert_eq!(first, second);
+        assert_eq!(first.transcript.verify_all().unwrap().tally, first.tally);
+
+        let other = run_election(848, 3, RINGSIZE, GROUPSIZE);
+        assert_ne!(first.transcript, other.transcript);
+    }
+
+    #[test]
+    fn test_spoiled_ballot() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let r = pk.get_r().to_dyn_residue_params();
+        let one = DynResidue::new(&BigInt::ONE, r);
+        let cast = ClearResidue::random(Some(one), pk);
+        let spoiled = ClearResidue::random(Some(one), pk);
+
+        let mut tally = Tally::new(pk);
+        assert!(tally.add_if_cast(cast.get_val(), false));
+        assert!(!tally.add_if_cast(spoiled.get_val(), true));
+        assert_eq!(tally.get_product(), cast.get_val());
+        assert_eq!(tally.finalize(&keypair).retrieve(), BigInt::ONE);
+
+        assert!(spoiled.verify_spoiled(pk));
+        let wrong_vote = ClearResidue::new(
+            spoiled.clone_val(),
+            ResidueClass::new(DynResidue::zero(r)),
+            spoiled.clone_witness(),
+            pk,
+        );
+        assert!(!wrong_vote.verify_spoiled(pk));
+    }
+
+    /// A replayed ballot is skipped, while a rerandomized copy is still counted
+    #[test]
+    fn test_tally_add_unique() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let one = DynResidue::new(&BigInt::ONE, pk.get_r().to_dyn_residue_params());
+        let ballot = ClearResidue::random(Some(one), pk).clone_val();
+
+        let mut tally = Tally::new(pk);
+        assert!(tally.add_unique(&ballot));
+        assert!(!tally.add_unique(&ballot));
+        assert_eq!(tally.clone().finalize(&keypair).retrieve(), BigInt::ONE);
+
+        let zero = ClearResidue::random(Some(DynResidue::zero(*one.params())), pk);
+        let rerandomized = ballot * zero.clone_val();
+        assert!(tally.add_unique(&rerandomized));
+        assert_eq!(tally.finalize(&keypair).retrieve(), BigInt::from_u8(2));
+    }
+
+    #[test]
+    fn test_tally_validate_range() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r();
+        let result = |tally: u64, ballot_count: usize| TallyResult {
+            tally: ResidueClass::from_u64(tally, r),
+            ballot_count,
+        };
+        assert!(result(3, 3).validate_range(3, 1));
+        assert!(result(0, 3).validate_range(3, 1));
+        assert!(!result(4, 3).validate_range(3, 1));
+        assert!(result(12, 3).validate_range(3, 5));
+        assert!(!result(16, 3).validate_range(3, 5));
+
+        // a tally that wrapped around (mod r) into a huge class is flagged
+        let wrapped = TallyResult {
+            tally: -ResidueClass::one(*r.params()),
+            ballot_count: 3,
+        };
+        assert!(!wrapped.validate_range(3, 1));
+
+        // the ring over 13 cannot tell 20 votes from 7
+        let small = RingModulus::from_hex("0d").unwrap();
+        let seven = TallyResult {
+            tally: ResidueClass::from_u64(20, &small),
+            ballot_count: 20,
+        };
+        assert_eq!(seven.tally.as_u64(), Some(7));
+        assert!(!seven.validate_range(20, 1));
+    }
+
+    /// Build the result of a district from its transcript
+    fn district_result(transcript: &ElectionTranscript) -> DistrictResult {
+        return DistrictResult {
+            aggregate: transcript.aggregate.unwrap(),
+            tally: transcript.tally.unwrap(),
+            proof: transcript.tally_proof.clone().unwrap(),
+            ballot_count: transcript.ballots.len(),
+        };
+    }
+
+    #[test]
+    fn test_combine_districts() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let north = record_election(&keypair, &[1, 0, 1]);
+        let south = record_election(&keypair, &[1, 1, 0, 1]);
+        let districts = [district_result(&north), district_result(&south)];
+
+        let national = combine_districts(&districts, pk).unwrap();
+        assert_eq!(national.tally.as_u64(), Some(5));
+        assert_eq!(national.ballot_count, 7);
+        assert_eq!(keypair.decrypt(&national.aggregate), national.tally);
+
+        // merging the district tallies gives the same aggregate
+        let mut merged = Tally::new(pk);
+        let mut other = Tally::new(pk);
+        north
+            .get_ballots()
+            .iter()
+            .for_each(|ballot| assert!(merged.add(ballot)));
+        south
+            .get_ballots()
+            .iter()
+            .for_each(|ballot| assert!(other.add(ballot)));
+        assert!(merged.merge(&other));
+        assert_eq!(merged.get_product(), &national.aggregate);
+
+        let mut forged = districts.clone();
+        forged[1].tally = ResidueClass::from_u64(2, pk.get_r());
+        assert_eq!(
+            combine_districts(&forged, pk),
+            Err(AuditError::InvalidDistrictProof(1))
+        );
+        // a district with a made-up tally and a forged proof, rather than a tampered honest one
+        for forged_proof in [
+            TallyProof::new(
+                districts[1].aggregate * pk.invert_y().pow(&forged[1].tally),
+                vec![],
+                vec![],
+                vec![],
+            ),
+            tally::forge(&districts[1].aggregate, &forged[1].tally, pk),
+        ] {
+            forged[1].proof = forged_proof;
+            assert_eq!(
+                combine_districts(&forged, pk),
+                Err(AuditError::InvalidDistrictProof(1))
+            );
+        }
+        let mut undercounted = districts.clone();
+        undercounted[0].ballot_count = 1;
+        assert_eq!(
+            combine_districts(&undercounted, pk),
+            Err(AuditError::DistrictOutOfRange(0))
+        );
+    }
+
+    #[test]
+    fn test_tally_rejects_invalid_ballot() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let invalid = OpaqueResidue::new(DynResidue::new(
+            &BigInt::ZERO,
+            keypair.get_pk().get_n().to_dyn_residue_params(),
+        ));
+        let mut tally = Tally::new(keypair.get_pk());
+        assert!(!tally.add(&invalid));
+        assert_eq!(tally.get_product().retrieve(), BigInt::ONE);
+    }
+}
@@ -1,14 +1,263 @@
 //! The key pairs
+#[cfg(feature = "std")]
+use crate::proofs::{
+    ballot::{self, BallotProof},
+    consonance::{ClearChallenge, GovernmentProof, OpaqueChallenge},
+};
 use crate::{
-    arithmetics::{self, GroupModulus, OpaqueResidue, RingModulus},
+    arithmetics::{
+        self, ClearResidue, GroupModulus, OpaqueResidue, ResidueClass, RingModulus, Witness,
+    },
     BigInt, LIMBS,
 };
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+#[cfg(feature = "alloc")]
+use crypto_bigint::Encoding;
 use crypto_bigint::{
     modular::runtime_mod::{DynResidue, DynResidueParams},
-    rand_core::OsRng,
-    CheckedAdd, CheckedMul, CheckedSub, NonZero, RandomMod,
+    rand_core::CryptoRngCore,
+    subtle::CtOption,
+    CheckedAdd, CheckedMul, CheckedSub, Integer, NonZero, RandomMod,
 };
 
+/// Errors that can occur when generating a key pair
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum KeyError {
+    /// The ring size is not a valid bit length for the prime r: it must be at least 2 and fit
+    /// in a BigInt
+    InvalidRingSize,
+
+    /// An intermediate value of the key generation (such as p, q, n, or phi) does not fit in a
+    /// BigInt
+    Overflow,
+
+    /// One of r, p, q supplied for a key import is not prime
+    NotPrime,
+
+    /// The supplied r and the primes p, q do not form a perfect consonance: r must divide phi,
+    /// and r and phi/r must be relatively prime
+    NotConsonant,
+
+    /// A component required to build the public key has not been supplied
+    MissingComponent,
+
+    /// y is not invertible under n, or it is an r-th residue and so cannot tell the residue
+    /// classes apart
+    InvalidY,
+
+    /// A ciphertext encrypted under the generated key did not decrypt to its residue class
+    SelfTestFailed,
+
+    /// The group size does not exceed the ring size by at least MIN_GROUP_MARGIN bits
+    InsufficientModulusSize,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRingSize => write!(f, "ring size is not a valid bit length for r"),
+            Self::Overflow => write!(f, "key parameters do not fit in {} bits", BigInt::BITS),
+            Self::NotPrime => write!(f, "r, p, and q must all be prime"),
+            Self::NotConsonant => write!(f, "r, p, and q do not form a perfect consonance"),
+            Self::MissingComponent => write!(f, "r, n, and y must all be supplied"),
+            Self::InvalidY => write!(f, "y is not an invertible non-residue"),
+            Self::SelfTestFailed => write!(f, "the key failed the encryption round-trip"),
+            Self::InsufficientModulusSize => write!(
+                f,
+                "group size must exceed ring size by at least {MIN_GROUP_MARGIN} bits"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for KeyError {}
+
+/// The least number of bits by which the group size must exceed the ring size. The factors p
+/// and q are dominated by the x term of group_size bits, so a smaller margin leaves p and q
+/// little more than multiples of r, and n correspondingly easy to factor.
+pub const MIN_GROUP_MARGIN: usize = 16;
+
+/// The version of the serialized form of PublicKey and KeyPair that this crate writes. A
+/// serialized key written under any other version is rejected instead of being misread.
+pub const CURRENT_KEY_FORMAT_VERSION: u8 = 1;
+
+/// The magic bytes that a serialized public key starts with
+const PUBLIC_KEY_MAGIC: &[u8; 4] = b"BNPK";
+
+/// The magic bytes that a serialized key pair starts with
+const KEY_PAIR_MAGIC: &[u8; 4] = b"BNKP";
+
+/// A serialized key pair records whether the prime factors of n follow
+const WITHOUT_FACTORS_TAG: u8 = 0;
+const WITH_FACTORS_TAG: u8 = 1;
+
+/// Errors that can occur when deserializing a key
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum FormatError {
+    /// The input does not start with the magic bytes of the expected kind of key
+    BadMagic,
+
+    /// The input was written under a format version that this crate does not read
+    UnsupportedVersion(u8),
+
+    /// The input ended before the key was complete
+    Truncated,
+
+    /// The input continues after the end of the key
+    TrailingBytes,
+
+    /// The tag byte that says whether the factors of n follow is neither 0 nor 1
+    InvalidTag(u8),
+
+    /// The prime factors do not multiply to n
+    InconsistentFactors,
+
+    /// The components were read but do not form a valid key
+    InvalidKey(KeyError),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "the input is not a serialized key of the expected kind"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "key format version {version} is not supported (expected {CURRENT_KEY_FORMAT_VERSION})"
+            ),
+            Self::Truncated => write!(f, "the key is truncated"),
+            Self::TrailingBytes => write!(f, "unexpected bytes after the end of the key"),
+            Self::InvalidTag(tag) => write!(f, "invalid factors tag {tag}"),
+            Self::InconsistentFactors => write!(f, "p * q is not the group modulus"),
+            Self::InvalidKey(err) => write!(f, "invalid key: {err}"),
+        }
+    }
+}
+
+impl core::error::Error for FormatError {}
+
+/// Reads the fields of a serialized key front to back
+struct KeyReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> KeyReader<'a> {
+    /// Check the magic bytes and the format version, and position the reader after them
+    fn new(bytes: &'a [u8], magic: &[u8; 4]) -> Result<Self, FormatError> {
+        let mut reader = Self { bytes };
+        if reader.take(magic.len())? != magic {
+            return Err(FormatError::BadMagic);
+        }
+        let version = reader.take(1)?[0];
+        if version != CURRENT_KEY_FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        return Ok(reader);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        return Ok(head);
+    }
+
+    fn read_uint(&mut self) -> Result<BigInt, FormatError> {
+        return Ok(BigInt::from_be_slice(self.take(BigInt::BYTES)?));
+    }
+
+    fn finish(self) -> Result<(), FormatError> {
+        if !self.bytes.is_empty() {
+            return Err(FormatError::TrailingBytes);
+        }
+        return Ok(());
+    }
+}
+
+/// Assemble the public key from the deserialized r, n, and y, checking them with phi if it is
+/// available. The moduli must be odd, and y must be reduced (mod n).
+fn build_public_key(
+    r: BigInt,
+    n: BigInt,
+    y: BigInt,
+    phi: Option<BigInt>,
+) -> Result<PublicKey, FormatError> {
+    if r <= BigInt::ONE || !bool::from(r.is_odd()) || !bool::from(n.is_odd()) {
+        return Err(FormatError::InvalidKey(KeyError::NotPrime));
+    }
+    if y >= n {
+        return Err(FormatError::InvalidKey(KeyError::InvalidY));
+    }
+    let mut builder = PublicKeyBuilder::new()
+        .ring_modulus(RingModulus::from_uint(&r))
+        .group_modulus(GroupModulus::from_uint(&n))
+        .y(y);
+    if let Some(phi) = phi {
+        builder = builder.phi(phi);
+    }
+    return builder.build().map_err(FormatError::InvalidKey);
+}
+
+/// Convert the result of a checked operation, which fails on overflow
+fn checked(value: CtOption<BigInt>) -> Result<BigInt, KeyError> {
+    return Option::from(value).ok_or(KeyError::Overflow);
+}
+
+/// Check that externally supplied integers (r, p, q) can carry a Benaloh key without building
+/// the key pair: r is prime, r divides p - 1 exactly once, and r does not divide q - 1. Under
+/// these conditions r divides phi = (p - 1)(q - 1) and is relatively prime to phi/r, which is
+/// what KeyPair::check_perfect_consonance checks. The primality of p and q is not checked.
+#[cfg(feature = "std")]
+pub fn is_perfectly_consonant(r: &BigInt, p: &BigInt, q: &BigInt) -> bool {
+    let (Some(r), Some(p_minus_one), Some(q_minus_one)) = (
+        Option::<NonZero<BigInt>>::from(NonZero::new(*r)),
+        Option::<BigInt>::from(p.checked_sub(&BigInt::ONE)),
+        Option::<BigInt>::from(q.checked_sub(&BigInt::ONE)),
+    ) else {
+        return false;
+    };
+    if !crypto_primes::is_prime_with_rng(&mut OsRng, &r) {
+        return false;
+    }
+    let divisible = p_minus_one % r == BigInt::ZERO;
+    let indivisible = (p_minus_one / r) % r != BigInt::ZERO;
+    return divisible && indivisible && q_minus_one % r != BigInt::ZERO;
+}
+
+/// Errors that can occur when decrypting a ciphertext
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum DecryptError {
+    /// The decrypted residue class is not one of the expected classes, which indicates a
+    /// malformed ciphertext
+    ClassOutOfRange,
+
+    /// The ciphertext is not an element of the multiplicative group Z/n, or it is not in any
+    /// residue class under y
+    MalformedCiphertext,
+
+    /// The decomposition found for the ciphertext does not recompose to it
+    InconsistentDecomposition,
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClassOutOfRange => write!(f, "decrypted residue class is out of range"),
+            Self::MalformedCiphertext => write!(f, "ciphertext is not in any residue class"),
+            Self::InconsistentDecomposition => {
+                write!(f, "decomposition does not recompose to the ciphertext")
+            }
+        }
+    }
+}
+
+impl core::error::Error for DecryptError {}
+
 /// The public key includes the ring size r, and group modulus n, and the residue class
 /// discriminator y. In this implementation, a public key is always a perfect consonance, meaning
 /// 1. r divides phi
@@ -25,12 +274,16 @@ pub struct PublicKey {
     /// naked BigInt
     /// TODO: replace this with OpaqueResidue
     y: OpaqueResidue,
+
+    /// The inverse of y, computed once when the key is constructed
+    y_inv: OpaqueResidue,
 }
 
 impl PublicKey {
     /// Instantiate an instance with no check
     pub fn new(r: RingModulus, n: GroupModulus, y: OpaqueResidue) -> Self {
-        return Self { r, n, y };
+        let y_inv = y.invert();
+        return Self { r, n, y, y_inv };
     }
 
     pub fn get_r(&self) -> &RingModulus {
@@ -46,14 +299,201 @@ impl PublicKey {
     }
 
     /// Return the multiplicative inverse of y. This inverse should always exist y is sampled from
-    /// the multiplicative group
-    pub fn invert_y(&self) -> OpaqueResidue {
-        return self.y.invert();
+    /// the multiplicative group. It is computed in new, so this does not invert y again.
+    pub fn invert_y(&self) -> &OpaqueResidue {
+        return &self.y_inv;
     }
 
     /// Sample a random element from the multiplicative group Z/n
+    #[cfg(feature = "std")]
     pub fn sample_invertible(&self) -> DynResidue<LIMBS> {
-        return arithmetics::sample_invertible(self.get_n().to_dyn_residue_params());
+        return arithmetics::sample_invertible(*self.get_n().params());
+    }
+
+    /// Sample a random element from the multiplicative group Z/n using the supplied source of
+    /// randomness
+    pub fn sample_invertible_with_rng(&self, rng: &mut impl CryptoRngCore) -> DynResidue<LIMBS> {
+        return arithmetics::sample_invertible_with_rng(*self.get_n().params(), rng);
+    }
+
+    /// Sample a random element from the multiplicative group Z/n, or return None if none of the
+    /// first max_tries draws is invertible
+    #[cfg(feature = "std")]
+    pub fn sample_invertible_bounded(&self, max_tries: usize) -> Option<DynResidue<LIMBS>> {
+        return self.sample_invertible_bounded_with_rng(max_tries, &mut OsRng);
+    }
+
+    /// Sample a random element from the multiplicative group Z/n as sample_invertible_bounded
+    /// does, using the supplied source of randomness
+    pub fn sample_invertible_bounded_with_rng(
+        &self,
+        max_tries: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> Option<DynResidue<LIMBS>> {
+        return arithmetics::sample_invertible_bounded_with_rng(
+            *self.get_n().params(),
+            max_tries,
+            rng,
+        );
+    }
+
+    /// The canonical representative y^c of the residue class c, i.e. the ciphertext of c with
+    /// witness 1. This is deterministic and gives away the class, so it is a reference point for
+    /// checking claimed decryptions and not a way to encrypt
+    pub fn encode_class(&self, c: &ResidueClass) -> OpaqueResidue {
+        return self.y.pow(c);
+    }
+
+    /// Encrypt a yes/no vote as 1 or 0, together with the proof that the ciphertext is in one
+    /// of the two classes
+    #[cfg(feature = "std")]
+    pub fn encrypt_bit(&self, bit: bool) -> (OpaqueResidue, BallotProof) {
+        let class = DynResidue::new(&BigInt::from_u8(bit as u8), *self.get_r().params());
+        let ballot = ClearResidue::random(Some(class), self);
+        let proof = BallotProof::from_statement(&ballot, &ballot::zero_or_one(self.get_r()), self);
+        return (ballot.into_opaque(), proof);
+    }
+
+    /// A valid ciphertext must be an element of the multiplicative group Z/n. A ciphertext that
+    /// shares a factor with n cannot be decrypted and would corrupt any product it is part of.
+    pub fn is_valid_ciphertext(&self, ct: &OpaqueResidue) -> bool {
+        return self.get_n().contains(ct.get_residue());
+    }
+
+    /// Check that y is genuinely a non-residue: y must be invertible and y^(phi/r) != 1 (mod n).
+    /// This is the invariant that key generation maintains when sampling y, but a hand-constructed
+    /// key (e.g. with y = 1) can violate it, in which case every ciphertext collapses into the
+    /// same residue class. Checking the invariant requires phi, hence the key pair.
+    pub fn is_valid_y(&self, keypair: &KeyPair) -> bool {
+        if !self.y.is_invertible() {
+            return false;
+        }
+        return self.y.get_residue().pow(&keypair.phi_over_r()).retrieve() != BigInt::ONE;
+    }
+
+    /// Challenge the holder of the secret key to identify the residue classes of random
+    /// ciphertexts, one per round, and return the fraction of rounds that the responder passed.
+    /// If (r, n, y) is not a perfect consonance, each round is passed with probability at most
+    /// 1/r, so a responder that passes all of k rounds is honest except with probability
+    /// (1/r) ** k. Running no rounds gives no confidence.
+    #[cfg(feature = "std")]
+    pub fn consonance_confidence(
+        &self,
+        responder: &impl Fn(&OpaqueChallenge) -> GovernmentProof,
+        rounds: usize,
+    ) -> f64 {
+        if rounds == 0 {
+            return 0.0;
+        }
+        let passed = (0..rounds)
+            .filter(|_| {
+                let challenge = ClearChallenge::generate(self, 1);
+                return challenge.verify_gov_proof(&responder(&challenge.obscure()));
+            })
+            .count();
+        return passed as f64 / rounds as f64;
+    }
+
+    /// Serialize the public key. The layout is the magic bytes "BNPK", the format version byte,
+    /// then r, n, and y, each as a big-endian integer of LIMBS words
+    #[cfg(feature = "alloc")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PUBLIC_KEY_MAGIC.len() + 1 + 3 * BigInt::BYTES);
+        bytes.extend_from_slice(PUBLIC_KEY_MAGIC);
+        bytes.push(CURRENT_KEY_FORMAT_VERSION);
+        self.write_components(&mut bytes);
+        return bytes;
+    }
+
+    /// The components r, n, and y as written by to_bytes, without the magic bytes and the
+    /// version. Proofs hash these into their challenges to bind them to the key.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn component_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(3 * BigInt::BYTES);
+        self.write_components(&mut bytes);
+        return bytes;
+    }
+
+    #[cfg(feature = "alloc")]
+    fn write_components(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.get_r().to_uint().to_be_bytes());
+        bytes.extend_from_slice(&self.get_n().to_uint().to_be_bytes());
+        bytes.extend_from_slice(&self.get_y().retrieve().to_be_bytes());
+    }
+
+    /// Deserialize a public key written by to_bytes. The components are checked the same way
+    /// PublicKeyBuilder checks them without phi.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = KeyReader::new(bytes, PUBLIC_KEY_MAGIC)?;
+        let (r, n, y) = (
+            reader.read_uint()?,
+            reader.read_uint()?,
+            reader.read_uint()?,
+        );
+        reader.finish()?;
+        return build_public_key(r, n, y, None);
+    }
+}
+
+/// Assemble a public key from components that are loaded separately, e.g. from different fields
+/// of a configuration file. Parsing each component is up to the caller; the builder checks that
+/// they fit together.
+#[derive(Debug, Default, Clone)]
+pub struct PublicKeyBuilder {
+    r: Option<RingModulus>,
+    n: Option<GroupModulus>,
+    y: Option<BigInt>,
+    phi: Option<BigInt>,
+}
+
+impl PublicKeyBuilder {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn ring_modulus(mut self, r: RingModulus) -> Self {
+        self.r = Some(r);
+        return self;
+    }
+
+    pub fn group_modulus(mut self, n: GroupModulus) -> Self {
+        self.n = Some(n);
+        return self;
+    }
+
+    /// Set y as an integer; it is reduced under the group modulus when the key is built
+    pub fn y(mut self, y: BigInt) -> Self {
+        self.y = Some(y);
+        return self;
+    }
+
+    /// Supply phi so that build can also check that y is a non-residue and that r and phi form
+    /// a perfect consonance. Without phi, only the invertibility of y can be checked.
+    pub fn phi(mut self, phi: BigInt) -> Self {
+        self.phi = Some(phi);
+        return self;
+    }
+
+    /// Check the components and assemble the public key
+    pub fn build(&self) -> Result<PublicKey, KeyError> {
+        let (r, n, y) = match (&self.r, &self.n, &self.y) {
+            (Some(r), Some(n), Some(y)) => (r, n, y),
+            _ => return Err(KeyError::MissingComponent),
+        };
+        let y = DynResidue::new(y, *n.params());
+        if !n.contains(&y) {
+            return Err(KeyError::InvalidY);
+        }
+        let pk = PublicKey::new(*r, *n, OpaqueResidue::new(y));
+        if let Some(phi) = &self.phi {
+            if !KeyPair::perfectly_consonant(r, phi) {
+                return Err(KeyError::NotConsonant);
+            }
+            if !pk.is_valid_y(&KeyPair::new(pk, SecretKey::new(*phi))) {
+                return Err(KeyError::InvalidY);
+            }
+        }
+        return Ok(pk);
     }
 }
 
@@ -77,11 +517,30 @@ impl SecretKey {
 pub struct KeyPair {
     pk: PublicKey,
     sk: SecretKey,
+
+    /// The prime factors (p, q) of n, if they are retained. With the factors, decryption can
+    /// split the exponentiations (mod n) into smaller exponentiations (mod p) and (mod q)
+    factors: Option<(BigInt, BigInt)>,
 }
 
 impl KeyPair {
     pub fn new(pk: PublicKey, sk: SecretKey) -> Self {
-        Self { pk, sk }
+        Self {
+            pk,
+            sk,
+            factors: None,
+        }
+    }
+
+    /// Retain the prime factors of n so that decryption can use the Chinese remainder theorem.
+    /// Panics if p * q is not the group modulus n.
+    pub fn with_crt(mut self, p: BigInt, q: BigInt) -> Self {
+        let n: Option<BigInt> = p.checked_mul(&q).into();
+        if n.as_ref() != Some(self.get_pk().get_n().modulus()) {
+            panic!("p * q is not the group modulus");
+        }
+        self.factors = Some((p, q));
+        return self;
     }
 
     pub fn get_pk(&self) -> &PublicKey {
@@ -92,78 +551,114 @@ impl KeyPair {
         &self.sk
     }
 
+    /// Return the prime factors (p, q) of n, if they are retained
+    pub fn get_factors(&self) -> Option<&(BigInt, BigInt)> {
+        return self.factors.as_ref();
+    }
+
     /// generate p according to the arithmetic sequence:
     /// p = r * r * x + b * r + 1
-    fn generate_p(r: &BigInt, xbound: BigInt, b: BigInt, safe: bool) -> BigInt {
-        let x = BigInt::random_mod(&mut OsRng, &NonZero::new(xbound).unwrap());
-        let rrx = r.checked_mul(&r).unwrap().checked_mul(&x).unwrap();
-        let rb = r.checked_mul(&b).unwrap();
-        let mut p = rrx
-            .checked_add(&rb)
-            .unwrap()
-            .checked_add(&BigInt::ONE)
-            .unwrap();
-        let mut ready = {
-            if safe {
-                crypto_primes::is_safe_prime(&p)
-            } else {
-                crypto_primes::is_prime(&p)
+    /// where x is sampled from [xmin, xbound)
+    fn generate_p(
+        r: &BigInt,
+        xmin: BigInt,
+        xbound: BigInt,
+        b: BigInt,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<BigInt, KeyError> {
+        loop {
+            let x = Self::sample_x(xmin, xbound, rng);
+            let p = Self::p_candidate(r, &x, &b)?;
+            if Self::is_ready(&p, safe, rng) {
+                return Ok(p);
             }
-        };
-        while !ready {
-            let x = BigInt::random_mod(&mut OsRng, &NonZero::new(xbound).unwrap());
-            let rrx = r.checked_mul(&r).unwrap().checked_mul(&x).unwrap();
-            let rb = r.checked_mul(&b).unwrap();
-            p = rrx
-                .checked_add(&rb)
-                .unwrap()
-                .checked_add(&BigInt::ONE)
-                .unwrap();
-            ready = {
-                if safe {
-                    crypto_primes::is_safe_prime(&p)
-                } else {
-                    crypto_primes::is_prime(&p)
-                }
-            };
         }
-        return p;
+    }
+
+    /// The term of the arithmetic sequence of p at x
+    fn p_candidate(r: &BigInt, x: &BigInt, b: &BigInt) -> Result<BigInt, KeyError> {
+        let rrx = checked(checked(r.checked_mul(r))?.checked_mul(x))?;
+        let rb = checked(r.checked_mul(b))?;
+        return checked(checked(rrx.checked_add(&rb))?.checked_add(&BigInt::ONE));
     }
 
     /// Generate q according to the arithmetic sequence:
     /// q = r * x + b
-    fn generate_q(r: &BigInt, xbound: BigInt, b: BigInt, safe: bool) -> BigInt {
-        let x = BigInt::random_mod(&mut OsRng, &NonZero::new(xbound).unwrap());
-        let mut q = r.checked_mul(&x).unwrap().checked_add(&b).unwrap();
-        let mut ready = {
-            if safe {
-                crypto_primes::is_safe_prime(&q)
-            } else {
-                crypto_primes::is_prime(&q)
+    /// where x is sampled from [xmin, xbound)
+    fn generate_q(
+        r: &BigInt,
+        xmin: BigInt,
+        xbound: BigInt,
+        b: BigInt,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<BigInt, KeyError> {
+        loop {
+            let x = Self::sample_x(xmin, xbound, rng);
+            let q = Self::q_candidate(r, &x, &b)?;
+            if Self::is_ready(&q, safe, rng) {
+                return Ok(q);
             }
-        };
-        while !ready {
-            let x = BigInt::random_mod(&mut OsRng, &NonZero::new(xbound).unwrap());
-            q = r.checked_mul(&x).unwrap().checked_add(&b).unwrap();
-            ready = {
-                if safe {
-                    crypto_primes::is_safe_prime(&q)
-                } else {
-                    crypto_primes::is_prime(&q)
-                }
-            };
         }
-        return q;
+    }
+
+    /// The term of the arithmetic sequence of q at x
+    fn q_candidate(r: &BigInt, x: &BigInt, b: &BigInt) -> Result<BigInt, KeyError> {
+        return checked(checked(r.checked_mul(x))?.checked_add(b));
+    }
+
+    /// Search for a prime in an arithmetic sequence by testing several candidates in parallel,
+    /// taking the first prime found. Each candidate is sampled independently and uniformly, the
+    /// same as in the sequential search, so the prime that comes out has the same distribution.
+    #[cfg(feature = "parallel")]
+    fn race_prime(
+        candidate: impl Fn(&BigInt) -> Result<BigInt, KeyError> + Sync,
+        xmin: BigInt,
+        xbound: BigInt,
+        safe: bool,
+    ) -> Result<BigInt, KeyError> {
+        use rayon::iter::{self, ParallelIterator};
+
+        return iter::repeat(())
+            .map(|_| candidate(&Self::sample_x(xmin, xbound, &mut OsRng)))
+            .find_any(|result| match result {
+                Ok(candidate) => Self::is_ready(candidate, safe, &mut OsRng),
+                Err(_) => true,
+            })
+            .expect("the stream of candidates is endless");
+    }
+
+    /// Sample the dominant term of the arithmetic sequence uniformly from [xmin, xbound), which
+    /// the callers guarantee to be non-empty
+    fn sample_x(xmin: BigInt, xbound: BigInt, rng: &mut impl CryptoRngCore) -> BigInt {
+        let width = NonZero::new(xbound.wrapping_sub(&xmin)).unwrap();
+        return xmin.wrapping_add(&BigInt::random_mod(rng, &width));
+    }
+
+    /// Check that a candidate is a (safe) prime. The primality test is randomized, so the source
+    /// of randomness is threaded through for reproducible key generation
+    fn is_ready(candidate: &BigInt, safe: bool, rng: &mut impl CryptoRngCore) -> bool {
+        if safe {
+            return crypto_primes::is_safe_prime_with_rng(rng, candidate);
+        }
+        return crypto_primes::is_prime_with_rng(rng, candidate);
     }
 
     /// Sample a non-residue. A non-residue is an invertible element such that
-    /// y^{phi/r} != 1 (mod n)
-    fn sample_nonresidue(modulus: &GroupModulus, r: &BigInt, phi: &BigInt) -> OpaqueResidue {
-        let quotient = phi.checked_div(r).unwrap();
-        let modulus = modulus.to_dyn_residue_params();
+    /// y^{phi/r} != 1 (mod n). The source of randomness is supplied so that y is reproducible
+    /// under a seeded RNG
+    fn sample_nonresidue_with_rng(
+        modulus: &GroupModulus,
+        r: &RingModulus,
+        phi: &BigInt,
+        rng: &mut impl CryptoRngCore,
+    ) -> OpaqueResidue {
+        let quotient = phi / r.as_nonzero();
+        let modulus = *modulus.params();
 
         loop {
-            let y = arithmetics::sample_invertible(modulus);
+            let y = arithmetics::sample_invertible_with_rng(modulus, rng);
             if y.pow(&quotient).retrieve() != BigInt::ONE {
                 return OpaqueResidue::new(y);
             }
@@ -175,10 +670,14 @@ impl KeyPair {
     /// 1. r divides phi
     /// 2. r and phi/r are relatively prime
     pub fn check_perfect_consonance(&self) -> bool {
-        let r = self.get_pk().get_r().modulus().clone();
-        let phi = self.get_sk().get_phi();
-        let divisible = phi % NonZero::new(r).unwrap() == BigInt::ZERO;
-        let indivisible = (phi.checked_div(&r).unwrap()) % NonZero::new(r).unwrap() != BigInt::ZERO;
+        return Self::perfectly_consonant(self.get_pk().get_r(), self.get_sk().get_phi());
+    }
+
+    /// The two conditions of perfect consonance on r and phi
+    fn perfectly_consonant(r: &RingModulus, phi: &BigInt) -> bool {
+        let r = r.as_nonzero();
+        let divisible = phi % r == BigInt::ZERO;
+        let indivisible = (phi / r) % r != BigInt::ZERO;
         return divisible && indivisible;
     }
 
@@ -193,34 +692,379 @@ impl KeyPair {
     /// than the ring size (duh).
     ///
     /// reference: 2 ** 33 ~= 8.58 billion, 2 ** 29 >= 300 million
+    ///
+    /// The group size must exceed the ring size by at least MIN_GROUP_MARGIN bits.
+    ///
+    /// Panics if the parameters are invalid; see try_keygen for the fallible version
+    #[cfg(feature = "std")]
     pub fn keygen(ring_size: usize, group_size: usize, safe: bool) -> Self {
-        let r: BigInt = crypto_primes::generate_prime(Some(ring_size));
+        return Self::keygen_with_rng(ring_size, group_size, safe, &mut OsRng);
+    }
+
+    /// Generate the key pair using the supplied source of randomness. With a seeded RNG, the
+    /// entire key pair (including the non-residue y) is reproducible, which is useful for
+    /// generating test vectors.
+    pub fn keygen_with_rng(
+        ring_size: usize,
+        group_size: usize,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        return Self::try_keygen_with_rng(ring_size, group_size, safe, rng)
+            .expect("key generation failed");
+    }
+
+    /// Generate the key pair, or report why the parameters cannot produce one
+    #[cfg(feature = "std")]
+    pub fn try_keygen(ring_size: usize, group_size: usize, safe: bool) -> Result<Self, KeyError> {
+        return Self::try_keygen_with_rng(ring_size, group_size, safe, &mut OsRng);
+    }
+
+    /// Generate the key pair using the supplied source of randomness, or report why the
+    /// parameters cannot produce one
+    pub fn try_keygen_with_rng(
+        ring_size: usize,
+        group_size: usize,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, KeyError> {
+        let (r, xbound, b) = Self::sequence_params(ring_size, group_size, rng)?;
+        let q = Self::generate_q(r.modulus(), BigInt::ZERO, xbound, b, safe, rng)?;
+        let p = Self::generate_p(r.modulus(), BigInt::ZERO, xbound, b, safe, rng)?;
+
+        return Self::from_parts(r, p, q, rng);
+    }
+
+    /// Generate the key pair as try_keygen does, then check that a random non-zero class
+    /// survives an encryption round-trip under it before handing it out
+    #[cfg(feature = "std")]
+    pub fn keygen_verified(
+        ring_size: usize,
+        group_size: usize,
+        safe: bool,
+    ) -> Result<Self, KeyError> {
+        let keypair = Self::try_keygen(ring_size, group_size, safe)?;
+        keypair.self_test_with_rng(&mut OsRng)?;
+        return Ok(keypair);
+    }
+
+    /// Encrypt a random non-zero residue class under the public key and decrypt it with the
+    /// secret key. A key pair whose y is an r-th residue, or whose phi does not match n, fails
+    /// with SelfTestFailed.
+    pub fn self_test_with_rng(&self, rng: &mut impl CryptoRngCore) -> Result<(), KeyError> {
+        let pk = self.get_pk();
+        let class = pk.get_r().sample_nonzero_with_rng(rng);
+        let ct = ClearResidue::random_with_rng(Some(class), pk, rng);
+        return match self.try_decrypt(ct.get_val()) {
+            Ok(decrypted) if decrypted.clone_residue() == class => Ok(()),
+            _ => Err(KeyError::SelfTestFailed),
+        };
+    }
+
+    /// Generate the key pair over the given ring modulus r instead of a fresh random prime, e.g.
+    /// the smallest prime above the number of voters. r must be an odd prime.
+    #[cfg(feature = "std")]
+    pub fn keygen_with_r(r: BigInt, group_size: usize, safe: bool) -> Result<Self, KeyError> {
+        return Self::keygen_with_r_with_rng(r, group_size, safe, &mut OsRng);
+    }
+
+    /// Generate the key pair over the given ring modulus r using the supplied source of
+    /// randomness
+    pub fn keygen_with_r_with_rng(
+        r: BigInt,
+        group_size: usize,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, KeyError> {
+        if r.bits() < 2 || r == BigInt::from_u8(2) {
+            return Err(KeyError::InvalidRingSize);
+        }
+        if !crypto_primes::is_prime_with_rng(rng, &r) {
+            return Err(KeyError::NotPrime);
+        }
         let r = RingModulus::new(DynResidueParams::new(&r));
-        let xbound = DynResidue::new(&BigInt::from_u8(2), DynResidueParams::new(&BigInt::MAX))
-            .pow(&BigInt::from_u64(group_size as u64))
-            .retrieve(); // x is the dominant term in the arithmetic sequence
-                         // Generate the non-zero remainder in the arithmetic sequence
+        let (xbound, b) = Self::sequence_terms(&r, group_size, rng)?;
+        let q = Self::generate_q(r.modulus(), BigInt::ZERO, xbound, b, safe, rng)?;
+        let p = Self::generate_p(r.modulus(), BigInt::ZERO, xbound, b, safe, rng)?;
 
-        // Generate the remainder term "b"
-        let mut b = r.sample();
-        while b.retrieve() == BigInt::ZERO {
-            b = r.sample();
+        return Self::from_parts(r, p, q, rng);
+    }
+
+    /// Generate the key pair, searching for p and q concurrently, each with several candidates
+    /// tested in parallel. The key pair has the same distribution as that of keygen.
+    ///
+    /// Panics if the parameters are invalid; see try_keygen_parallel for the fallible version
+    #[cfg(feature = "parallel")]
+    pub fn keygen_parallel(ring_size: usize, group_size: usize, safe: bool) -> Self {
+        return Self::try_keygen_parallel(ring_size, group_size, safe)
+            .expect("key generation failed");
+    }
+
+    /// Generate the key pair the same way as keygen_parallel, or report why the parameters
+    /// cannot produce one
+    #[cfg(feature = "parallel")]
+    pub fn try_keygen_parallel(
+        ring_size: usize,
+        group_size: usize,
+        safe: bool,
+    ) -> Result<Self, KeyError> {
+        let (r, xbound, b) = Self::sequence_params(ring_size, group_size, &mut OsRng)?;
+        let modulus = r.modulus();
+        let (q, p) = rayon::join(
+            || {
+                Self::race_prime(
+                    |x| Self::q_candidate(modulus, x, &b),
+                    BigInt::ZERO,
+                    xbound,
+                    safe,
+                )
+            },
+            || {
+                Self::race_prime(
+                    |x| Self::p_candidate(modulus, x, &b),
+                    BigInt::ZERO,
+                    xbound,
+                    safe,
+                )
+            },
+        );
+
+        return Self::from_parts(r, p?, q?, &mut OsRng);
+    }
+
+    /// Validate the sizes, then generate r, the bound on the dominant term x, and the remainder
+    /// term b of the arithmetic sequences of p and q
+    fn sequence_params(
+        ring_size: usize,
+        group_size: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<(RingModulus, BigInt, BigInt), KeyError> {
+        if !(2..=BigInt::BITS).contains(&ring_size) {
+            return Err(KeyError::InvalidRingSize);
         }
-        let b = b.retrieve();
+        let r: BigInt = crypto_primes::generate_prime_with_rng(rng, Some(ring_size));
+        let r = RingModulus::new(DynResidueParams::new(&r));
+        let (xbound, b) = Self::sequence_terms(&r, group_size, rng)?;
 
-        let q = Self::generate_q(r.modulus(), xbound, b, safe);
-        let p = Self::generate_p(r.modulus(), xbound, b, safe);
+        return Ok((r, xbound, b));
+    }
 
-        // Compute n and phi
-        let n = GroupModulus::from_uint(&p.checked_mul(&q).unwrap());
-        let phi = p
-            .checked_sub(&BigInt::ONE)
-            .unwrap()
-            .checked_mul(&q.checked_sub(&BigInt::ONE).unwrap())
-            .unwrap();
-        let y = Self::sample_nonresidue(&n, r.modulus(), &phi);
+    /// Generate the bound on the dominant term x and the remainder term b of the arithmetic
+    /// sequences of p and q over the ring modulus r
+    fn sequence_terms(
+        r: &RingModulus,
+        group_size: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<(BigInt, BigInt), KeyError> {
+        if group_size >= BigInt::BITS {
+            return Err(KeyError::Overflow);
+        }
+        if group_size < r.modulus().bits() + MIN_GROUP_MARGIN {
+            return Err(KeyError::InsufficientModulusSize);
+        }
+        // x is the dominant term in the arithmetic sequence
+        let xbound = BigInt::ONE.shl_vartime(group_size);
+        // the remainder term "b" in the arithmetic sequences must be non-zero
+        let b = r.sample_nonzero_with_rng(rng).retrieve();
 
-        return Self::new(PublicKey::new(r, n, y), SecretKey::new(phi));
+        return Ok((xbound, b));
+    }
+
+    /// Generate a key pair whose modulus n = pq is either n_bits or (n_bits + 1) bits long. This
+    /// is useful for matching a fixed LIMBS or a mandated security parameter, which keygen only
+    /// loosely controls.
+    ///
+    /// Panics if the parameters cannot produce a key pair; see try_keygen_exact_modulus
+    #[cfg(feature = "std")]
+    pub fn keygen_exact_modulus(ring_size: usize, n_bits: usize, safe: bool) -> Self {
+        return Self::keygen_exact_modulus_with_rng(ring_size, n_bits, safe, &mut OsRng);
+    }
+
+    /// Generate a key pair whose modulus is n_bits or (n_bits + 1) bits long, using the supplied
+    /// source of randomness
+    pub fn keygen_exact_modulus_with_rng(
+        ring_size: usize,
+        n_bits: usize,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        return Self::try_keygen_exact_modulus_with_rng(ring_size, n_bits, safe, rng)
+            .expect("key generation failed");
+    }
+
+    /// Generate a key pair whose modulus is n_bits or (n_bits + 1) bits long, or report why the
+    /// parameters cannot produce one
+    #[cfg(feature = "std")]
+    pub fn try_keygen_exact_modulus(
+        ring_size: usize,
+        n_bits: usize,
+        safe: bool,
+    ) -> Result<Self, KeyError> {
+        return Self::try_keygen_exact_modulus_with_rng(ring_size, n_bits, safe, &mut OsRng);
+    }
+
+    /// Generate a key pair whose modulus is n_bits or (n_bits + 1) bits long using the supplied
+    /// source of randomness, or report why the parameters cannot produce one. n_bits must leave
+    /// both x terms at least MIN_GROUP_MARGIN bits longer than the ring size, which takes at
+    /// least (5 * ring_size + 2 * MIN_GROUP_MARGIN - 1) bits, and n must fit in a BigInt.
+    ///
+    /// With r being ring_size bits and x being sampled with its top bit set, q = r * x + b has
+    /// about (ring_size + q_size) bits and p = (r ** 2) * x + br + 1 has about
+    /// (2 * ring_size + p_size) bits. The sizes of x are chosen so that the largest possible n
+    /// has (n_bits + 1) bits; a product that comes out short is discarded and b, p, q
+    /// regenerated. As in keygen, each x term is at least MIN_GROUP_MARGIN bits longer than r,
+    /// so there are plenty of primes in both sequences whatever b is drawn.
+    pub fn try_keygen_exact_modulus_with_rng(
+        ring_size: usize,
+        n_bits: usize,
+        safe: bool,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, KeyError> {
+        if !(2..=BigInt::BITS).contains(&ring_size) {
+            return Err(KeyError::InvalidRingSize);
+        }
+        if n_bits + 1 > BigInt::BITS {
+            return Err(KeyError::Overflow);
+        }
+        if n_bits < 5 * ring_size + 2 * MIN_GROUP_MARGIN - 1 {
+            return Err(KeyError::InsufficientModulusSize);
+        }
+        let r: BigInt = crypto_primes::generate_prime_with_rng(rng, Some(ring_size));
+        let r = RingModulus::new(DynResidueParams::new(&r));
+
+        // n is roughly (r ** 3) * x_p * x_q, so the x's are sized against the actual length of
+        // r ** 3, which can be up to two bits shorter than 3 * ring_size. Otherwise a small r
+        // could make every product too short, and the search below would never end.
+        let rrr = checked(checked(r.modulus().checked_mul(r.modulus()))?.checked_mul(r.modulus()))?;
+        let x_size = n_bits + 1 - rrr.bits();
+        let q_size = x_size / 2;
+        let p_size = x_size - q_size;
+        let (xmin_q, xbound_q) = Self::x_range(q_size);
+        let (xmin_p, xbound_p) = Self::x_range(p_size);
+        // q_size is at least ring_size + MIN_GROUP_MARGIN because r ** 3 has at most
+        // 3 * ring_size bits
+        loop {
+            let b = r.sample_nonzero_with_rng(rng).retrieve();
+            let q = Self::generate_q(r.modulus(), xmin_q, xbound_q, b, safe, rng)?;
+            let p = Self::generate_p(r.modulus(), xmin_p, xbound_p, b, safe, rng)?;
+            let n = checked(p.checked_mul(&q))?;
+            if n.bits() == n_bits || n.bits() == n_bits + 1 {
+                return Self::from_parts(r, p, q, rng);
+            }
+        }
+    }
+
+    /// The range [2 ** (size - 1), 2 ** size) of x values that are exactly size bits long
+    fn x_range(size: usize) -> (BigInt, BigInt) {
+        return (
+            BigInt::ONE.shl_vartime(size - 1),
+            BigInt::ONE.shl_vartime(size),
+        );
+    }
+
+    /// Compute n and phi from the primes p and q, then sample the non-residue y
+    fn from_parts(
+        r: RingModulus,
+        p: BigInt,
+        q: BigInt,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, KeyError> {
+        let n = GroupModulus::from_uint(&checked(p.checked_mul(&q))?);
+        let phi = Self::totient(&p, &q)?;
+        let y = Self::sample_nonresidue_with_rng(&n, &r, &phi, rng);
+
+        return Ok(Self::new(PublicKey::new(r, n, y), SecretKey::new(phi)).with_crt(p, q));
+    }
+
+    /// phi = (p - 1)(q - 1)
+    fn totient(p: &BigInt, q: &BigInt) -> Result<BigInt, KeyError> {
+        let p_minus_one = checked(p.checked_sub(&BigInt::ONE))?;
+        let q_minus_one = checked(q.checked_sub(&BigInt::ONE))?;
+        return checked(p_minus_one.checked_mul(&q_minus_one));
+    }
+
+    /// Build a key pair from primes p and q generated elsewhere, such as by another tool. The
+    /// non-residue y is sampled here. The primes must form a perfect consonance with r.
+    #[cfg(feature = "std")]
+    pub fn from_primes(p: BigInt, q: BigInt, r: RingModulus) -> Result<Self, KeyError> {
+        let is_prime = |candidate: &BigInt| crypto_primes::is_prime_with_rng(&mut OsRng, candidate);
+        if !is_prime(&p) || !is_prime(&q) || !is_prime(r.modulus_ref()) {
+            return Err(KeyError::NotPrime);
+        }
+        if !Self::perfectly_consonant(&r, &Self::totient(&p, &q)?) {
+            return Err(KeyError::NotConsonant);
+        }
+        return Self::from_parts(r, p, q, &mut OsRng);
+    }
+
+    /// Replace the non-residue y with a freshly sampled one, keeping r, n, and phi.
+    ///
+    /// WARNING: every ciphertext encrypted under the old y becomes undecryptable under the
+    /// rotated key: its residue classes are measured against the old y, so decrypting it gives
+    /// a meaningless class or fails. Decrypt (or re-encrypt) everything under the old key before
+    /// rotating, and publish the new public key to every voter, since ballots and proofs made
+    /// under the old public key will not verify either.
+    #[cfg(feature = "std")]
+    pub fn rotate_y(&mut self) {
+        self.rotate_y_with_rng(&mut OsRng);
+    }
+
+    /// Rotate y as rotate_y does, using the supplied source of randomness
+    pub fn rotate_y_with_rng(&mut self, rng: &mut impl CryptoRngCore) {
+        let pk = self.get_pk();
+        let y = Self::sample_nonresidue_with_rng(pk.get_n(), pk.get_r(), self.sk.get_phi(), rng);
+        self.pk = PublicKey::new(*pk.get_r(), *pk.get_n(), y);
+    }
+
+    /// Serialize the key pair, including the secret key. The layout is the magic bytes "BNKP",
+    /// the format version byte, then r, n, y, and phi, each as a big-endian integer of LIMBS
+    /// words, and finally a tag byte that is 1 if the prime factors p, q of n follow and 0 if not
+    #[cfg(feature = "alloc")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(KEY_PAIR_MAGIC.len() + 2 + 6 * BigInt::BYTES);
+        bytes.extend_from_slice(KEY_PAIR_MAGIC);
+        bytes.push(CURRENT_KEY_FORMAT_VERSION);
+        self.get_pk().write_components(&mut bytes);
+        bytes.extend_from_slice(&self.get_sk().get_phi().to_be_bytes());
+        match self.get_factors() {
+            None => bytes.push(WITHOUT_FACTORS_TAG),
+            Some((p, q)) => {
+                bytes.push(WITH_FACTORS_TAG);
+                bytes.extend_from_slice(&p.to_be_bytes());
+                bytes.extend_from_slice(&q.to_be_bytes());
+            }
+        }
+        return bytes;
+    }
+
+    /// Deserialize a key pair written by to_bytes. The components are checked the same way
+    /// PublicKeyBuilder checks them with phi, so the key pair is a perfect consonance.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = KeyReader::new(bytes, KEY_PAIR_MAGIC)?;
+        let (r, n, y) = (
+            reader.read_uint()?,
+            reader.read_uint()?,
+            reader.read_uint()?,
+        );
+        let phi = reader.read_uint()?;
+        let pk = build_public_key(r, n, y, Some(phi))?;
+        let keypair = Self::new(pk, SecretKey::new(phi));
+        let tag = reader.take(1)?[0];
+        let keypair = match tag {
+            WITHOUT_FACTORS_TAG => keypair,
+            WITH_FACTORS_TAG => {
+                let p = reader.read_uint()?;
+                let q = reader.read_uint()?;
+                let n: Option<BigInt> = p.checked_mul(&q).into();
+                if n.as_ref() != Some(keypair.get_pk().get_n().modulus()) {
+                    return Err(FormatError::InconsistentFactors);
+                }
+                keypair.with_crt(p, q)
+            }
+            _ => return Err(FormatError::InvalidTag(tag)),
+        };
+        reader.finish()?;
+        return Ok(keypair);
     }
 
     /// A convenience method for computing the quantity phi/r (over the integers).
@@ -228,14 +1072,117 @@ impl KeyPair {
     /// that (r, n, y) is a perfect consonance
     pub fn phi_over_r(&self) -> BigInt {
         let phi = self.get_sk().get_phi();
-        let r = self.get_pk().get_r().modulus();
-        return phi.checked_div(r).unwrap();
+        return phi / self.get_pk().get_r().as_nonzero();
+    }
+
+    /// Check whether z is an r-th residue, i.e. z ** (phi/r) = 1 (mod n). This takes a single
+    /// exponentiation, where rth_root computes the root and then checks it
+    pub fn is_rth_residue(&self, z: &OpaqueResidue) -> bool {
+        return z.get_residue().pow(&self.phi_over_r()).retrieve() == BigInt::ONE;
+    }
+
+    /// Check that the multiplicative order of y under n is a multiple of r, which is what makes
+    /// the r classes distinguishable. Computing the order itself would require factoring p - 1
+    /// and q - 1, but under perfect consonance r divides phi exactly once, so r divides ord(y)
+    /// if and only if y^(phi/r) != 1 (mod n)
+    pub fn y_order_divisible_by_r(&self) -> bool {
+        if !self.check_perfect_consonance() {
+            return false;
+        }
+        return !self.is_rth_residue(&OpaqueResidue::new(*self.get_pk().get_y()));
+    }
+
+    /// Decrypt a ciphertext into its residue class. Decrypting the product of many ballots gives
+    /// the sum of their (possibly weighted) votes (mod r).
+    ///
+    /// The discrete log is solved with discrete_log_ct, so the time it takes to decrypt does not
+    /// depend on the plaintext. Unlike ClearResidue::decompose, this does not recover the
+    /// witness.
+    ///
+    /// Panics if the ciphertext is malformed; see try_decrypt
+    pub fn decrypt(&self, ct: &OpaqueResidue) -> ResidueClass {
+        return self.try_decrypt(ct).expect("failed to decrypt");
+    }
+
+    /// Decrypt a ciphertext into its residue class, or return an error if the ciphertext is not
+    /// an element of Z/n or the discrete log has no solution
+    pub fn try_decrypt(&self, ct: &OpaqueResidue) -> Result<ResidueClass, DecryptError> {
+        if !self.get_pk().is_valid_ciphertext(ct) {
+            return Err(DecryptError::MalformedCiphertext);
+        }
+        let phi_over_r = self.phi_over_r();
+        let base = self.get_pk().get_y().pow(&phi_over_r).retrieve();
+        return self.decrypt_with(ct, &phi_over_r, &base);
+    }
+
+    /// Lazily decrypt each ciphertext of the iterator as try_decrypt does. phi/r and
+    /// y ** (phi/r) are computed once and shared by every element, so this is cheaper than
+    /// calling try_decrypt in a loop. The iterator can be handed to rayon's par_bridge to
+    /// decrypt in parallel.
+    pub fn decrypt_iter<'a, I: Iterator<Item = &'a OpaqueResidue> + 'a>(
+        &'a self,
+        iter: I,
+    ) -> impl Iterator<Item = Result<ResidueClass, DecryptError>> + 'a {
+        let phi_over_r = self.phi_over_r();
+        let base = self.get_pk().get_y().pow(&phi_over_r).retrieve();
+        return iter.map(move |ct| {
+            if !self.get_pk().is_valid_ciphertext(ct) {
+                return Err(DecryptError::MalformedCiphertext);
+            }
+            return self.decrypt_with(ct, &phi_over_r, &base);
+        });
+    }
+
+    /// Decrypt a valid ciphertext given phi/r and y ** (phi/r)
+    fn decrypt_with(
+        &self,
+        ct: &OpaqueResidue,
+        phi_over_r: &BigInt,
+        base: &BigInt,
+    ) -> Result<ResidueClass, DecryptError> {
+        let r = self.get_pk().get_r();
+        let class = arithmetics::discrete_log_ct(
+            base,
+            &ct.get_residue().pow(phi_over_r).retrieve(),
+            r.modulus_ref(),
+            self.get_pk().get_n().modulus_ref(),
+        )
+        .ok_or(DecryptError::MalformedCiphertext)?;
+        return Ok(ResidueClass::new(DynResidue::new(&class, *r.params())));
+    }
+
+    /// Re-derive the witness x of a ciphertext val = (y ** class) * (x ** r) whose residue class
+    /// is already known, such as from an audit log of (ciphertext, class) pairs. Return None if
+    /// val is not in the purported residue class.
+    pub fn recover_witness(&self, val: &OpaqueResidue, class: &ResidueClass) -> Option<Witness> {
+        let z = *val * self.get_pk().invert_y().pow(class);
+        let witness = arithmetics::rth_root(
+            z.clone_residue(),
+            self.get_pk().get_r().modulus(),
+            self.get_sk().get_phi(),
+        )?;
+        return Some(Witness::new(witness));
+    }
+
+    /// Decrypt a ciphertext whose residue class is expected to be an index in 0..num_classes,
+    /// such as a candidate index in a ballot. A residue class outside of this range indicates
+    /// a malformed ciphertext.
+    pub fn decrypt_index(
+        &self,
+        ct: &OpaqueResidue,
+        num_classes: usize,
+    ) -> Result<usize, DecryptError> {
+        return match self.try_decrypt(ct)?.as_u64() {
+            Some(class) if class < num_classes as u64 => Ok(class as usize),
+            _ => Err(DecryptError::ClassOutOfRange),
+        };
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
     const RINGSIZE: usize = 16;
     const MODSIZE: usize = 64;
     const SAFEPRIME: bool = false;
@@ -245,4 +1192,555 @@ mod tests {
         let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
         assert!(keypair.check_perfect_consonance());
     }
+
+    /// Two key generations under the same seed should produce the same key pair, including y
+    #[test]
+    fn test_seeded_keygen() {
+        let first = KeyPair::keygen_with_rng(
+            RINGSIZE,
+            MODSIZE,
+            SAFEPRIME,
+            &mut ChaCha20Rng::seed_from_u64(811),
+        );
+        let second = KeyPair::keygen_with_rng(
+            RINGSIZE,
+            MODSIZE,
+            SAFEPRIME,
+            &mut ChaCha20Rng::seed_from_u64(811),
+        );
+        assert_eq!(first.get_pk().get_y(), second.get_pk().get_y());
+        assert_eq!(first, second);
+        assert!(first.check_perfect_consonance());
+    }
+
+    #[test]
+    fn test_consonance_confidence() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let faithful = |challenge: &OpaqueChallenge| GovernmentProof::respond(challenge, &keypair);
+        assert_eq!(pk.consonance_confidence(&faithful, 2), 1.0);
+        assert_eq!(pk.consonance_confidence(&faithful, 0), 0.0);
+
+        let refusing =
+            |challenge: &OpaqueChallenge| GovernmentProof::new(pk.clone(), challenge.clone(), None);
+        assert_eq!(pk.consonance_confidence(&refusing, 2), 0.0);
+    }
+
+    #[test]
+    fn test_is_perfectly_consonant() {
+        for _ in 0..4 {
+            let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+            let r = keypair.get_pk().get_r().to_uint();
+            let (p, q) = keypair.get_factors().unwrap();
+            assert!(is_perfectly_consonant(&r, p, q));
+            // r divides p - 1, not q - 1
+            assert!(!is_perfectly_consonant(&r, q, p));
+        }
+
+        let small = |r: u8, p: u8, q: u8| {
+            is_perfectly_consonant(
+                &BigInt::from_u8(r),
+                &BigInt::from_u8(p),
+                &BigInt::from_u8(q),
+            )
+        };
+        assert!(small(3, 7, 11));
+        assert!(!small(3, 11, 7)); // 3 does not divide 10
+        assert!(!small(3, 19, 11)); // 9 divides 18
+        assert!(!small(3, 7, 13)); // 3 divides 12
+        assert!(!small(9, 19, 11)); // 9 is not prime
+        assert!(!small(0, 7, 11));
+        assert!(!small(3, 0, 11));
+    }
+
+    #[test]
+    fn test_key_bytes_round_trip() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let bytes = pk.to_bytes();
+        assert_eq!(bytes.len(), 5 + 3 * BigInt::BYTES);
+        assert_eq!(&bytes[..4], b"BNPK");
+        assert_eq!(bytes[4], CURRENT_KEY_FORMAT_VERSION);
+        assert_eq!(PublicKey::from_bytes(&bytes), Ok(*pk));
+
+        let bytes = keypair.to_bytes();
+        assert_eq!(KeyPair::from_bytes(&bytes), Ok(keypair));
+        let without_factors = KeyPair::new(*pk, *keypair.get_sk());
+        assert_eq!(
+            KeyPair::from_bytes(&without_factors.to_bytes()),
+            Ok(without_factors)
+        );
+    }
+
+    #[test]
+    fn test_key_bytes_malformed() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk_bytes = keypair.get_pk().to_bytes();
+        let keypair_bytes = keypair.to_bytes();
+
+        let mut bumped = pk_bytes.clone();
+        bumped[4] = CURRENT_KEY_FORMAT_VERSION + 1;
+        assert_eq!(
+            PublicKey::from_bytes(&bumped),
+            Err(FormatError::UnsupportedVersion(
+                CURRENT_KEY_FORMAT_VERSION + 1
+            ))
+        );
+        let mut bumped = keypair_bytes.clone();
+        bumped[4] = CURRENT_KEY_FORMAT_VERSION + 1;
+        assert_eq!(
+            KeyPair::from_bytes(&bumped),
+            Err(FormatError::UnsupportedVersion(
+                CURRENT_KEY_FORMAT_VERSION + 1
+            ))
+        );
+
+        assert_eq!(KeyPair::from_bytes(&pk_bytes), Err(FormatError::BadMagic));
+        assert_eq!(
+            PublicKey::from_bytes(&keypair_bytes),
+            Err(FormatError::BadMagic)
+        );
+        assert_eq!(
+            PublicKey::from_bytes(&pk_bytes[..pk_bytes.len() - 1]),
+            Err(FormatError::Truncated)
+        );
+        let mut trailing = pk_bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            PublicKey::from_bytes(&trailing),
+            Err(FormatError::TrailingBytes)
+        );
+
+        // the factors tag follows r, n, y, and phi
+        let tag = 5 + 4 * BigInt::BYTES;
+        let mut bad_tag = keypair_bytes.clone();
+        bad_tag[tag] = 2;
+        assert_eq!(
+            KeyPair::from_bytes(&bad_tag),
+            Err(FormatError::InvalidTag(2))
+        );
+        let mut corrupted = keypair_bytes.clone();
+        corrupted[tag + 1 + BigInt::BYTES - 1] ^= 2;
+        assert_eq!(
+            KeyPair::from_bytes(&corrupted),
+            Err(FormatError::InconsistentFactors)
+        );
+
+        // phi that does not fit r is caught by the same checks as the builder
+        let mut bad_phi = keypair_bytes.clone();
+        bad_phi[tag - 1] ^= 1;
+        assert_eq!(
+            KeyPair::from_bytes(&bad_phi),
+            Err(FormatError::InvalidKey(KeyError::NotConsonant))
+        );
+    }
+
+    #[test]
+    fn test_keygen_with_r() {
+        // 65537 is prime
+        let r = BigInt::from_u32(65537);
+        let keypair = KeyPair::keygen_with_r(r, MODSIZE, SAFEPRIME).unwrap();
+        assert_eq!(keypair.get_pk().get_r().to_uint(), r);
+        assert!(keypair.check_perfect_consonance());
+        assert!(keypair.get_pk().is_valid_y(&keypair));
+
+        let class = ResidueClass::from_u64(65536, keypair.get_pk().get_r());
+        let ct = ClearResidue::random(Some(class.clone_residue()), keypair.get_pk());
+        assert_eq!(keypair.decrypt(ct.get_val()), class);
+
+        assert_eq!(
+            KeyPair::keygen_with_r(BigInt::from_u32(65535), MODSIZE, SAFEPRIME),
+            Err(KeyError::NotPrime)
+        );
+        assert_eq!(
+            KeyPair::keygen_with_r(BigInt::from_u8(2), MODSIZE, SAFEPRIME),
+            Err(KeyError::InvalidRingSize)
+        );
+        assert_eq!(
+            KeyPair::keygen_with_r(r, BigInt::BITS, SAFEPRIME),
+            Err(KeyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_keygen_exact_modulus() {
+        let min_bits = 5 * RINGSIZE + 2 * MIN_GROUP_MARGIN - 1;
+        for n_bits in [min_bits, min_bits + 1] {
+            let keypair = KeyPair::keygen_exact_modulus(RINGSIZE, n_bits, SAFEPRIME);
+            let bits = keypair.get_pk().get_n().modulus().bits();
+            assert!(bits == n_bits || bits == n_bits + 1);
+            assert!(keypair.check_perfect_consonance());
+        }
+    }
+
+    /// Below the minimum, the x terms would be too narrow to leave a margin over r, and at
+    /// worst pinned to a single value that makes the search for p and q endless
+    #[test]
+    fn test_keygen_exact_modulus_too_small() {
+        let n_bits = 5 * RINGSIZE + 2 * MIN_GROUP_MARGIN - 2;
+        assert_eq!(
+            KeyPair::try_keygen_exact_modulus(RINGSIZE, n_bits, SAFEPRIME),
+            Err(KeyError::InsufficientModulusSize)
+        );
+        assert_eq!(
+            KeyPair::try_keygen_exact_modulus(RINGSIZE, BigInt::BITS, SAFEPRIME),
+            Err(KeyError::Overflow)
+        );
+        assert_eq!(
+            KeyPair::try_keygen_exact_modulus(1, n_bits, SAFEPRIME),
+            Err(KeyError::InvalidRingSize)
+        );
+    }
+
+    #[test]
+    fn test_try_keygen() {
+        let keypair = KeyPair::try_keygen(RINGSIZE, MODSIZE + 32, SAFEPRIME).unwrap();
+        assert!(keypair.check_perfect_consonance());
+        assert_eq!(
+            KeyPair::try_keygen(1, MODSIZE, SAFEPRIME),
+            Err(KeyError::InvalidRingSize)
+        );
+        assert_eq!(
+            KeyPair::try_keygen(BigInt::BITS + 1, MODSIZE, SAFEPRIME),
+            Err(KeyError::InvalidRingSize)
+        );
+        // n = pq has roughly 3 * 16 + 2 * 120 bits, which does not fit in a BigInt
+        assert_eq!(
+            KeyPair::try_keygen(RINGSIZE, 120, SAFEPRIME),
+            Err(KeyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_insufficient_modulus_size() {
+        for group_size in [RINGSIZE - 1, RINGSIZE, RINGSIZE + MIN_GROUP_MARGIN - 1] {
+            assert_eq!(
+                KeyPair::try_keygen(RINGSIZE, group_size, SAFEPRIME),
+                Err(KeyError::InsufficientModulusSize)
+            );
+        }
+        assert_eq!(
+            KeyPair::keygen_with_r(BigInt::from_u32(65537), RINGSIZE + 1, SAFEPRIME),
+            Err(KeyError::InsufficientModulusSize)
+        );
+        assert!(KeyPair::try_keygen(RINGSIZE, RINGSIZE + MIN_GROUP_MARGIN, SAFEPRIME).is_ok());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_keygen_parallel() {
+        let keypair = KeyPair::keygen_parallel(RINGSIZE, MODSIZE, SAFEPRIME);
+        assert!(keypair.check_perfect_consonance());
+        assert!(keypair.get_pk().is_valid_y(&keypair));
+        assert_eq!(
+            KeyPair::try_keygen_parallel(1, MODSIZE, SAFEPRIME),
+            Err(KeyError::InvalidRingSize)
+        );
+    }
+
+    #[test]
+    fn test_from_primes() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let (p, q) = *keypair.get_factors().unwrap();
+        let r = *keypair.get_pk().get_r();
+        let imported = KeyPair::from_primes(p, q, r).unwrap();
+        assert_eq!(imported.get_pk().get_n(), keypair.get_pk().get_n());
+        assert_eq!(imported.get_sk(), keypair.get_sk());
+        assert!(imported.check_perfect_consonance());
+
+        // phi = 10 * 6 = 60 = 5 * 12
+        let five = RingModulus::from_hex("05").unwrap();
+        let small = KeyPair::from_primes(BigInt::from_u8(11), BigInt::from_u8(7), five).unwrap();
+        assert!(small.get_pk().is_valid_y(&small));
+    }
+
+    #[test]
+    fn test_keygen_verified() {
+        let keypair = KeyPair::keygen_verified(RINGSIZE, MODSIZE, SAFEPRIME).unwrap();
+        assert!(keypair.self_test_with_rng(&mut OsRng).is_ok());
+
+        // y ** r is an r-th residue, so every ciphertext under it decrypts to the class 0
+        let (p, q) = *keypair.get_factors().unwrap();
+        let imported = KeyPair::from_primes(p, q, *keypair.get_pk().get_r()).unwrap();
+        let pk = imported.get_pk();
+        let y_bad = pk.get_y().pow(pk.get_r().modulus_ref());
+        let corrupted = KeyPair::new(
+            PublicKey::new(*pk.get_r(), *pk.get_n(), OpaqueResidue::new(y_bad)),
+            *imported.get_sk(),
+        );
+        assert_eq!(
+            corrupted.self_test_with_rng(&mut OsRng),
+            Err(KeyError::SelfTestFailed)
+        );
+    }
+
+    #[test]
+    fn test_rotate_y() {
+        let mut keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let before = *keypair.get_pk();
+        keypair.rotate_y();
+        let pk = keypair.get_pk();
+        assert_ne!(pk.get_y(), before.get_y());
+        assert_eq!(pk.get_r(), before.get_r());
+        assert_eq!(pk.get_n(), before.get_n());
+        assert!(keypair.check_perfect_consonance());
+        assert!(keypair.y_order_divisible_by_r());
+        assert!(pk.is_valid_y(&keypair));
+
+        let class = ResidueClass::from_u64(7, pk.get_r());
+        let ct = ClearResidue::random(Some(class.clone_residue()), pk);
+        assert_eq!(keypair.decrypt(ct.get_val()), class);
+        assert!(keypair.self_test_with_rng(&mut OsRng).is_ok());
+    }
+
+    #[test]
+    fn test_from_primes_not_consonant() {
+        let five = RingModulus::from_hex("05").unwrap();
+        let seven = RingModulus::from_hex("07").unwrap();
+        // phi = 10 * 30 = 300 = 5 * 60, but 5 also divides 60
+        assert_eq!(
+            KeyPair::from_primes(BigInt::from_u8(11), BigInt::from_u8(31), five),
+            Err(KeyError::NotConsonant)
+        );
+        // phi = 10 * 12 = 120 is not divisible by 7
+        assert_eq!(
+            KeyPair::from_primes(BigInt::from_u8(11), BigInt::from_u8(13), seven),
+            Err(KeyError::NotConsonant)
+        );
+        assert_eq!(
+            KeyPair::from_primes(BigInt::from_u8(15), BigInt::from_u8(7), five),
+            Err(KeyError::NotPrime)
+        );
+    }
+
+    #[test]
+    fn test_invalid_ciphertext() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let n = keypair.get_pk().get_n().to_dyn_residue_params();
+        let valid = OpaqueResidue::new(keypair.get_pk().sample_invertible());
+        let invalid = OpaqueResidue::new(DynResidue::new(&BigInt::ZERO, n));
+        assert!(keypair.get_pk().is_valid_ciphertext(&valid));
+        assert!(!keypair.get_pk().is_valid_ciphertext(&invalid));
+
+        let bounded = keypair.get_pk().sample_invertible_bounded(1).unwrap();
+        assert!(keypair
+            .get_pk()
+            .is_valid_ciphertext(&OpaqueResidue::new(bounded)));
+        assert_eq!(keypair.get_pk().sample_invertible_bounded(0), None);
+    }
+
+    #[test]
+    fn test_identity_y_is_invalid() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        assert!(keypair.get_pk().is_valid_y(&keypair));
+
+        let pk = keypair.get_pk();
+        let one = OpaqueResidue::one(pk.get_n().to_dyn_residue_params());
+        let degenerate = PublicKey::new(*pk.get_r(), *pk.get_n(), one);
+        let degenerate = KeyPair::new(degenerate, *keypair.get_sk());
+        assert!(!degenerate.get_pk().is_valid_y(&degenerate));
+    }
+
+    #[test]
+    fn test_y_order_divisible_by_r() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        assert!(keypair.y_order_divisible_by_r());
+
+        // y^r is an r-th residue, so its order divides phi/r
+        let pk = keypair.get_pk();
+        let y_to_r = OpaqueResidue::new(pk.get_y().pow(pk.get_r().modulus_ref()));
+        let residue = PublicKey::new(*pk.get_r(), *pk.get_n(), y_to_r);
+        let residue = KeyPair::new(residue, *keypair.get_sk());
+        assert!(!residue.y_order_divisible_by_r());
+    }
+
+    #[test]
+    fn test_recover_witness() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let class = DynResidue::new(&BigInt::from_u8(3), pk.get_r().to_dyn_residue_params());
+        let ct = ClearResidue::random(Some(class), pk);
+        let witness = keypair.recover_witness(ct.get_val(), ct.get_rc()).unwrap();
+        let recovered = ClearResidue::new(ct.clone_val(), ct.clone_rc(), witness, pk);
+        assert!(recovered.is_consistent());
+
+        let wrong_class = ResidueClass::new(class + DynResidue::one(class.params().clone()));
+        assert!(keypair
+            .recover_witness(ct.get_val(), &wrong_class)
+            .is_none());
+    }
+
+    #[test]
+    fn test_is_rth_residue() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        for class in [0u8, 0, 1, 2, 0, 5] {
+            let class =
+                DynResidue::new(&BigInt::from_u8(class), pk.get_r().to_dyn_residue_params());
+            let z = ClearResidue::random(Some(class), pk).clone_val();
+            let root = arithmetics::rth_root(
+                z.clone_residue(),
+                pk.get_r().modulus(),
+                keypair.get_sk().get_phi(),
+            );
+            assert_eq!(keypair.is_rth_residue(&z), root.is_some());
+            assert_eq!(keypair.is_rth_residue(&z), class.retrieve() == BigInt::ZERO);
+        }
+    }
+
+    /// The cached inverse of y is the inverse that invert would compute, and it is carried over
+    /// when the key is rebuilt, e.g. from its serialized form
+    #[test]
+    fn test_invert_y_cached() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let y = OpaqueResidue::new(*pk.get_y());
+        assert_eq!(*pk.invert_y(), y.invert());
+        assert_eq!((y * *pk.invert_y()).retrieve(), BigInt::ONE);
+
+        let restored = PublicKey::from_bytes(&pk.to_bytes()).unwrap();
+        assert_eq!(restored.invert_y(), pk.invert_y());
+    }
+
+    #[test]
+    fn test_public_key_builder() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let builder = PublicKeyBuilder::new()
+            .ring_modulus(*pk.get_r())
+            .group_modulus(*pk.get_n())
+            .y(pk.get_y().retrieve());
+        assert_eq!(builder.build(), Ok(*pk));
+        let with_phi = builder.clone().phi(*keypair.get_sk().get_phi());
+        assert_eq!(with_phi.build(), Ok(*pk));
+
+        // y = 1 is invertible, so it is only caught with phi
+        assert!(builder.clone().y(BigInt::ONE).build().is_ok());
+        assert_eq!(
+            with_phi.clone().y(BigInt::ONE).build(),
+            Err(KeyError::InvalidY)
+        );
+        assert_eq!(
+            with_phi.clone().phi(BigInt::ZERO).build(),
+            Err(KeyError::NotConsonant)
+        );
+        assert_eq!(
+            builder.clone().y(BigInt::ZERO).build(),
+            Err(KeyError::InvalidY)
+        );
+
+        let missing = PublicKeyBuilder::new()
+            .ring_modulus(*pk.get_r())
+            .y(pk.get_y().retrieve());
+        assert_eq!(missing.build(), Err(KeyError::MissingComponent));
+    }
+
+    #[test]
+    fn test_encrypt_bit() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let classes = ballot::zero_or_one(pk.get_r());
+        for bit in [false, true] {
+            let (ct, proof) = pk.encrypt_bit(bit);
+            assert_eq!(proof.statement, ct);
+            assert!(proof.verify(&classes, pk));
+            assert_eq!(keypair.decrypt(&ct).as_u64(), Some(bit as u64));
+        }
+    }
+
+    #[test]
+    fn test_try_decrypt_matches_decompose() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        for class in [0u8, 1, 42] {
+            let class =
+                DynResidue::new(&BigInt::from_u8(class), pk.get_r().to_dyn_residue_params());
+            let ct = ClearResidue::random(Some(class), pk).clone_val();
+            let decomposed = ClearResidue::decompose(ct.clone_residue(), &keypair);
+            assert_eq!(keypair.try_decrypt(&ct), Ok(*decomposed.get_rc()));
+        }
+    }
+
+    #[test]
+    fn test_encode_class() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let class = ResidueClass::from_u64(7, pk.get_r());
+        let encoded = pk.encode_class(&class);
+        assert_eq!(keypair.decrypt(&encoded), class);
+        assert_eq!(encoded, pk.encode_class(&class));
+    }
+
+    #[test]
+    fn test_decrypt_malformed() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let (p, _) = keypair.get_factors().unwrap();
+        let malformed = OpaqueResidue::new(DynResidue::new(p, pk.get_n().to_dyn_residue_params()));
+        assert_eq!(
+            keypair.try_decrypt(&malformed),
+            Err(DecryptError::MalformedCiphertext)
+        );
+        assert_eq!(
+            keypair.decrypt_index(&malformed, 2),
+            Err(DecryptError::MalformedCiphertext)
+        );
+        assert_eq!(
+            ClearResidue::try_decompose(malformed.clone_residue(), &keypair),
+            Err(DecryptError::MalformedCiphertext)
+        );
+    }
+
+    #[test]
+    fn test_decrypt_iter() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let pk = keypair.get_pk();
+        let plaintexts = [0u64, 1, 42, 1, 0];
+        let mut ciphertexts = plaintexts
+            .iter()
+            .map(|&val| {
+                let class = ResidueClass::from_u64(val, pk.get_r());
+                return ClearResidue::random(Some(class.clone_residue()), pk).clone_val();
+            })
+            .collect::<Vec<OpaqueResidue>>();
+        let classes = keypair
+            .decrypt_iter(ciphertexts.iter())
+            .map(|class| class.unwrap().as_u64().unwrap())
+            .collect::<Vec<u64>>();
+        assert_eq!(classes, plaintexts);
+
+        // a malformed ciphertext fails on its own without stopping the rest
+        let (p, _) = keypair.get_factors().unwrap();
+        ciphertexts[1] = OpaqueResidue::new(DynResidue::new(p, pk.get_n().to_dyn_residue_params()));
+        let results = keypair
+            .decrypt_iter(ciphertexts.iter())
+            .collect::<Vec<Result<ResidueClass, DecryptError>>>();
+        assert_eq!(results[1], Err(DecryptError::MalformedCiphertext));
+        assert_eq!(results[2], keypair.try_decrypt(&ciphertexts[2]));
+    }
+
+    #[test]
+    fn test_decrypt_index() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let class = DynResidue::new(
+            &BigInt::from_u8(2),
+            keypair.get_pk().get_r().to_dyn_residue_params(),
+        );
+        let ct = ClearResidue::random(Some(class), keypair.get_pk()).clone_val();
+        assert_eq!(keypair.decrypt_index(&ct, 3), Ok(2));
+    }
+
+    #[test]
+    fn test_decrypt_index_out_of_range() {
+        let keypair = KeyPair::keygen(RINGSIZE, MODSIZE, SAFEPRIME);
+        let class = DynResidue::new(
+            &BigInt::from_u8(5),
+            keypair.get_pk().get_r().to_dyn_residue_params(),
+        );
+        let ct = ClearResidue::random(Some(class), keypair.get_pk()).clone_val();
+        assert_eq!(
+            keypair.decrypt_index(&ct, 3),
+            Err(DecryptError::ClassOutOfRange)
+        );
+    }
 }
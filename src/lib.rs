@@ -1,4 +1,13 @@
 //! Libraries for higher residue arithmetics
+//!
+//! The residue arithmetic and the keys build without the standard library. The proofs and the
+//! election need the "alloc" feature, and the methods that sample without an explicit source of
+//! randomness need the "std" feature, which is on by default.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use crypto_bigint::Uint;
 
 /// Use the same big integer type everywhere
@@ -9,5 +18,8 @@ pub const RINGSIZE: usize = 16;
 pub const GROUPSIZE: usize = 64;
 
 pub mod arithmetics;
+#[cfg(feature = "alloc")]
+pub mod election;
 pub mod keys;
+#[cfg(feature = "alloc")]
 pub mod proofs;
@@ -0,0 +1,206 @@
+//! Auditing a whole election from its public record: every ballot proof, the aggregate of the
+//! ballots, and the tally proof. None of the checks need the secret key.
+use crate::{
+    arithmetics::{OpaqueResidue, ResidueClass},
+    keys::PublicKey,
+    proofs::{ballot::BallotProof, tally::TallyProof},
+};
+use core::fmt;
+
+/// Reasons for which an election fails the audit
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum AuditError {
+    /// The tally has not been recorded in the transcript
+    MissingTally,
+
+    /// The proof recorded next to the ballot at this index is about some other ciphertext
+    BallotMismatch(usize),
+
+    /// The proof of the ballot at this index does not verify
+    InvalidBallotProof(usize),
+
+    /// The recorded aggregate is not the product of the recorded ballots
+    AggregateMismatch,
+
+    /// The tally proof does not show that the aggregate decrypts to the announced tally
+    InvalidTallyProof,
+
+    /// The tally proof of the district at this index does not verify against its aggregate
+    InvalidDistrictProof(usize),
+
+    /// The tally of the district at this index is more than its number of ballots
+    DistrictOutOfRange(usize),
+
+    /// The total number of ballots is not less than r, so the combined tally may have wrapped
+    /// around
+    TallyOverflow,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingTally => write!(f, "the transcript has no tally"),
+            Self::BallotMismatch(i) => {
+                write!(f, "the proof of ballot {i} is about another ciphertext")
+            }
+            Self::InvalidBallotProof(i) => write!(f, "the proof of ballot {i} is invalid"),
+            Self::AggregateMismatch => write!(f, "the aggregate is not the product of the ballots"),
+            Self::InvalidTallyProof => write!(f, "the tally proof is invalid"),
+            Self::InvalidDistrictProof(i) => {
+                write!(f, "the tally proof of district {i} is invalid")
+            }
+            Self::DistrictOutOfRange(i) => {
+                write!(f, "the tally of district {i} exceeds its ballot count")
+            }
+            Self::TallyOverflow => write!(f, "the combined tally may have wrapped around r"),
+        }
+    }
+}
+
+impl core::error::Error for AuditError {}
+
+/// Check that the proof of the ballot at index i is about that ballot and shows that the ballot
+/// is in one of the classes
+pub(crate) fn verify_ballot(
+    i: usize,
+    ballot: &OpaqueResidue,
+    proof: &BallotProof,
+    classes: &[ResidueClass],
+    pk: &PublicKey,
+) -> Result<(), AuditError> {
+    if proof.statement != *ballot {
+        return Err(AuditError::BallotMismatch(i));
+    }
+    if !proof.verify(classes, pk) {
+        return Err(AuditError::InvalidBallotProof(i));
+    }
+    return Ok(());
+}
+
+/// Audit an election in one call:
+/// 1. every ballot proof is about its ballot and shows that the ballot is in one of the classes
+/// 2. the aggregate is recomputed as the product of all ballots
+/// 3. the tally proof shows that the recomputed aggregate decrypts to the claimed tally
+///
+/// The first ballot that fails is reported by its index
+pub fn verify_election(
+    ballots: &[(OpaqueResidue, BallotProof)],
+    classes: &[ResidueClass],
+    tally_proof: &TallyProof,
+    claimed_tally: &ResidueClass,
+    pk: &PublicKey,
+) -> Result<(), AuditError> {
+    let mut aggregate = OpaqueResidue::one(*pk.get_n().params());
+    for (i, (ballot, proof)) in ballots.iter().enumerate() {
+        verify_ballot(i, ballot, proof, classes, pk)?;
+        aggregate = aggregate * *ballot;
+    }
+    if !tally_proof.verify_public(&aggregate, claimed_tally, pk) {
+        return Err(AuditError::InvalidTallyProof);
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arithmetics::ClearResidue,
+        keys::KeyPair,
+        proofs::{ballot::zero_or_one, tally},
+        BigInt, GROUPSIZE, RINGSIZE,
+    };
+    use crypto_bigint::modular::runtime_mod::DynResidue;
+
+    /// Cast the given votes, then tally and prove the tally
+    fn cast(
+        keypair: &KeyPair,
+        votes: &[u8],
+    ) -> (Vec<(OpaqueResidue, BallotProof)>, TallyProof, ResidueClass) {
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let mut aggregate = OpaqueResidue::one(pk.get_n().to_dyn_residue_params());
+        let mut ballots = vec![];
+        for vote in votes {
+            let vote = DynResidue::new(&BigInt::from_u8(*vote), pk.get_r().to_dyn_residue_params());
+            let ballot = ClearResidue::random(Some(vote), pk);
+            let proof = BallotProof::from_statement(&ballot, &classes, pk);
+            aggregate = aggregate * *ballot.get_val();
+            ballots.push((ballot.into_opaque(), proof));
+        }
+        let (result, proof) = TallyProof::from_aggregate(&aggregate, keypair);
+        return (ballots, proof, result);
+    }
+
+    #[test]
+    fn test_verify_election() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let (ballots, proof, result) = cast(&keypair, &[1, 0, 1]);
+        assert_eq!(result.retrieve(), BigInt::from_u8(2));
+        assert_eq!(
+            verify_election(&ballots, &classes, &proof, &result, pk),
+            Ok(())
+        );
+
+        let wrong_tally =
+            ResidueClass::new(result.clone_residue() + DynResidue::one(*result.params()));
+        assert_eq!(
+            verify_election(&ballots, &classes, &proof, &wrong_tally, pk),
+            Err(AuditError::InvalidTallyProof)
+        );
+    }
+
+    /// A tally proof forged for a made-up tally fails the audit, whether it has no rounds or its
+    /// rounds are answered as if every challenge were 0
+    #[test]
+    fn test_verify_election_forged_tally() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let (ballots, _, _) = cast(&keypair, &[1, 0, 1]);
+        let aggregate = ballots.iter().fold(
+            OpaqueResidue::one(*pk.get_n().params()),
+            |acc, (ballot, _)| acc * *ballot,
+        );
+        let claimed = ResidueClass::from_u64(1000, pk.get_r());
+        let statement = aggregate * pk.invert_y().pow(&claimed);
+        let empty = TallyProof::new(statement, vec![], vec![], vec![]);
+        assert_eq!(
+            verify_election(&ballots, &classes, &empty, &claimed, pk),
+            Err(AuditError::InvalidTallyProof)
+        );
+        let forged = tally::forge(&aggregate, &claimed, pk);
+        assert_eq!(
+            verify_election(&ballots, &classes, &forged, &claimed, pk),
+            Err(AuditError::InvalidTallyProof)
+        );
+    }
+
+    #[test]
+    fn test_verify_election_bad_ballot() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let (mut ballots, proof, result) = cast(&keypair, &[1, 0, 1]);
+
+        // a ballot for 2, with a proof over the wrong set of classes
+        let two = DynResidue::new(&BigInt::from_u8(2), pk.get_r().to_dyn_residue_params());
+        let zero_or_two = [DynResidue::zero(*two.params()), two].map(ResidueClass::new);
+        let bad = ClearResidue::random(Some(two), pk);
+        let bad_proof = BallotProof::from_statement(&bad, &zero_or_two, pk);
+        let honest = ballots[1].clone();
+        ballots[1] = (bad.clone_val(), bad_proof.clone());
+        assert_eq!(
+            verify_election(&ballots, &classes, &proof, &result, pk),
+            Err(AuditError::InvalidBallotProof(1))
+        );
+
+        ballots[1] = (honest.0, bad_proof);
+        assert_eq!(
+            verify_election(&ballots, &classes, &proof, &result, pk),
+            Err(AuditError::BallotMismatch(1))
+        );
+    }
+}
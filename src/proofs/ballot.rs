@@ -13,34 +13,116 @@
 //! selected in the subset, an appropriate element within the capsule is selected to demonstrate
 //! the residue class of the ciphertext
 use crate::{
-    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass, RingModulus},
+    arithmetics::{
+        ClassPowers, ClearResidue, OpaqueResidue, ResidueClass, RingModulus, SmallResidueClass,
+        Witness,
+    },
     keys::PublicKey,
-    BigInt,
+    proofs::{self, HashAlgorithm, NonInteractiveProof, ProofError},
+    BigInt, LIMBS,
+};
+use alloc::{vec, vec::Vec};
+use core::{convert::Infallible, fmt};
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+use crypto_bigint::{
+    modular::runtime_mod::DynResidue, rand_core::CryptoRngCore, CheckedAdd, Encoding, Limb,
 };
-use crypto_bigint::{modular::runtime_mod::DynResidue, rand_core::OsRng, Encoding};
 use rand::seq::SliceRandom;
 use sha3::{Digest, Sha3_256};
 
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/ballot/v2";
+
 /// The choice of using SHA-256 decides that the confidence level has to be 256
 /// In a more robust setting we should have dynamic confidence level
 pub const CONFIDENCE: usize = 256;
 
+/// The serialized proof starts with the identifier of the hash algorithm
+const HASH_ID_BYTES: usize = 1;
+
+/// Errors that can occur when the prover consumes a capsule
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ConsumeError {
+    /// No element of the capsule has the residue class of the statement, i.e. the statement is
+    /// not in any of the classes that the proof is about
+    NoMatchingElement,
+}
+
+impl fmt::Display for ConsumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatchingElement => write!(f, "capsule does not have a matching element"),
+        }
+    }
+}
+
+impl core::error::Error for ConsumeError {}
+
+/// The statement is followed by the number of capsules and the number of elements in each
+/// capsule, each as a big-endian u32
+const HEADER_BYTES: usize = 8;
+
+/// Each response is prefixed with a tag byte indicating whether the capsule was opened
+const TAG_BYTES: usize = 1;
+const OPEN_CAPSULE_TAG: u8 = 1;
+const CONSUME_CAPSULE_TAG: u8 = 0;
+
 /// Use this function to generate the 2-array of residue classes RC[0] and RC[1]
 pub fn zero_or_one(modulus: &RingModulus) -> [ResidueClass; 2] {
-    let one = ResidueClass::new(DynResidue::new(
-        &BigInt::ONE,
-        modulus.to_dyn_residue_params(),
-    ));
-    let zero = ResidueClass::new(DynResidue::new(
-        &BigInt::ZERO,
-        modulus.to_dyn_residue_params(),
-    ));
+    let one = ResidueClass::from_u64(1, modulus);
+    let zero = ResidueClass::from_u64(0, modulus);
     return [one, zero];
 }
 
+/// Generate the 2-array of residue classes RC[w] and RC[0] for a voter with weight w, whose
+/// ballot is an encryption of either w (for) or 0 (against)
+pub fn zero_or_weight(modulus: &RingModulus, weight: u64) -> [ResidueClass; 2] {
+    let weight = ResidueClass::from_u64(weight, modulus);
+    let zero = ResidueClass::zero(*modulus.params());
+    return [weight, zero];
+}
+
+/// The tally is only the weighted sum of the votes (instead of the sum mod r) if the sum of all
+/// weights is less than r. Check that this is the case before accepting the weights.
+pub fn weights_fit(modulus: &RingModulus, weights: &[u64]) -> bool {
+    let mut total = BigInt::ZERO;
+    for weight in weights {
+        match Option::<BigInt>::from(total.checked_add(&BigInt::from_u64(*weight))) {
+            Some(sum) => total = sum,
+            None => return false,
+        }
+    }
+    return total < *modulus.modulus_ref();
+}
+
+/// Encrypt each of the votes and prove that the ballot is in one of the classes. With the
+/// "parallel" feature, the ballots are generated concurrently on the rayon thread pool. The
+/// ballots are returned in the order of the votes either way.
+#[cfg(feature = "std")]
+pub fn generate_ballots(
+    votes: &[ResidueClass],
+    pk: &PublicKey,
+    classes: &[ResidueClass],
+) -> Vec<(OpaqueResidue, BallotProof)> {
+    let generate = |vote: &ResidueClass| {
+        let ballot = ClearResidue::random(Some(vote.clone_residue()), pk);
+        let proof = BallotProof::from_statement(&ballot, classes, pk);
+        return (ballot.into_opaque(), proof);
+    };
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+        return votes.par_iter().map(generate).collect();
+    }
+    #[cfg(not(feature = "parallel"))]
+    return votes.iter().map(generate).collect();
+}
+
 /// Proof that the ballot belongs to one of the pre-specified residue classes without revealing
 /// which specific class. In a simple election, we prove that the ballot belongs to either
 /// RC[0] or RC[1]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct BallotProof {
     pub statement: OpaqueResidue,
 
@@ -49,9 +131,13 @@ pub struct BallotProof {
     pub challenge: Vec<bool>,
 
     pub response: Vec<Response>,
+
+    /// The hash that the challenge is derived with
+    pub hash: HashAlgorithm,
 }
 
 impl BallotProof {
+    /// Assemble a proof whose challenge is derived with the default hash
     pub fn new(
         statement: OpaqueResidue,
         commitment: Vec<OpaqueCapsule>,
@@ -63,52 +149,227 @@ impl BallotProof {
             commitment,
             challenge,
             response,
+            hash: HashAlgorithm::default(),
         };
     }
 
     /// Produce a proof that the statement is in one of the specified residue classes
+    ///
+    /// Panics if the statement is in none of the classes; see try_from_statement for the
+    /// fallible version
+    #[cfg(feature = "std")]
     pub fn from_statement(
         statement: &ClearResidue,
         classes: &[ResidueClass],
         pk: &PublicKey,
     ) -> Self {
-        let answers = (0..CONFIDENCE)
-            .map(|_| ClearCapsule::generate(classes, pk))
-            .collect::<Vec<ClearCapsule>>();
-        let commitment = answers
-            .iter()
-            .map(|clear| clear.obscure())
-            .collect::<Vec<OpaqueCapsule>>();
-        let challenge = Self::generate_challenge(&commitment);
-        let response = Self::respond(statement, &answers, &challenge, pk);
+        return Self::from_statement_with_rng(statement, classes, pk, &mut OsRng);
+    }
 
-        return Self::new(statement.clone_val(), commitment, challenge, response);
+    /// Produce the proof using the supplied source of randomness, so that the proof is
+    /// reproducible under a seeded RNG
+    pub fn from_statement_with_rng(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        return Self::try_from_statement_with_rng(statement, classes, pk, rng)
+            .expect("the statement is in none of the classes");
+    }
+
+    /// Produce a proof that the statement is in one of the specified residue classes, or report
+    /// that there is no such proof because the statement is in none of them
+    #[cfg(feature = "std")]
+    pub fn try_from_statement(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+    ) -> Result<Self, ConsumeError> {
+        return Self::try_from_statement_with_rng(statement, classes, pk, &mut OsRng);
     }
 
-    fn generate_challenge(commitment: &[OpaqueCapsule]) -> Vec<bool> {
-        let mut hasher = Sha3_256::new();
+    /// Produce the proof as try_from_statement does, using the supplied source of randomness
+    pub fn try_from_statement_with_rng(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, ConsumeError> {
+        return Self::prove(
+            statement,
+            classes,
+            0,
+            &[],
+            HashAlgorithm::default(),
+            pk,
+            rng,
+        );
+    }
 
+    /// Produce the proof with the challenge derived under the given hash instead of the default
+    #[cfg(feature = "std")]
+    pub fn from_statement_with_hash(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        hash: HashAlgorithm,
+        pk: &PublicKey,
+    ) -> Self {
+        return Self::prove(statement, classes, 0, &[], hash, pk, &mut OsRng)
+            .expect("the statement is in none of the classes");
+    }
+
+    /// Produce a proof whose challenge also commits to the given context, such as the election
+    /// ID and the voter ID, so that the proof cannot be replayed in another election or for
+    /// another voter. The verifier must supply the same context to verify_with_context.
+    #[cfg(feature = "std")]
+    pub fn from_statement_with_context(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+        context: &[u8],
+    ) -> Self {
+        return Self::prove(
+            statement,
+            classes,
+            0,
+            context,
+            HashAlgorithm::default(),
+            pk,
+            &mut OsRng,
+        )
+        .expect("the statement is in none of the classes");
+    }
+
+    /// Produce the proof with capsules that carry the given number of decoy elements on top of
+    /// one element per class; see ClearCapsule::generate_with_decoys
+    #[cfg(feature = "std")]
+    pub fn from_statement_with_decoys(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        decoys: usize,
+        pk: &PublicKey,
+    ) -> Self {
+        return Self::prove(
+            statement,
+            classes,
+            decoys,
+            &[],
+            HashAlgorithm::default(),
+            pk,
+            &mut OsRng,
+        )
+        .expect("the statement is in none of the classes");
+    }
+
+    fn prove(
+        statement: &ClearResidue,
+        classes: &[ResidueClass],
+        decoys: usize,
+        context: &[u8],
+        hash: HashAlgorithm,
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, ConsumeError> {
+        let powers = ClassPowers::new(classes, pk);
+        let mut answers = Vec::with_capacity(CONFIDENCE);
+        let mut commitment = Vec::with_capacity(CONFIDENCE);
+        for _ in 0..CONFIDENCE {
+            let answer = ClearCapsule::sample(classes, decoys, &powers, rng);
+            commitment.push(answer.obscure());
+            answers.push(answer);
+        }
+        let challenge =
+            Self::generate_challenge(hash, context, &[*statement.as_opaque()], &commitment, pk);
+        let response = Self::respond(statement, &answers, &challenge, pk)?;
+
+        return Ok(Self {
+            statement: *statement.as_opaque(),
+            commitment,
+            challenge,
+            response,
+            hash,
+        });
+    }
+
+    /// Construct the proof that a weighted ballot is an encryption of either 0 or the weight
+    #[cfg(feature = "std")]
+    pub fn from_weighted_statement(statement: &ClearResidue, weight: u64, pk: &PublicKey) -> Self {
+        return Self::from_statement(statement, &zero_or_weight(pk.get_r(), weight), pk);
+    }
+
+    /// The bytes of the commitment that are hashed into the challenge: the canonical big-endian
+    /// value of every element of every capsule, in order
+    fn commitment_bytes(commitment: &[OpaqueCapsule]) -> Vec<u8> {
+        let mut bytes = vec![];
         for capsule in commitment {
             for residue in capsule.get_elements() {
-                hasher.update(residue.retrieve().to_be_bytes());
+                bytes.extend_from_slice(&residue.to_be_bytes());
             }
         }
+        return bytes;
+    }
 
-        let hash: Vec<u8> = hasher.finalize().to_vec();
-        let mut challenge = vec![true; 256];
-        for (i, byte) in hash.iter().enumerate() {
-            for j in 0..u8::BITS {
-                let loc = i * 8 + j as usize;
-                let mask = 0b1000_0000u8 >> j;
-                if mask & byte == 0 {
-                    challenge[loc] = false;
-                } else {
-                    challenge[loc] = true;
-                }
-            }
+    /// The SHA3-256 digest of the commitment alone, i.e. of every element of every capsule as a
+    /// fixed-width big-endian integer. A transparency log can store this in place of the full
+    /// commitment. This is not the digest that the challenge is derived from, which also absorbs
+    /// the domain tag and the context and may use another hash.
+    pub fn commitment_hash(&self) -> [u8; 32] {
+        return Sha3_256::digest(Self::commitment_bytes(&self.commitment)).into();
+    }
+
+    /// Check that the commitment of the proof hashes to the digest recorded earlier, e.g. in a
+    /// transparency log. This does not verify the proof itself.
+    pub fn verify_against_commitment_hash(&self, expected: &[u8; 32]) -> bool {
+        return &self.commitment_hash() == expected;
+    }
+
+    /// Return exactly the bytes that are hashed into the challenge of a proof without context;
+    /// see challenge_preimage_with_context
+    pub fn challenge_preimage(&self, pk: &PublicKey) -> Vec<u8> {
+        return self.challenge_preimage_with_context(&[], pk);
+    }
+
+    /// Return exactly the bytes that are hashed into the challenge: the domain tag, the length
+    /// of the context as a big-endian u32, the context, the public key (r, n, y), the number of
+    /// statements as a big-endian u32 (always 1 here), the statement, and then the commitment. The challenge bits are the digest of these bytes under the
+    /// proof's hash (SHA3-256 by default), most significant bit first, so an auditor can
+    /// recompute the challenge independently.
+    pub fn challenge_preimage_with_context(&self, context: &[u8], pk: &PublicKey) -> Vec<u8> {
+        return Self::preimage(context, &[self.statement], &self.commitment, pk);
+    }
+
+    /// The context is absorbed first, and is prefixed with its length so that the boundary
+    /// between the context and the rest is unambiguous. The statement and the key are hashed
+    /// so that the responses cannot be carried over to another ballot, such as the statement
+    /// multiplied by an r-th residue.
+    fn preimage(
+        context: &[u8],
+        statements: &[OpaqueResidue],
+        commitment: &[OpaqueCapsule],
+        pk: &PublicKey,
+    ) -> Vec<u8> {
+        let mut preimage = DOMAIN.to_vec();
+        preimage.extend_from_slice(&(context.len() as u32).to_be_bytes());
+        preimage.extend_from_slice(context);
+        preimage.extend_from_slice(&pk.component_bytes());
+        preimage.extend_from_slice(&(statements.len() as u32).to_be_bytes());
+        for statement in statements {
+            preimage.extend_from_slice(&statement.to_be_bytes());
         }
+        preimage.extend_from_slice(&Self::commitment_bytes(commitment));
+        return preimage;
+    }
 
-        return challenge;
+    fn generate_challenge(
+        hash: HashAlgorithm,
+        context: &[u8],
+        statements: &[OpaqueResidue],
+        commitment: &[OpaqueCapsule],
+        pk: &PublicKey,
+    ) -> Vec<bool> {
+        let preimage = Self::preimage(context, statements, commitment, pk);
+        return proofs::challenge_bits(&hash.digest(&preimage));
     }
 
     fn respond(
@@ -116,12 +377,12 @@ impl BallotProof {
         commitment: &[ClearCapsule],
         challenge: &[bool],
         pk: &PublicKey,
-    ) -> Vec<Response> {
+    ) -> Result<Vec<Response>, ConsumeError> {
         if challenge.len() != commitment.len() {
             panic!("Challenge and commitment not equal in length");
         }
 
-        let mut responses = vec![];
+        let mut responses = Vec::with_capacity(challenge.len());
         for (i, open_capsule) in challenge.iter().enumerate() {
             if *open_capsule {
                 let clear_capsule = commitment.get(i).unwrap().clone();
@@ -129,70 +390,622 @@ impl BallotProof {
                 responses.push(response);
             } else {
                 responses.push(Response::ConsumeCapsule(
-                    commitment.get(i).unwrap().consume(statement, pk),
+                    commitment.get(i).unwrap().consume(statement, pk)?,
                 ));
             }
         }
-        return responses;
+        return Ok(responses);
     }
 
     /// Verify a single response. If the response is "open capsule", then check that the
-    /// values of the opened capsule match exactly with the values of the commitment capsules.
+    /// values of the opened capsule match exactly with the values of the commitment capsules,
+    /// that each opened element is a consistent decomposition of its value, and that the
+    /// revealed residue classes are exactly the claimed classes (some of them possibly more
+    /// than once, if the capsule has decoys).
     /// if the response is "consume capsule", then use the response to reconstruct the element
-    /// from the capsule, and check that such an element indeed exists.
+    /// from the capsule, and check that such an element indeed exists, where the response must
+    /// be a decomposition of an r-th residue.
+    ///
+    /// Every decomposition is checked under its own copy of the public key, so each of them must
+    /// be the given key; otherwise a prover could decompose under a y of its own choosing.
     fn verify_response(
         statement: &OpaqueResidue,
         commitment: &OpaqueCapsule,
         response: &Response,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
     ) -> bool {
         match response {
             Response::ConsumeCapsule(quotient) => {
+                if quotient.get_ambience() != pk
+                    || !quotient.is_exact_residue()
+                    || !quotient.is_consistent()
+                {
+                    return false;
+                }
                 let reconstructed = statement.clone() * quotient.clone_val();
-                let has_match = commitment
+                return commitment.get_elements().contains(&reconstructed);
+            }
+            Response::OpenCapsule(open_cap) => {
+                if commitment.get_elements().len() != open_cap.get_elements().len() {
+                    return false;
+                }
+                if !open_cap.reveals_classes(classes) {
+                    return false;
+                }
+                return commitment
                     .get_elements()
                     .iter()
-                    .any(|elem| *elem == reconstructed);
-                if !has_match {
-                    panic!("Consume capsule failed to verify");
+                    .zip(open_cap.get_elements().iter())
+                    .all(|(commit_elem, open_elem)| {
+                        return commit_elem == open_elem.get_val()
+                            && open_elem.get_ambience() == pk
+                            && open_elem.is_consistent();
+                    });
+            }
+        }
+    }
+
+    /// Verify the proof that the statement belongs to one of the claimed residue classes under
+    /// the public key
+    pub fn verify(&self, classes: &[ResidueClass], pk: &PublicKey) -> bool {
+        return self.verify_with_context(classes, &[], pk);
+    }
+
+    /// Verify the proof as verify does, but report a proof whose commitment, challenge, and
+    /// response differ in length as malformed instead of as invalid. Ok(false) means that the
+    /// proof is well-formed but does not verify.
+    pub fn verify_checked(
+        &self,
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+    ) -> Result<bool, ProofError> {
+        if self.commitment.len() != self.challenge.len()
+            || self.commitment.len() != self.response.len()
+        {
+            return Err(ProofError::LengthMismatch);
+        }
+        return Ok(self.verify(classes, pk));
+    }
+
+    /// Verify the proof, whose challenge must be the hash of the given context and the
+    /// commitment. A proof produced under another context is rejected.
+    pub fn verify_with_context(
+        &self,
+        classes: &[ResidueClass],
+        context: &[u8],
+        pk: &PublicKey,
+    ) -> bool {
+        // a statement outside of the multiplicative group is never a valid ballot
+        if !pk.is_valid_ciphertext(&self.statement) {
+            return false;
+        }
+        if self.challenge
+            != Self::generate_challenge(self.hash, context, &[self.statement], &self.commitment, pk)
+        {
+            return false;
+        }
+        if self.commitment.len() != self.challenge.len() {
+            return false;
+        }
+        if self.commitment.len() != self.response.len() {
+            return false;
+        }
+
+        return self
+            .commitment
+            .iter()
+            .zip(self.response.iter())
+            .all(|(commitment, response)| {
+                return Self::verify_response(&self.statement, commitment, response, classes, pk);
+            });
+    }
+
+    /// The challenge packed into bytes, first bit as the most significant bit, e.g. for logging.
+    /// This is the same packing as in to_bytes.
+    pub fn challenge_bytes(&self) -> Vec<u8> {
+        return pack_bits(&self.challenge);
+    }
+
+    /// Replace the challenge with bits unpacked from bytes packed by challenge_bytes. One bit is
+    /// read per capsule of the commitment, so padding in the last byte is dropped.
+    pub fn set_challenge_from_bytes(&mut self, bytes: &[u8]) {
+        self.challenge = unpack_bits(bytes, self.commitment.len());
+    }
+
+    /// The soundness of the proof in bits. A cheating prover can prepare each capsule to pass
+    /// either the opening or the consumption but not both, so it survives each challenge bit
+    /// with probability 1/2, and the soundness error of the whole proof is 2 ** -soundness_bits.
+    pub fn soundness_bits(&self) -> usize {
+        return self.challenge.len();
+    }
+
+    /// Serialize the proof. Every residue is written as its canonical value in big-endian with a
+    /// fixed width of LIMBS words. The layout is:
+    /// 1. the identifier byte of the hash that the challenge is derived with
+    /// 2. the statement
+    /// 3. the number of capsules and the number of elements per capsule, as big-endian u32
+    /// 4. the elements of every committed capsule
+    /// 5. the challenge, packed into bytes with the first bit as the most significant bit
+    /// 6. for each response, a tag byte (1 for an opened capsule, 0 for a consumed capsule)
+    ///    followed by the (value, class, witness) of every revealed residue
+    ///
+    /// If r fits in 16 bits, each class is written as a big-endian u16 instead of a full-width
+    /// residue; see SmallResidueClass.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.serialized_size());
+        bytes.push(self.hash.id());
+        write_residue(&mut bytes, &self.statement);
+        bytes.extend_from_slice(&(self.commitment.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.capsule_width() as u32).to_be_bytes());
+        for capsule in self.commitment.iter() {
+            for element in capsule.get_elements() {
+                write_residue(&mut bytes, element);
+            }
+        }
+        bytes.extend_from_slice(&pack_bits(&self.challenge));
+        for response in self.response.iter() {
+            match response {
+                Response::OpenCapsule(capsule) => {
+                    bytes.push(OPEN_CAPSULE_TAG);
+                    for element in capsule.get_elements() {
+                        write_clear_residue(&mut bytes, element);
+                    }
+                }
+                Response::ConsumeCapsule(quotient) => {
+                    bytes.push(CONSUME_CAPSULE_TAG);
+                    write_clear_residue(&mut bytes, quotient);
                 }
-                return has_match;
             }
-            Response::OpenCapsule(open_cap) => {
+        }
+        return bytes;
+    }
+
+    /// The number of bytes that to_bytes will produce, computed without serializing the proof
+    pub fn serialized_size(&self) -> usize {
+        let residue_bytes = BigInt::BYTES;
+        let width = self.capsule_width();
+        let clear_bytes = 2 * residue_bytes + self.class_bytes();
+        let responses: usize = self
+            .response
+            .iter()
+            .map(|response| match response {
+                Response::OpenCapsule(capsule) => capsule.get_elements().len() * clear_bytes,
+                Response::ConsumeCapsule(_) => clear_bytes,
+            })
+            .sum();
+        return HASH_ID_BYTES
+            + residue_bytes
+            + HEADER_BYTES
+            + self.commitment.len() * width * residue_bytes
+            + self.challenge.len().div_ceil(8)
+            + self.response.len() * TAG_BYTES
+            + responses;
+    }
+
+    /// Estimate the serialized size of a proof over num_classes residue classes with the given
+    /// confidence, where each residue is limbs words wide, without building the proof.
+    ///
+    /// The estimate is an upper bound that assumes every capsule is opened. Which capsules are
+    /// opened depends on the challenge, and each consumed capsule reveals a single residue
+    /// instead of num_classes residues, so the actual size is smaller by
+    /// 3 * (num_classes - 1) residues for every consumed capsule. The estimate also assumes
+    /// full-width classes, which a ring of at most 16 bits does not use.
+    pub fn estimate_size(num_classes: usize, confidence: usize, limbs: usize) -> usize {
+        let residue_bytes = limbs * Limb::BYTES;
+        return HASH_ID_BYTES
+            + residue_bytes
+            + HEADER_BYTES
+            + confidence * num_classes * residue_bytes
+            + confidence.div_ceil(8)
+            + confidence * (TAG_BYTES + num_classes * 3 * residue_bytes);
+    }
+
+    /// Deserialize a proof written by to_bytes. The challenge is verified under the hash named
+    /// in the serialized proof, not under the current default, so proofs stored before a change
+    /// of the default hash still verify.
+    pub fn from_bytes(bytes: &[u8], pk: &PublicKey) -> Result<Self, ProofError> {
+        let mut reader = Reader::new(bytes);
+        let id = reader.take(HASH_ID_BYTES)?[0];
+        let hash = HashAlgorithm::from_id(id).ok_or(ProofError::UnknownHashAlgorithm(id))?;
+        let statement = reader.read_opaque(pk)?;
+        let count = reader.read_u32()? as usize;
+        let width = reader.read_u32()? as usize;
+        let mut commitment = Vec::with_capacity(count.min(CONFIDENCE));
+        for _ in 0..count {
+            let elements = (0..width)
+                .map(|_| reader.read_opaque(pk))
+                .collect::<Result<Vec<OpaqueResidue>, ProofError>>()?;
+            commitment.push(OpaqueCapsule::new(elements));
+        }
+        let challenge = unpack_bits(reader.take(count.div_ceil(8))?, count);
+        let mut response = Vec::with_capacity(count.min(CONFIDENCE));
+        for _ in 0..count {
+            let tag = reader.take(TAG_BYTES)?[0];
+            match tag {
+                OPEN_CAPSULE_TAG => {
+                    let elements =
+                        (0..width)
+                            .map(|_| reader.read_clear(pk))
+                            .collect::<Result<Vec<ClearResidue>, ProofError>>()?;
+                    response.push(Response::OpenCapsule(ClearCapsule::new(elements)));
+                }
+                CONSUME_CAPSULE_TAG => {
+                    response.push(Response::ConsumeCapsule(reader.read_clear(pk)?));
+                }
+                _ => return Err(ProofError::InvalidTag(tag)),
+            }
+        }
+        if !reader.is_empty() {
+            return Err(ProofError::TrailingBytes);
+        }
+        return Ok(Self {
+            statement,
+            commitment,
+            challenge,
+            response,
+            hash,
+        });
+    }
+
+    /// The number of bytes that each revealed residue class is written in, taken from the ring
+    /// of the first response
+    fn class_bytes(&self) -> usize {
+        let rc = match self.response.first() {
+            Some(Response::OpenCapsule(capsule)) => capsule.get_elements().first(),
+            Some(Response::ConsumeCapsule(quotient)) => Some(quotient),
+            None => None,
+        };
+        return match rc {
+            Some(residue) if SmallResidueClass::fits(residue.get_rc().params().modulus()) => {
+                SmallResidueClass::BYTES
+            }
+            _ => BigInt::BYTES,
+        };
+    }
+
+    /// The number of elements in each capsule, taken from the first capsule
+    fn capsule_width(&self) -> usize {
+        return self
+            .commitment
+            .first()
+            .map_or(0, |capsule| capsule.get_elements().len());
+    }
+}
+
+/// Write the canonical value of a residue in big-endian with a fixed width
+fn write_residue(bytes: &mut Vec<u8>, residue: &DynResidue<LIMBS>) {
+    bytes.extend_from_slice(&residue.retrieve().to_be_bytes());
+}
+
+/// Write the value, residue class, and witness of a clear residue
+fn write_clear_residue(bytes: &mut Vec<u8>, residue: &ClearResidue) {
+    write_residue(bytes, residue.get_val());
+    match SmallResidueClass::from_class(residue.get_rc()) {
+        Some(small) => bytes.extend_from_slice(&small.to_be_bytes()),
+        None => write_residue(bytes, residue.get_rc()),
+    }
+    write_residue(bytes, residue.get_witness().get_residue());
+}
+
+/// Reads the fields of a serialized proof front to back
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        return Self { bytes };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.bytes.is_empty();
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProofError> {
+        if self.bytes.len() < len {
+            return Err(ProofError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        return Ok(head);
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ProofError> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        return Ok(u32::from_be_bytes(bytes));
+    }
+
+    /// Read a fixed-width residue, which must already be reduced under the modulus
+    fn read_residue(&mut self, modulus: &BigInt) -> Result<BigInt, ProofError> {
+        let val = BigInt::from_be_slice(self.take(BigInt::BYTES)?);
+        if &val >= modulus {
+            return Err(ProofError::OutOfRange);
+        }
+        return Ok(val);
+    }
+
+    fn read_opaque(&mut self, pk: &PublicKey) -> Result<OpaqueResidue, ProofError> {
+        let val = self.read_residue(pk.get_n().modulus_ref())?;
+        return Ok(OpaqueResidue::new(DynResidue::new(
+            &val,
+            *pk.get_n().params(),
+        )));
+    }
+
+    /// Read a residue class, compact if the ring fits in a SmallResidueClass
+    fn read_class(&mut self, r: &RingModulus) -> Result<ResidueClass, ProofError> {
+        if !SmallResidueClass::fits(r.modulus_ref()) {
+            let rc = self.read_residue(r.modulus_ref())?;
+            return Ok(ResidueClass::new(DynResidue::new(&rc, *r.params())));
+        }
+        let bytes: [u8; SmallResidueClass::BYTES] =
+            self.take(SmallResidueClass::BYTES)?.try_into().unwrap();
+        let small = SmallResidueClass::from_be_bytes(bytes);
+        if BigInt::from_u16(small.get()) >= *r.modulus_ref() {
+            return Err(ProofError::OutOfRange);
+        }
+        return Ok(small.to_class(r));
+    }
+
+    fn read_clear(&mut self, pk: &PublicKey) -> Result<ClearResidue, ProofError> {
+        let val = self.read_opaque(pk)?;
+        let rc = self.read_class(pk.get_r())?;
+        let witness = Witness::new(self.read_opaque(pk)?.clone_residue());
+        return Ok(ClearResidue::new(val, rc, witness, pk));
+    }
+}
+
+/// Unpack the first len bits of a sequence of bytes packed by pack_bits
+fn unpack_bits(bytes: &[u8], len: usize) -> Vec<bool> {
+    let mut bits = proofs::challenge_bits(bytes);
+    bits.truncate(len);
+    return bits;
+}
+
+/// Pack a sequence of bits into bytes, with the first bit as the most significant bit of the
+/// first byte. The last byte is padded with zeros.
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    return bits
+        .chunks(8)
+        .map(|chunk| {
+            return chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (j, bit)| byte | ((*bit as u8) << (7 - j)));
+        })
+        .collect();
+}
+
+/// Through the common proof interface, a ballot proof shows that the statement is in RC[0] or
+/// RC[1], which is what a simple election needs
+impl NonInteractiveProof for BallotProof {
+    type Statement = ClearResidue;
+    type Verifier = PublicKey;
+    type Error = Infallible;
+
+    #[cfg(feature = "std")]
+    fn prove(statement: &ClearResidue, pk: &PublicKey) -> Self {
+        return Self::from_statement(statement, &zero_or_one(pk.get_r()), pk);
+    }
+
+    fn verify(&self, pk: &PublicKey) -> Result<bool, Infallible> {
+        return Ok(BallotProof::verify(self, &zero_or_one(pk.get_r()), pk));
+    }
+}
+
+/// Proof that each of several statements belongs to its own set of residue classes, such as a
+/// ballot with multiple independent yes/no questions. In each round, the capsules for the
+/// individual statements are concatenated into a single merged capsule, so the whole compound
+/// ballot is covered by a single challenge. Each statement contributes one element per class of
+/// its own set to a merged capsule, laid out in the same order as the statements.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CompoundBallotProof {
+    pub statements: Vec<OpaqueResidue>,
+
+    pub commitment: Vec<OpaqueCapsule>,
+
+    pub challenge: Vec<bool>,
+
+    pub response: Vec<CompoundResponse>,
+}
+
+impl CompoundBallotProof {
+    /// Produce a proof that each statement is in its own set of residue classes. Panics if a
+    /// statement is in none of its classes; see try_from_statements
+    #[cfg(feature = "std")]
+    pub fn from_statements(
+        statements: &[(&ClearResidue, &[ResidueClass])],
+        pk: &PublicKey,
+    ) -> Self {
+        return Self::try_from_statements(statements, pk)
+            .expect("a statement is in none of its classes");
+    }
+
+    /// Produce a proof that each statement is in its own set of residue classes, or report that
+    /// there is no such proof because some statement is in none of its classes
+    #[cfg(feature = "std")]
+    pub fn try_from_statements(
+        statements: &[(&ClearResidue, &[ResidueClass])],
+        pk: &PublicKey,
+    ) -> Result<Self, ConsumeError> {
+        return Self::try_from_statements_with_rng(statements, pk, &mut OsRng);
+    }
+
+    /// Produce the proof as try_from_statements does, using the supplied source of randomness
+    pub fn try_from_statements_with_rng(
+        statements: &[(&ClearResidue, &[ResidueClass])],
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, ConsumeError> {
+        let answers = (0..CONFIDENCE)
+            .map(|_| {
+                return statements
+                    .iter()
+                    .map(|(_, classes)| ClearCapsule::generate_with_rng(classes, pk, rng))
+                    .collect::<Vec<ClearCapsule>>();
+            })
+            .collect::<Vec<Vec<ClearCapsule>>>();
+        let commitment = answers
+            .iter()
+            .map(|capsules| ClearCapsule::merge(capsules).obscure())
+            .collect::<Vec<OpaqueCapsule>>();
+        let opaque_statements = statements
+            .iter()
+            .map(|(statement, _)| *statement.as_opaque())
+            .collect::<Vec<OpaqueResidue>>();
+        let challenge = BallotProof::generate_challenge(
+            HashAlgorithm::default(),
+            &[],
+            &opaque_statements,
+            &commitment,
+            pk,
+        );
+
+        let mut response = Vec::with_capacity(challenge.len());
+        for (open_capsule, capsules) in challenge.iter().zip(answers.iter()) {
+            if *open_capsule {
+                response.push(CompoundResponse::OpenCapsule(ClearCapsule::merge(capsules)));
+                continue;
+            }
+            let quotients = statements
+                .iter()
+                .zip(capsules.iter())
+                .map(|((statement, _), capsule)| capsule.consume(statement, pk))
+                .collect::<Result<Vec<ClearResidue>, ConsumeError>>()?;
+            response.push(CompoundResponse::ConsumeCapsule(quotients));
+        }
+
+        return Ok(Self {
+            statements: opaque_statements,
+            commitment,
+            challenge,
+            response,
+        });
+    }
+
+    /// Verify a single response against a merged capsule, segment by segment, with the same
+    /// checks as BallotProof::verify_response. An opened segment must match the commitment, each
+    /// of its elements must be a consistent decomposition, and it must reveal exactly the classes
+    /// of its statement; a consumed segment must show, through a consistent decomposition of an
+    /// r-th residue, that the statement has the same residue class as one of its elements.
+    fn verify_response(
+        &self,
+        commitment: &OpaqueCapsule,
+        response: &CompoundResponse,
+        classes: &[&[ResidueClass]],
+        pk: &PublicKey,
+    ) -> bool {
+        match response {
+            CompoundResponse::ConsumeCapsule(quotients) => {
+                if quotients.len() != self.statements.len() {
+                    return false;
+                }
+                let mut offset = 0;
+                for ((statement, quotient), classes) in self
+                    .statements
+                    .iter()
+                    .zip(quotients.iter())
+                    .zip(classes.iter())
+                {
+                    if quotient.get_ambience() != pk
+                        || !quotient.is_exact_residue()
+                        || !quotient.is_consistent()
+                    {
+                        return false;
+                    }
+                    let segment = &commitment.get_elements()[offset..offset + classes.len()];
+                    let reconstructed = statement.clone() * quotient.clone_val();
+                    if !segment.contains(&reconstructed) {
+                        return false;
+                    }
+                    offset += classes.len();
+                }
+                return true;
+            }
+            CompoundResponse::OpenCapsule(open_cap) => {
                 if commitment.get_elements().len() != open_cap.get_elements().len() {
                     return false;
                 }
+                let mut offset = 0;
+                for classes in classes.iter() {
+                    let segment = &open_cap.get_elements()[offset..offset + classes.len()];
+                    if !ClearCapsule::new(segment.to_vec()).reveals_classes(classes) {
+                        return false;
+                    }
+                    offset += classes.len();
+                }
                 return commitment
                     .get_elements()
                     .iter()
                     .zip(open_cap.get_elements().iter())
                     .all(|(commit_elem, open_elem)| {
-                        return commit_elem == open_elem.get_val();
+                        return commit_elem == open_elem.get_val()
+                            && open_elem.get_ambience() == pk
+                            && open_elem.is_consistent();
                     });
             }
         }
     }
 
-    /// Verify the proof
-    pub fn verify(&self) -> bool {
+    /// Verify the proof that each statement belongs to its own set of residue classes, given in
+    /// the same order as the statements, under the public key
+    pub fn verify(&self, classes: &[&[ResidueClass]], pk: &PublicKey) -> bool {
+        if self
+            .statements
+            .iter()
+            .any(|statement| !pk.is_valid_ciphertext(statement))
+        {
+            return false;
+        }
+        if self.statements.len() != classes.len() {
+            return false;
+        }
         if self.commitment.len() != self.challenge.len() {
             return false;
         }
         if self.commitment.len() != self.response.len() {
             return false;
         }
+        let width: usize = classes.iter().map(|classes| classes.len()).sum();
+        if self
+            .commitment
+            .iter()
+            .any(|capsule| capsule.get_elements().len() != width)
+        {
+            return false;
+        }
+        if self.challenge
+            != BallotProof::generate_challenge(
+                HashAlgorithm::default(),
+                &[],
+                &self.statements,
+                &self.commitment,
+                pk,
+            )
+        {
+            return false;
+        }
 
         return self
             .commitment
             .iter()
             .zip(self.response.iter())
             .all(|(commitment, response)| {
-                return Self::verify_response(&self.statement, commitment, response);
+                return self.verify_response(commitment, response, classes, pk);
             });
     }
+
+    /// The soundness of the proof in bits, which is the number of challenge bits, same as for
+    /// BallotProof
+    pub fn soundness_bits(&self) -> usize {
+        return self.challenge.len();
+    }
 }
 
 /// Each closed capsule contains one random element from each of the specified residue
 /// classes, but we don't know which one is which
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct OpaqueCapsule {
     elements: Vec<OpaqueResidue>,
 }
@@ -205,6 +1018,13 @@ impl OpaqueCapsule {
     pub fn get_elements(&self) -> &[OpaqueResidue] {
         return &self.elements;
     }
+
+    /// Merge two capsules into one. The elements of self come before the elements of other
+    pub fn concat(&self, other: &OpaqueCapsule) -> OpaqueCapsule {
+        let mut elements = self.elements.clone();
+        elements.extend_from_slice(&other.elements);
+        return Self::new(elements);
+    }
 }
 
 /// Each opened capsule reveals the residue class that each element belongs to
@@ -222,20 +1042,106 @@ impl ClearCapsule {
         return &self.elements;
     }
 
+    #[cfg(feature = "std")]
     pub fn generate(classes: &[ResidueClass], pk: &PublicKey) -> Self {
-        let mut elements = classes
-            .iter()
-            .map(|rc| ClearResidue::random(Some(rc.clone_residue()), pk))
-            .collect::<Vec<ClearResidue>>();
-        elements.shuffle(&mut OsRng);
+        return Self::generate_with_rng(classes, pk, &mut OsRng);
+    }
+
+    /// Generate the capsule using the supplied source of randomness. Both the elements and the
+    /// order they are shuffled into are drawn from rng, so a seeded rng reproduces the same
+    /// capsule element for element, e.g. for replaying an audit transcript
+    pub fn generate_with_rng(
+        classes: &[ResidueClass],
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        return Self::sample(classes, 0, &ClassPowers::new(classes, pk), rng);
+    }
+
+    /// Generate a capsule with one element per class plus the given number of decoy elements,
+    /// each in a class drawn at random from the claimed classes, all shuffled together.
+    ///
+    /// With decoys, an opened capsule no longer shows exactly one element per class, so the
+    /// capsule does not give away how many classes there are, and the element consumed against
+    /// the statement is one of possibly several elements of its class. The decoys must never be
+    /// drawn from outside the claimed classes: an element of any other class would let a
+    /// cheating prover consume a statement of that class. Each decoy costs one more encryption
+    /// per capsule, i.e. CONFIDENCE more exponentiations for the prover and CONFIDENCE more
+    /// residues in the proof.
+    ///
+    /// Panics if there are decoys but no classes to draw them from
+    #[cfg(feature = "std")]
+    pub fn generate_with_decoys(classes: &[ResidueClass], decoys: usize, pk: &PublicKey) -> Self {
+        return Self::sample(classes, decoys, &ClassPowers::new(classes, pk), &mut OsRng);
+    }
+
+    /// Sample the elements with y ** c for each of the classes taken from the precomputed
+    /// powers, which are shared by all capsules of a proof
+    fn sample(
+        classes: &[ResidueClass],
+        decoys: usize,
+        powers: &ClassPowers,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let mut elements = Vec::with_capacity(classes.len() + decoys);
+        for rc in classes {
+            elements.push(ClearResidue::random_with_powers(
+                rc.clone_residue(),
+                powers,
+                rng,
+            ));
+        }
+        for _ in 0..decoys {
+            let rc = classes.choose(rng).expect("decoys need at least one class");
+            elements.push(ClearResidue::random_with_powers(
+                rc.clone_residue(),
+                powers,
+                rng,
+            ));
+        }
+        elements.shuffle(rng);
         return Self::new(elements);
     }
 
+    /// Merge two capsules into one. The elements of self come before the elements of other, so
+    /// the merged capsule obscures into the concatenation of the two obscured capsules
+    pub fn concat(&self, other: &ClearCapsule) -> ClearCapsule {
+        let mut elements = self.elements.clone();
+        elements.extend_from_slice(&other.elements);
+        return Self::new(elements);
+    }
+
+    /// Merge a non-empty sequence of capsules in order
+    fn merge(capsules: &[ClearCapsule]) -> ClearCapsule {
+        return capsules[1..]
+            .iter()
+            .fold(capsules[0].clone(), |merged, capsule| {
+                merged.concat(capsule)
+            });
+    }
+
+    /// Check that the residue classes of the elements are exactly the given classes, in any
+    /// order: every element is in one of the classes, and every class has at least one element.
+    /// A class has more than one element only if the capsule has decoys.
+    fn reveals_classes(&self, classes: &[ResidueClass]) -> bool {
+        let revealed: Vec<BigInt> = self
+            .elements
+            .iter()
+            .map(|element| element.get_rc().retrieve())
+            .collect();
+        let expected: Vec<BigInt> = classes.iter().map(|rc| rc.retrieve()).collect();
+        return revealed.iter().all(|class| expected.contains(class))
+            && expected.iter().all(|class| revealed.contains(class));
+    }
+
+    /// Hide the residue classes of the elements. The i-th element of the opaque capsule is the
+    /// ciphertext of the i-th element of this capsule, so serializing an obscured capsule is as
+    /// deterministic as the capsule itself
     pub fn obscure(&self) -> OpaqueCapsule {
         let elements = self
             .elements
             .iter()
-            .map(|clear| clear.clone_val())
+            .map(|clear| *clear.as_opaque())
             .collect::<Vec<OpaqueResidue>>();
         return OpaqueCapsule::new(elements);
     }
@@ -245,32 +1151,48 @@ impl ClearCapsule {
     /// elements.
     ///
     /// If two elements w, w' have the same residue class, then w' * w^(-1) is an r-th
-    /// residue. So the returned value will be a decomposition of the value.
-    pub fn consume(&self, statement: &ClearResidue, pk: &PublicKey) -> ClearResidue {
+    /// residue. So the returned value will be a decomposition of the value. If no element has
+    /// the residue class of the statement, there is nothing to show.
+    pub fn consume(
+        &self,
+        statement: &ClearResidue,
+        pk: &PublicKey,
+    ) -> Result<ClearResidue, ConsumeError> {
         for element in self.elements.iter() {
             if element.get_rc() == statement.get_rc() {
                 // there is no straightforward way to invert a clear residue without
                 // the secret key, so we compute the response from the decomposition
                 let witness = element.clone_witness() * (statement.clone_witness().invert());
-                let zero = DynResidue::new(&BigInt::ZERO, pk.get_r().to_dyn_residue_params());
-                return ClearResidue::compose(zero, witness.get_residue().clone(), pk);
+                let zero = DynResidue::new(&BigInt::ZERO, *pk.get_r().params());
+                return Ok(ClearResidue::compose(zero, witness.clone_residue(), pk));
             }
         }
-        panic!("Capsule does not have matching element");
+        return Err(ConsumeError::NoMatchingElement);
     }
 }
 
 /// Depending on whether the capsule is chosen, you either "open the capsule"
 /// and reveal which element belongs to which residue class, or "consume the capsule" and
 /// show the decomposition of (statement / capsule)
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Response {
     OpenCapsule(ClearCapsule),
     ConsumeCapsule(ClearResidue),
 }
 
+/// The response for a merged capsule: either open the whole merged capsule, or consume each
+/// statement's segment of it
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum CompoundResponse {
+    OpenCapsule(ClearCapsule),
+    ConsumeCapsule(Vec<ClearResidue>),
+}
+
 #[cfg(test)]
 mod tests {
-    use crypto_bigint::{rand_core::OsRng, Random};
+    use crypto_bigint::{modular::runtime_mod::DynResidueParams, rand_core::OsRng, Random};
+    use sha3::Digest;
 
     use super::*;
     use crate::keys::KeyPair;
@@ -285,10 +1207,34 @@ mod tests {
         let statement = ClearResidue::random(Some(residue_class), keypair.get_pk());
         let element = ClearResidue::random(Some(residue_class), keypair.get_pk());
         let capsule = ClearCapsule::new(vec![element]);
-        let response = capsule.consume(&statement, keypair.get_pk());
+        let response = capsule.consume(&statement, keypair.get_pk()).unwrap();
         assert!(response.is_exact_residue());
     }
 
+    /// Consuming a capsule without an element in the class of the statement is an error, and so
+    /// is proving a statement that is in none of the classes
+    #[test]
+    fn test_consume_capsule_without_match() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let two = ResidueClass::from_u64(2, pk.get_r());
+        let statement = ClearResidue::random(Some(two.clone_residue()), pk);
+        let capsule = ClearCapsule::generate(&classes, pk);
+        assert_eq!(
+            capsule.consume(&statement, pk),
+            Err(ConsumeError::NoMatchingElement)
+        );
+        assert_eq!(
+            BallotProof::try_from_statement(&statement, &classes, pk),
+            Err(ConsumeError::NoMatchingElement)
+        );
+
+        let one = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let proof = BallotProof::try_from_statement(&one, &classes, pk).unwrap();
+        assert!(proof.verify(&classes, pk));
+    }
+
     /// Test that honest prover can prove to an honest verifier
     #[test]
     fn test_correctness() {
@@ -298,11 +1244,604 @@ mod tests {
             keypair.get_pk().get_r().to_dyn_residue_params(),
         );
         let statement = ClearResidue::random(Some(one), keypair.get_pk());
-        let proof = BallotProof::from_statement(
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let proof = BallotProof::from_statement(&statement, &classes, keypair.get_pk());
+        assert!(proof.verify(&classes, keypair.get_pk()));
+    }
+
+    /// A proof owns its residues together with their moduli, so it can be kept after the key
+    /// pair is dropped and verified on another thread
+    #[test]
+    fn test_proof_outlives_key() {
+        let (proof, classes, pk) = {
+            let keypair = KeyPair::keygen(16, 64, false);
+            let pk = keypair.get_pk();
+            let classes = zero_or_one(pk.get_r());
+            let statement = ClearResidue::random(Some(classes[1].clone_residue()), pk);
+            (
+                BallotProof::from_statement(&statement, &classes, pk),
+                classes,
+                pk.clone(),
+            )
+        };
+        let verified = std::thread::spawn(move || proof.verify(&classes, &pk))
+            .join()
+            .unwrap();
+        assert!(verified);
+    }
+
+    /// A prover that commits to capsules with a class outside of the claimed set is caught when
+    /// the capsules are opened
+    #[test]
+    fn test_reject_off_set_class() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let zero_or_five =
+            [0, 5].map(|class| ResidueClass::from_u64(class, keypair.get_pk().get_r()));
+        let statement =
+            ClearResidue::random(Some(zero_or_five[1].clone_residue()), keypair.get_pk());
+        let proof = BallotProof::from_statement(&statement, &zero_or_five, keypair.get_pk());
+        assert!(proof.verify(&zero_or_five, keypair.get_pk()));
+
+        // a ballot for 0 proven over {0, 5} is not accepted as a proof over {0, 1}
+        let statement =
+            ClearResidue::random(Some(zero_or_five[0].clone_residue()), keypair.get_pk());
+        let proof = BallotProof::from_statement(&statement, &zero_or_five, keypair.get_pk());
+        assert!(!proof.verify(&zero_or_one(keypair.get_pk().get_r()), keypair.get_pk()));
+    }
+
+    /// A consume response that does not reconstruct any element of its capsule is rejected
+    /// rather than crashing the verifier
+    #[test]
+    fn test_reject_tampered_consume_response() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let mut proof = BallotProof::from_statement(&statement, &classes, pk);
+        let consumed = proof.challenge.iter().position(|open| !open).unwrap();
+        let zero = ResidueClass::zero(pk.get_r().to_dyn_residue_params());
+        let forged = ClearResidue::random(Some(zero.clone_residue()), pk);
+        proof.response[consumed] = Response::ConsumeCapsule(forged);
+        assert!(!proof.verify(&classes, pk));
+    }
+
+    #[test]
+    fn test_decoys() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let capsule = ClearCapsule::generate_with_decoys(&classes, 3, pk);
+        assert_eq!(capsule.get_elements().len(), classes.len() + 3);
+        assert!(capsule.reveals_classes(&classes));
+
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let proof = BallotProof::from_statement_with_decoys(&statement, &classes, 3, pk);
+        assert!(proof.verify(&classes, pk));
+    }
+
+    /// Decoys do not let a statement outside of the claimed classes through
+    #[test]
+    fn test_decoys_reject_off_set_class() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let zero_or_five = [0, 5].map(|class| ResidueClass::from_u64(class, pk.get_r()));
+        let statement = ClearResidue::random(Some(zero_or_five[1].clone_residue()), pk);
+        let proof = BallotProof::from_statement_with_decoys(&statement, &zero_or_five, 3, pk);
+        assert!(proof.verify(&zero_or_five, pk));
+        assert!(!proof.verify(&zero_or_one(pk.get_r()), pk));
+    }
+
+    #[test]
+    fn test_proof_dimensions() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let one = DynResidue::new(
+            &BigInt::ONE,
+            keypair.get_pk().get_r().to_dyn_residue_params(),
+        );
+        let statement = ClearResidue::random(Some(one), keypair.get_pk());
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let proof =
+            BallotProof::from_statement_with_decoys(&statement, &classes, 1, keypair.get_pk());
+        assert_eq!(proof.commitment.len(), CONFIDENCE);
+        assert_eq!(proof.response.len(), CONFIDENCE);
+        assert!(proof
+            .commitment
+            .iter()
+            .all(|capsule| capsule.get_elements().len() == classes.len() + 1));
+        assert!(proof.verify(&classes, keypair.get_pk()));
+    }
+
+    #[test]
+    fn test_seeded_capsule_order() {
+        use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+        let keypair = KeyPair::keygen(16, 64, false);
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let first = ClearCapsule::generate_with_rng(
+            &classes,
+            keypair.get_pk(),
+            &mut ChaCha20Rng::seed_from_u64(855),
+        );
+        let second = ClearCapsule::generate_with_rng(
+            &classes,
+            keypair.get_pk(),
+            &mut ChaCha20Rng::seed_from_u64(855),
+        );
+        assert_eq!(first, second);
+        assert_eq!(first.obscure(), second.obscure());
+        for (clear, opaque) in first
+            .get_elements()
+            .iter()
+            .zip(first.obscure().get_elements())
+        {
+            assert_eq!(clear.get_val(), opaque);
+        }
+    }
+
+    #[test]
+    fn test_concat_capsules() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let first = ClearCapsule::generate(&classes, keypair.get_pk());
+        let second = ClearCapsule::generate(&classes[..1], keypair.get_pk());
+        let merged = first.concat(&second);
+        assert_eq!(merged.get_elements().len(), 3);
+        assert_eq!(
+            merged.obscure().get_elements(),
+            first.obscure().concat(&second.obscure()).get_elements()
+        );
+    }
+
+    /// A compound proof verifies iff every statement is in its own set of classes
+    #[test]
+    fn test_compound_proof() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let yes_or_no = zero_or_one(keypair.get_pk().get_r());
+        let candidates =
+            [0, 1, 2].map(|class| ResidueClass::from_u64(class, keypair.get_pk().get_r()));
+        let first = ClearResidue::random(Some(DynResidue::new(&BigInt::ONE, r)), keypair.get_pk());
+        let second = ClearResidue::random(
+            Some(DynResidue::new(&BigInt::from_u8(2), r)),
+            keypair.get_pk(),
+        );
+
+        let mut proof = CompoundBallotProof::from_statements(
+            &[(&first, &yes_or_no), (&second, &candidates)],
+            keypair.get_pk(),
+        );
+        let class_sets: [&[ResidueClass]; 2] = [&yes_or_no, &candidates];
+        assert!(proof.verify(&class_sets, keypair.get_pk()));
+
+        // the class sets are those of the statements, in order
+        assert!(!proof.verify(&[&candidates, &yes_or_no], keypair.get_pk()));
+        assert!(!proof.verify(&[&yes_or_no, &candidates[..2]], keypair.get_pk()));
+
+        // the first statement is mauled by an r-th residue t, and its quotients by t^-1
+        let z = Witness::new(keypair.get_pk().sample_invertible());
+        let t = z.rth_power(keypair.get_pk().get_r());
+        let mut mauled = proof.clone();
+        mauled.statements[0] = proof.statements[0] * t;
+        for response in mauled.response.iter_mut() {
+            if let CompoundResponse::ConsumeCapsule(quotients) = response {
+                quotients[0] = ClearResidue::new(
+                    quotients[0].clone_val() * t.invert(),
+                    quotients[0].clone_rc(),
+                    quotients[0].clone_witness() * z.invert(),
+                    keypair.get_pk(),
+                );
+            }
+        }
+        assert!(!mauled.verify(&class_sets, keypair.get_pk()));
+
+        // the second statement is swapped for a ciphertext outside of its class set
+        let outsider = ClearResidue::random(
+            Some(DynResidue::new(&BigInt::from_u8(3), r)),
+            keypair.get_pk(),
+        );
+        proof.statements[1] = outsider.clone_val();
+        assert!(!proof.verify(&class_sets, keypair.get_pk()));
+        assert_eq!(
+            CompoundBallotProof::try_from_statements(
+                &[(&first, &yes_or_no), (&outsider, &candidates)],
+                keypair.get_pk(),
+            ),
+            Err(ConsumeError::NoMatchingElement)
+        );
+    }
+
+    /// A prover whose second statement is outside of its class set answers every consumed capsule
+    /// with the quotient element / statement, which reconstructs an element of the capsule but
+    /// is not the decomposition of an r-th residue
+    #[test]
+    fn test_compound_proof_cheating_prover() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let yes_or_no = zero_or_one(pk.get_r());
+        let candidates = [0, 1, 2].map(|class| ResidueClass::from_u64(class, pk.get_r()));
+        let first = ClearResidue::random(Some(yes_or_no[1].clone_residue()), pk);
+        let three = ResidueClass::from_u64(3, pk.get_r());
+        let outsider = ClearResidue::random(Some(three.clone_residue()), pk).clone_val();
+
+        let answers = (0..CONFIDENCE)
+            .map(|_| {
+                return [
+                    ClearCapsule::generate(&yes_or_no, pk),
+                    ClearCapsule::generate(&candidates, pk),
+                ];
+            })
+            .collect::<Vec<[ClearCapsule; 2]>>();
+        let commitment = answers
+            .iter()
+            .map(|capsules| ClearCapsule::merge(capsules).obscure())
+            .collect::<Vec<OpaqueCapsule>>();
+        let statements = vec![first.clone_val(), outsider];
+        let challenge = BallotProof::generate_challenge(
+            HashAlgorithm::default(),
+            &[],
+            &statements,
+            &commitment,
+            pk,
+        );
+        let response = challenge
+            .iter()
+            .zip(answers.iter())
+            .map(|(open_capsule, capsules)| {
+                if *open_capsule {
+                    return CompoundResponse::OpenCapsule(ClearCapsule::merge(capsules));
+                }
+                let honest = capsules[0].consume(&first, pk).unwrap();
+                let element = capsules[1].get_elements()[0].clone();
+                let forged = ClearResidue::new(
+                    element.clone_val() * outsider.invert(),
+                    ResidueClass::zero(*pk.get_r().params()),
+                    element.clone_witness(),
+                    pk,
+                );
+                return CompoundResponse::ConsumeCapsule(vec![honest, forged]);
+            })
+            .collect::<Vec<CompoundResponse>>();
+        let proof = CompoundBallotProof {
+            statements,
+            commitment,
+            challenge,
+            response,
+        };
+        assert!(!proof.verify(&[&yes_or_no, &candidates], pk));
+    }
+
+    /// The number of residue classes that the responses of the proof reveal
+    fn revealed_classes(proof: &BallotProof) -> usize {
+        return proof
+            .response
+            .iter()
+            .map(|response| match response {
+                Response::OpenCapsule(capsule) => capsule.get_elements().len(),
+                Response::ConsumeCapsule(_) => 1,
+            })
+            .sum();
+    }
+
+    /// The estimate is exact for opened capsules and over-counts each consumed capsule by the
+    /// residues that consuming it does not reveal. A ring of at most 16 bits also writes every
+    /// revealed class in two bytes instead of a full residue.
+    #[test]
+    fn test_serialized_size() {
+        for (ring_size, class_bytes) in [(16, SmallResidueClass::BYTES), (24, BigInt::BYTES)] {
+            let keypair = KeyPair::keygen(ring_size, 64, false);
+            let pk = keypair.get_pk();
+            let classes = zero_or_one(pk.get_r());
+            let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+            let proof = BallotProof::from_statement(&statement, &classes, pk);
+
+            let serialized_size = proof.serialized_size();
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes.len(), serialized_size);
+            assert_eq!(BallotProof::from_bytes(&bytes, pk).unwrap(), proof);
+
+            let consumed = proof.challenge.iter().filter(|open| !**open).count();
+            let estimate = BallotProof::estimate_size(classes.len(), CONFIDENCE, LIMBS);
+            assert_eq!(
+                estimate - serialized_size,
+                consumed * 3 * (classes.len() - 1) * BigInt::BYTES
+                    + revealed_classes(&proof) * (BigInt::BYTES - class_bytes)
+            );
+        }
+    }
+
+    /// A proof keeps verifying under the hash it was produced with, whatever the default is
+    #[test]
+    fn test_hash_algorithm_round_trip() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let one = DynResidue::new(&BigInt::ONE, pk.get_r().to_dyn_residue_params());
+        let statement = ClearResidue::random(Some(one), pk);
+
+        for hash in [HashAlgorithm::Sha3_256, HashAlgorithm::Shake256] {
+            let proof = BallotProof::from_statement_with_hash(&statement, &classes, hash, pk);
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes[0], hash.id());
+            assert_eq!(bytes.len(), proof.serialized_size());
+            let restored = BallotProof::from_bytes(&bytes, pk).unwrap();
+            assert_eq!(restored, proof);
+            assert!(restored.verify(&classes, pk));
+
+            // the same transcript under the other hash does not verify
+            let mut relabeled = bytes.clone();
+            relabeled[0] = match hash {
+                HashAlgorithm::Sha3_256 => HashAlgorithm::Shake256.id(),
+                HashAlgorithm::Shake256 => HashAlgorithm::Sha3_256.id(),
+            };
+            assert!(!BallotProof::from_bytes(&relabeled, pk)
+                .unwrap()
+                .verify(&classes, pk));
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_malformed() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[1].clone_residue()), pk);
+        let bytes = BallotProof::from_statement(&statement, &classes, pk).to_bytes();
+
+        let mut unknown = bytes.clone();
+        unknown[0] = 0xff;
+        assert_eq!(
+            BallotProof::from_bytes(&unknown, pk),
+            Err(ProofError::UnknownHashAlgorithm(0xff))
+        );
+        assert_eq!(
+            BallotProof::from_bytes(&bytes[..bytes.len() - 1], pk),
+            Err(ProofError::Truncated)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            BallotProof::from_bytes(&trailing, pk),
+            Err(ProofError::TrailingBytes)
+        );
+        let mut out_of_range = bytes.clone();
+        out_of_range[HASH_ID_BYTES..HASH_ID_BYTES + BigInt::BYTES].fill(0xff);
+        assert_eq!(
+            BallotProof::from_bytes(&out_of_range, pk),
+            Err(ProofError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_generate_ballots() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let votes = [1, 0, 1, 1].map(|vote| ResidueClass::from_u64(vote, pk.get_r()));
+        let ballots = generate_ballots(&votes, pk, &classes);
+        assert_eq!(ballots.len(), votes.len());
+        for ((ballot, proof), vote) in ballots.iter().zip(votes.iter()) {
+            assert_eq!(&proof.statement, ballot);
+            assert!(proof.verify(&classes, pk));
+            assert_eq!(&keypair.decrypt(ballot), vote);
+        }
+    }
+
+    #[test]
+    fn test_verify_checked() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let proof = BallotProof::from_statement(&statement, &classes, pk);
+        assert_eq!(proof.verify_checked(&classes, pk), Ok(true));
+        assert_eq!(proof.verify_checked(&[classes[1]], pk), Ok(false));
+
+        let mut short_commitment = proof.clone();
+        short_commitment.commitment.pop();
+        let mut short_challenge = proof.clone();
+        short_challenge.challenge.pop();
+        let mut short_response = proof.clone();
+        short_response.response.pop();
+        for malformed in [short_commitment, short_challenge, short_response] {
+            assert_eq!(
+                malformed.verify_checked(&classes, pk),
+                Err(ProofError::LengthMismatch)
+            );
+            assert!(!malformed.verify(&classes, pk));
+        }
+    }
+
+    #[test]
+    fn test_challenge_bytes() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let proof = BallotProof::from_statement(&statement, &classes, pk);
+        let bytes = proof.challenge_bytes();
+        assert_eq!(bytes.len(), CONFIDENCE / 8);
+
+        let mut restored = proof.clone();
+        restored.set_challenge_from_bytes(&bytes);
+        assert_eq!(restored, proof);
+
+        let mut flipped = bytes.clone();
+        flipped[0] ^= 0b1000_0000;
+        restored.set_challenge_from_bytes(&flipped);
+        assert_ne!(restored.challenge[0], proof.challenge[0]);
+        assert!(!restored.verify(&classes, pk));
+    }
+
+    #[test]
+    fn test_pack_bits() {
+        let bits = [true, false, false, false, false, false, false, true, true];
+        assert_eq!(pack_bits(&bits), vec![0b1000_0001, 0b1000_0000]);
+    }
+
+    /// Three voters with distinct weights; the tally is the weighted sum of the votes
+    #[test]
+    fn test_weighted_ballots() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let weights = [3u64, 5, 7];
+        let votes = [true, false, true];
+        assert!(weights_fit(pk.get_r(), &weights));
+
+        let ballots: Vec<OpaqueResidue> = weights
+            .iter()
+            .zip(votes.iter())
+            .map(|(weight, vote)| {
+                let [for_class, against_class] = zero_or_weight(pk.get_r(), *weight);
+                let class = if *vote { for_class } else { against_class };
+                let ballot = ClearResidue::random(Some(class.clone_residue()), pk);
+                let proof = BallotProof::from_weighted_statement(&ballot, *weight, pk);
+                assert!(proof.verify(&zero_or_weight(pk.get_r(), *weight), pk));
+                return ballot.clone_val();
+            })
+            .collect();
+        let aggregate = ballots[1..]
+            .iter()
+            .fold(ballots[0], |acc, ballot| acc * *ballot);
+        assert_eq!(keypair.decrypt(&aggregate).retrieve(), BigInt::from_u64(10));
+    }
+
+    #[test]
+    fn test_weights_fit() {
+        let r = RingModulus::new(DynResidueParams::new(&BigInt::from_u8(11)));
+        assert!(weights_fit(&r, &[3, 5, 2]));
+        assert!(!weights_fit(&r, &[3, 5, 3]));
+        assert!(!weights_fit(
+            &r,
+            &[u64::MAX, u64::MAX, u64::MAX, u64::MAX, u64::MAX]
+        ));
+    }
+
+    /// Hashing the preimage with SHA3-256 reproduces the challenge bits
+    #[test]
+    fn test_challenge_preimage() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), keypair.get_pk());
+        let proof = BallotProof::from_statement(&statement, &classes, keypair.get_pk());
+        let digest = sha3::Sha3_256::digest(proof.challenge_preimage(keypair.get_pk()));
+        assert!(proof
+            .challenge_preimage(keypair.get_pk())
+            .starts_with(DOMAIN));
+        assert_eq!(pack_bits(&proof.challenge), digest.to_vec());
+    }
+
+    #[test]
+    fn test_commitment_hash() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let proof = BallotProof::from_statement(&statement, &classes, pk);
+        let hash = proof.commitment_hash();
+        assert!(proof.verify_against_commitment_hash(&hash));
+
+        let mut tampered = proof.clone();
+        let mut elements = tampered.commitment[0].get_elements().to_vec();
+        elements.swap(0, 1);
+        tampered.commitment[0] = OpaqueCapsule::new(elements);
+        assert_ne!(tampered.commitment_hash(), hash);
+        assert!(!tampered.verify_against_commitment_hash(&hash));
+    }
+
+    #[test]
+    fn test_context_binding() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), pk);
+        let context = b"election 852/voter 7";
+        let proof = BallotProof::from_statement_with_context(&statement, &classes, pk, context);
+        assert!(proof.verify_with_context(&classes, context, pk));
+        assert!(!proof.verify_with_context(&classes, b"election 853/voter 7", pk));
+        assert!(!proof.verify(&classes, pk));
+
+        let digest = sha3::Sha3_256::digest(proof.challenge_preimage_with_context(context, pk));
+        assert_eq!(pack_bits(&proof.challenge), digest.to_vec());
+    }
+
+    #[test]
+    fn test_soundness_bits() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let statement = ClearResidue::random(Some(classes[0].clone_residue()), keypair.get_pk());
+        let proof = BallotProof::from_statement(&statement, &classes, keypair.get_pk());
+        assert_eq!(proof.soundness_bits(), CONFIDENCE);
+    }
+
+    /// Multiplying a cast ballot by an r-th residue t and every consumed quotient by t^-1 gives a
+    /// new ballot in the same class whose responses are still consistent, so the challenge must
+    /// depend on the statement for the proof not to carry over
+    #[test]
+    fn test_reject_mauled_ballot() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let classes = zero_or_one(pk.get_r());
+        let statement = ClearResidue::random(Some(classes[1].clone_residue()), pk);
+        let proof = BallotProof::from_statement(&statement, &classes, pk);
+
+        let z = Witness::new(pk.sample_invertible());
+        let t = z.rth_power(pk.get_r());
+        let mut mauled = proof.clone();
+        mauled.statement = proof.statement * t;
+        for response in mauled.response.iter_mut() {
+            if let Response::ConsumeCapsule(quotient) = response {
+                *quotient = ClearResidue::new(
+                    quotient.clone_val() * t.invert(),
+                    quotient.clone_rc(),
+                    quotient.clone_witness() * z.invert(),
+                    pk,
+                );
+            }
+        }
+        assert!(mauled.response.iter().all(|response| match response {
+            Response::ConsumeCapsule(quotient) => quotient.is_consistent(),
+            Response::OpenCapsule(_) => true,
+        }));
+        assert!(proof.verify(&classes, pk));
+        assert!(!mauled.verify(&classes, pk));
+    }
+
+    /// A statement that is not invertible should be rejected before any capsule is checked
+    #[test]
+    fn test_reject_invalid_statement() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let one = DynResidue::new(
+            &BigInt::ONE,
+            keypair.get_pk().get_r().to_dyn_residue_params(),
+        );
+        let statement = ClearResidue::random(Some(one), keypair.get_pk());
+        let mut proof = BallotProof::from_statement(
             &statement,
             &zero_or_one(keypair.get_pk().get_r()),
             keypair.get_pk(),
         );
-        assert!(proof.verify());
+        proof.statement = OpaqueResidue::new(DynResidue::new(
+            &BigInt::ZERO,
+            keypair.get_pk().get_n().to_dyn_residue_params(),
+        ));
+        assert!(!proof.verify(&zero_or_one(keypair.get_pk().get_r()), keypair.get_pk()));
+    }
+
+    /// A proof whose responses decompose under another y is rejected, even if n and r match
+    #[test]
+    fn test_reject_foreign_key() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let mut foreign = keypair.clone();
+        foreign.rotate_y();
+        let classes = zero_or_one(keypair.get_pk().get_r());
+        let statement = ClearResidue::random(Some(classes[1].clone_residue()), foreign.get_pk());
+        let proof = BallotProof::from_statement(&statement, &classes, foreign.get_pk());
+        assert!(proof.verify(&classes, foreign.get_pk()));
+        assert!(!proof.verify(&classes, keypair.get_pk()));
+
+        let proof =
+            CompoundBallotProof::from_statements(&[(&statement, &classes[..])], foreign.get_pk());
+        assert!(proof.verify(&[&classes], foreign.get_pk()));
+        assert!(!proof.verify(&[&classes], keypair.get_pk()));
     }
 }
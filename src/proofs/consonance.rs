@@ -4,12 +4,29 @@
 //! 3. Government validates the proof; if the proof is invalid, the procedure is aborted
 //! 4. Government decomposes the challenge ciphertext and returns the residue class
 //! 5. Voter validates that the returned residue class matches the true residue class
+#[cfg(feature = "serde")]
+use crate::proofs::wire::{self, KeySeed};
 use crate::{
     arithmetics::{ClearResidue, OpaqueResidue, ResidueClass},
     keys::{KeyPair, PublicKey},
+    proofs::{self, NonInteractiveProof},
+    BigInt,
 };
-use crypto_bigint::Encoding;
-use sha3::{Digest, Sha3_256};
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use alloc::{string::String, vec};
+use core::convert::Infallible;
+#[cfg(feature = "serde")]
+use serde::{
+    de::{self, DeserializeSeed},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/voter-pok/v2";
+
+/// The domain tag that the Fiat-Shamir hash of a VoterProofSet starts with
+pub(crate) const SET_DOMAIN: &[u8] = b"benaloh/voter-pok-set/v2";
 
 /// The voter's copy of the challenge with answers included
 pub struct ClearChallenge {
@@ -24,24 +41,22 @@ impl ClearChallenge {
     /// Generate a random set of challenge ciphertexts. The number of challenge ciphertexts is
     /// determined by the confidence parameter. Higher confidence parameter means more
     /// challenge ciphertext will be generated.
+    ///
+    /// The classes of the challenges are independent and uniform in Z/r. If (r, n, y) is not a
+    /// perfect consonance, then with r prime there is only one residue class, and the
+    /// government can only guess each class with probability 1/r. A government that identifies
+    /// every challenge is therefore honest except with probability (1/r) ** confidence.
+    #[cfg(feature = "std")]
     pub fn generate(pk: &PublicKey, confidence: usize) -> Self {
-        let answers = (0..confidence)
-            .map(|_| {
-                return ClearResidue::random(None, pk);
-            })
-            .collect::<Vec<ClearResidue>>();
-        let challenges = answers
-            .iter()
-            .map(|clear| {
-                return OpaqueResidue::new(clear.get_val().get_residue().clone());
-            })
-            .collect::<Vec<OpaqueResidue>>();
-        let proofs = answers
-            .iter()
-            .map(|clear| {
-                return VoterProof::from_statement(&clear, pk);
-            })
-            .collect::<Vec<VoterProof>>();
+        let mut answers = Vec::with_capacity(confidence);
+        let mut challenges = Vec::with_capacity(confidence);
+        let mut proofs = Vec::with_capacity(confidence);
+        for _ in 0..confidence {
+            let answer = ClearResidue::random(None, pk);
+            challenges.push(*answer.as_opaque());
+            proofs.push(VoterProof::from_statement(&answer, pk));
+            answers.push(answer);
+        }
         return Self {
             challenges,
             answers,
@@ -53,9 +68,12 @@ impl ClearChallenge {
         return &self.answers;
     }
 
+    /// Check that the government correctly identified the residue class of every challenge
+    /// ciphertext. A government that refused to respond (because it found some of the voter's
+    /// proofs invalid) has not demonstrated anything, so its proof does not verify.
     pub fn verify_gov_proof(&self, gov_proof: &GovernmentProof) -> bool {
         match &gov_proof.response {
-            None => todo!("How to verify if proof fails?"),
+            None => return false,
             Some(decryptions) => {
                 if decryptions.len() != self.answers.len() {
                     return false;
@@ -95,11 +113,48 @@ impl OpaqueChallenge {
         return &self.challenges;
     }
 
-    /// Return True iff all of the proofs can be verified
+    /// Return True iff there is one proof per challenge ciphertext, each proof is about the
+    /// challenge ciphertext at its index, and all of the proofs can be verified. Otherwise the
+    /// government would decrypt ciphertexts that the voter has not proven knowledge of.
     pub fn verify_proofs(&self, keypair: &KeyPair) -> bool {
-        return self.proofs.iter().all(|proof| {
-            return proof.verify(&keypair);
-        });
+        if self.challenges.len() != self.proofs.len() {
+            return false;
+        }
+        let context = VerifierContext::new(keypair);
+        return self
+            .challenges
+            .iter()
+            .zip(self.proofs.iter())
+            .all(|(challenge, proof)| {
+                return proof.statement == *challenge && context.verify(proof);
+            });
+    }
+
+    /// Verify every proof and return the result of each, in the order of the proofs. Unlike
+    /// verify_proofs, this does not stop at the first proof that fails. A proof fails if it is
+    /// not about the challenge ciphertext at its index.
+    pub fn verify_proofs_detailed(&self, keypair: &KeyPair) -> Vec<bool> {
+        let context = VerifierContext::new(keypair);
+        return self
+            .proofs
+            .iter()
+            .enumerate()
+            .map(|(i, proof)| {
+                return self.challenges.get(i) == Some(&proof.statement) && context.verify(proof);
+            })
+            .collect();
+    }
+
+    /// Return the indices of the proofs that do not verify, e.g. to identify the voter who
+    /// misbehaved during the consonance challenge
+    pub fn failing_indices(&self, keypair: &KeyPair) -> Vec<usize> {
+        return self
+            .verify_proofs_detailed(keypair)
+            .iter()
+            .enumerate()
+            .filter(|(_, verified)| !**verified)
+            .map(|(i, _)| i)
+            .collect();
     }
 }
 
@@ -117,9 +172,8 @@ pub struct VoterProof {
     /// The opaque residue that the voter claims to know the residue class of
     statement: OpaqueResidue,
 
-    /// The opaque residue that is used as the commitment
-    /// TODO: convert to Vec<OpaqueResidue> to allow a commitment that contains multiple values for
-    /// better confidence
+    /// The opaque residue that is used as the commitment. See VoterProofSet for a proof with
+    /// many commitments for better confidence
     commitment: OpaqueResidue,
 
     challenge: ResidueClass,
@@ -131,57 +185,281 @@ impl VoterProof {
     /// Construct the proof from the statement. Note that an honest prover should possess the
     /// secret knowledge (of the residue class), so even though the output of the proof will
     /// obscure the residue class of the statement, the construction requires a clear residue.
+    ///
+    /// This is the three-move interactive proof with the verifier's challenge replaced by the
+    /// hash of the commitment.
+    #[cfg(feature = "std")]
     pub fn from_statement(statement: &ClearResidue, pk: &PublicKey) -> Self {
-        let commitment = Self::generate_commitment(pk);
+        let (commitment, secret) = Self::commit(statement, pk);
         let challenge = Self::generate_challenge(commitment.get_val(), pk);
-        let response = Self::respond(statement, &commitment, &challenge);
+        let response = Self::respond(secret, &challenge);
         return Self {
-            statement: statement.clone_val(),
+            statement: *statement.as_opaque(),
             commitment: commitment.clone_val(),
             challenge,
             response,
         };
     }
 
-    /// Generate the opaque residue as the commitment. This method is called by the prover, so
-    /// the prover knows the residue class of the commitment. The transcript (the proof
-    /// struct itself) will not reveal the residue class of the commitment
-    fn generate_commitment(pk: &PublicKey) -> ClearResidue {
-        return ClearResidue::random(None, pk);
+    /// The first move of the interactive proof. The prover generates a random commitment and
+    /// sends the opaque commitment to the verifier, while keeping the residue classes of the
+    /// statement and of the commitment to itself.
+    #[cfg(feature = "std")]
+    pub fn commit(statement: &ClearResidue, pk: &PublicKey) -> (VoterCommitment, VoterSecret) {
+        let commitment = ClearResidue::random(None, pk);
+        let secret = VoterSecret {
+            statement: statement.clone_rc(),
+            commitment: commitment.clone_rc(),
+        };
+        return (VoterCommitment::new(commitment.into_opaque()), secret);
+    }
+
+    /// Return exactly the bytes that are hashed into the challenge: the domain tag followed by
+    /// the canonical big-endian value of the commitment. The challenge is derived from these
+    /// bytes with SHAKE256 by rejection sampling, so that it is uniform over Z/r.
+    pub fn challenge_preimage(&self) -> Vec<u8> {
+        let mut preimage = DOMAIN.to_vec();
+        preimage.extend_from_slice(&self.commitment.to_be_bytes());
+        return preimage;
     }
 
     /// Hash the commitment into the challenge
     fn generate_challenge(commitment: &OpaqueResidue, pk: &PublicKey) -> ResidueClass {
-        let mut hasher = Sha3_256::new();
-        hasher.update(commitment.retrieve().to_be_bytes());
-        let hash: Vec<u8> = hasher.finalize().to_vec();
-        let challenge = ResidueClass::from_be_bytes(&hash, pk.get_r());
-        return challenge;
-    }
-
-    /// Compute the response based on the statement, commitment, and the challenge
-    /// The response takes the form (c' + b * c)
-    fn respond(
-        statement: &ClearResidue,
-        commitment: &ClearResidue,
-        challenge: &ResidueClass,
-    ) -> ResidueClass {
-        return statement.clone_rc() * challenge.clone() + commitment.clone_rc();
+        return proofs::challenge_class(DOMAIN, &commitment.to_be_bytes(), pk.get_r());
+    }
+
+    /// The third move of the interactive proof. Compute the response to the verifier's
+    /// challenge, which takes the form (c' + b * c). The secret is consumed because answering
+    /// two different challenges under the same commitment reveals the residue class c.
+    pub fn respond(secret: VoterSecret, challenge: &ResidueClass) -> ResidueClass {
+        return secret.statement * challenge.clone() + secret.commitment;
     }
 
-    /// Given a public transcript of the proof, check whether the proof is valid
-    /// Verification uses the the fact that if the prover is honest, then the following
-    /// quantity is an r-th residue
+    /// Check a transcript of the interactive proof, where the challenge was chosen by the
+    /// verifier. If the prover is honest, then the following quantity is an r-th residue
     /// v = (commit * statement ** challenge) * ((y ** -1) ** response)
     /// To verify something to be an r-th residue, the secret key is needed, which is okay
-    /// because the government indeed has the secret key
+    /// because the government indeed has the secret key. A statement or commitment that is not
+    /// a ciphertext under the key is rejected.
+    pub fn verify_interactive(
+        statement: &OpaqueResidue,
+        commitment: &VoterCommitment,
+        challenge: &ResidueClass,
+        response: &ResidueClass,
+        keypair: &KeyPair,
+    ) -> bool {
+        let pk = keypair.get_pk();
+        if !pk.is_valid_ciphertext(statement) || !pk.is_valid_ciphertext(commitment.get_val()) {
+            return false;
+        }
+        // z should be an r-th residue
+        let z = commitment.clone_val() * statement.pow(challenge) * pk.invert_y().pow(response);
+        return keypair.is_rth_residue(&z);
+    }
+
+    /// Given a public transcript of the proof, check whether the proof is valid. The challenge
+    /// must be the hash of the commitment, after which the transcript is checked the same way
+    /// as an interactive transcript.
+    pub fn verify(&self, keypair: &KeyPair) -> bool {
+        if Self::generate_challenge(&self.commitment, keypair.get_pk()) != self.challenge {
+            return false;
+        }
+        return Self::verify_interactive(
+            &self.statement,
+            &VoterCommitment::new(self.commitment),
+            &self.challenge,
+            &self.response,
+            keypair,
+        );
+    }
+
+    /// The soundness of the proof in bits. The challenge is drawn from Z/r, so a cheating prover
+    /// succeeds with probability 1/r, which is at most 2 ** -soundness_bits.
+    pub fn soundness_bits(&self) -> usize {
+        return proofs::ring_soundness_bits(self.challenge.params().modulus());
+    }
+}
+
+/// The voter's proof of knowledge repeated over several rounds, each with its own independent
+/// commitment. A single round has soundness error 1/r; with n rounds a cheating prover must
+/// answer every round, so the soundness error is (1/r) ** n, e.g. 2 ** -120 for 8 rounds over a
+/// 16-bit r.
+///
+/// The challenges of all rounds are derived from one hash over all of the commitments, so a
+/// cheating prover cannot grind the rounds one at a time
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct VoterProofSet {
+    statement: OpaqueResidue,
+    commitments: Vec<OpaqueResidue>,
+    challenges: Vec<ResidueClass>,
+    responses: Vec<ResidueClass>,
+}
+
+impl VoterProofSet {
+    /// Run the given number of rounds of the voter's proof on the statement
+    #[cfg(feature = "std")]
+    pub fn from_statement(statement: &ClearResidue, rounds: usize, pk: &PublicKey) -> Self {
+        let mut commitments = Vec::with_capacity(rounds);
+        let mut secrets = Vec::with_capacity(rounds);
+        for _ in 0..rounds {
+            let (commitment, secret) = VoterProof::commit(statement, pk);
+            commitments.push(commitment.clone_val());
+            secrets.push(secret);
+        }
+        let challenges = Self::generate_challenges(&commitments, pk);
+        let responses = secrets
+            .into_iter()
+            .zip(challenges.iter())
+            .map(|(secret, challenge)| VoterProof::respond(secret, challenge))
+            .collect::<Vec<ResidueClass>>();
+        return Self {
+            statement: *statement.as_opaque(),
+            commitments,
+            challenges,
+            responses,
+        };
+    }
+
+    /// The challenge of round i is the hash of all commitments followed by i, expanded into a
+    /// uniform class (mod r)
+    fn generate_challenges(commitments: &[OpaqueResidue], pk: &PublicKey) -> Vec<ResidueClass> {
+        let mut preimage = Vec::new();
+        for commitment in commitments {
+            preimage.extend_from_slice(&commitment.to_be_bytes());
+        }
+        return (0..commitments.len())
+            .map(|round| {
+                let mut round_preimage = preimage.clone();
+                round_preimage.extend_from_slice(&(round as u32).to_be_bytes());
+                return proofs::challenge_class(SET_DOMAIN, &round_preimage, pk.get_r());
+            })
+            .collect();
+    }
+
+    /// Check that the challenges are the hash of the commitments and that every round verifies
     pub fn verify(&self, keypair: &KeyPair) -> bool {
+        if self.commitments.is_empty()
+            || self.responses.len() != self.commitments.len()
+            || Self::generate_challenges(&self.commitments, keypair.get_pk()) != self.challenges
+        {
+            return false;
+        }
+        return self
+            .commitments
+            .iter()
+            .zip(self.challenges.iter())
+            .zip(self.responses.iter())
+            .all(|((commitment, challenge), response)| {
+                VoterProof::verify_interactive(
+                    &self.statement,
+                    &VoterCommitment::new(*commitment),
+                    challenge,
+                    response,
+                    keypair,
+                )
+            });
+    }
+
+    /// The number of rounds
+    pub fn rounds(&self) -> usize {
+        return self.commitments.len();
+    }
+
+    /// The soundness of the proof in bits: each round contributes the soundness of a single
+    /// VoterProof
+    pub fn soundness_bits(&self) -> usize {
+        return match self.challenges.first() {
+            Some(challenge) => {
+                self.rounds() * proofs::ring_soundness_bits(challenge.params().modulus())
+            }
+            None => 0,
+        };
+    }
+}
+
+/// The prover's first message in the voter's proof: the commitment w' = (y ** c')(x' ** r)
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct VoterCommitment(OpaqueResidue);
+
+impl VoterCommitment {
+    pub fn new(commitment: OpaqueResidue) -> Self {
+        return Self(commitment);
+    }
+
+    /// Return a reference to the committed value
+    pub fn get_val(&self) -> &OpaqueResidue {
+        return &self.0;
+    }
+
+    /// Clone the committed value
+    pub fn clone_val(&self) -> OpaqueResidue {
+        return self.0.clone();
+    }
+}
+
+/// The prover's private state between the commitment and the response: the residue classes of
+/// the statement and of the commitment. This must never be sent to the verifier.
+#[derive(Debug)]
+pub struct VoterSecret {
+    statement: ResidueClass,
+    commitment: ResidueClass,
+}
+
+/// The voter's proof can only be verified by the government, who holds the secret key
+impl NonInteractiveProof for VoterProof {
+    type Statement = ClearResidue;
+    type Verifier = KeyPair;
+    type Error = Infallible;
+
+    #[cfg(feature = "std")]
+    fn prove(statement: &ClearResidue, pk: &PublicKey) -> Self {
+        return Self::from_statement(statement, pk);
+    }
+
+    fn verify(&self, keypair: &KeyPair) -> Result<bool, Infallible> {
+        return Ok(VoterProof::verify(self, keypair));
+    }
+}
+
+/// The government's state for verifying many voter proofs under the same key pair. The exponent
+/// phi/r is computed once instead of once per proof, and the r-th residue check is a single
+/// exponentiation instead of a full decomposition.
+///
+/// The context borrows the key pair instead of keeping a copy, so the secret key cannot outlive
+/// the verification or be carried around with the context. Like every type that can reach the
+/// secret key, it does not implement Serialize.
+#[derive(Debug, Clone)]
+pub struct VerifierContext<'a> {
+    keypair: &'a KeyPair,
+    phi_over_r: BigInt,
+}
+
+impl<'a> VerifierContext<'a> {
+    pub fn new(keypair: &'a KeyPair) -> Self {
+        return Self {
+            keypair,
+            phi_over_r: keypair.phi_over_r(),
+        };
+    }
+
+    pub fn get_keypair(&self) -> &'a KeyPair {
+        return self.keypair;
+    }
+
+    /// Equivalent to VoterProof::verify under the key pair of this context
+    pub fn verify(&self, proof: &VoterProof) -> bool {
+        let pk = self.keypair.get_pk();
+        if !pk.is_valid_ciphertext(&proof.statement) || !pk.is_valid_ciphertext(&proof.commitment) {
+            return false;
+        }
+        if VoterProof::generate_challenge(&proof.commitment, pk) != proof.challenge {
+            return false;
+        }
         // z should be an r-th residue
-        let z = self.commitment
-            * self.statement.pow(&self.challenge)
-            * keypair.get_pk().invert_y().pow(&self.response);
-        let z = ClearResidue::decompose(z.clone_residue(), keypair);
-        return z.is_exact_residue();
+        let z = proof.commitment
+            * proof.statement.pow(&proof.challenge)
+            * pk.invert_y().pow(&proof.response);
+        return z.get_residue().pow(&self.phi_over_r).retrieve() == BigInt::ONE;
     }
 }
 
@@ -223,10 +501,208 @@ impl GovernmentProof {
     }
 }
 
+/// The wire format of a VoterProof, with every residue as fixed-width hex
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct VoterProofWire {
+    statement: String,
+    commitment: String,
+    challenge: String,
+    response: String,
+}
+
+#[cfg(feature = "serde")]
+impl VoterProofWire {
+    fn new(proof: &VoterProof) -> Self {
+        return Self {
+            statement: wire::residue_to_hex(proof.statement.get_residue()),
+            commitment: wire::residue_to_hex(proof.commitment.get_residue()),
+            challenge: wire::residue_to_hex(proof.challenge.get_residue()),
+            response: wire::residue_to_hex(proof.response.get_residue()),
+        };
+    }
+
+    fn into_proof<E: de::Error>(self, pk: &PublicKey) -> Result<VoterProof, E> {
+        let n = pk.get_n().params();
+        let r = pk.get_r().params();
+        return Ok(VoterProof {
+            statement: OpaqueResidue::new(wire::residue_from_hex(&self.statement, n)?),
+            commitment: OpaqueResidue::new(wire::residue_from_hex(&self.commitment, n)?),
+            challenge: ResidueClass::new(wire::residue_from_hex(&self.challenge, r)?),
+            response: ResidueClass::new(wire::residue_from_hex(&self.response, r)?),
+        });
+    }
+}
+
+/// The wire format of an OpaqueChallenge: one voter's proof for each challenge ciphertext
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct OpaqueChallengeWire {
+    challenges: Vec<String>,
+    proofs: Vec<VoterProofWire>,
+}
+
+#[cfg(feature = "serde")]
+impl OpaqueChallengeWire {
+    fn new(challenge: &OpaqueChallenge) -> Self {
+        return Self {
+            challenges: challenge
+                .challenges
+                .iter()
+                .map(|opaque| wire::residue_to_hex(opaque.get_residue()))
+                .collect(),
+            proofs: challenge.proofs.iter().map(VoterProofWire::new).collect(),
+        };
+    }
+
+    fn into_challenge<E: de::Error>(self, pk: &PublicKey) -> Result<OpaqueChallenge, E> {
+        if self.challenges.len() != self.proofs.len() {
+            return Err(E::custom(proofs::ProofError::LengthMismatch));
+        }
+        let mut challenges = Vec::with_capacity(self.challenges.len());
+        for hex in &self.challenges {
+            let challenge = wire::residue_from_hex(hex, pk.get_n().params())?;
+            challenges.push(OpaqueResidue::new(challenge));
+        }
+        let mut proofs = Vec::with_capacity(self.proofs.len());
+        for (i, proof) in self.proofs.into_iter().enumerate() {
+            let proof = proof.into_proof(pk)?;
+            if proof.statement != challenges[i] {
+                return Err(E::custom(proofs::ProofError::StatementMismatch(i)));
+            }
+            proofs.push(proof);
+        }
+        return Ok(OpaqueChallenge::new(challenges, proofs));
+    }
+}
+
+/// The wire format of a decomposed challenge ciphertext in the government's response
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ClearResidueWire {
+    val: String,
+    rc: String,
+    witness: String,
+}
+
+/// The wire format of a GovernmentProof. The statement is the public key, which is transmitted
+/// separately, so it is left out.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct GovernmentProofWire {
+    challenge: OpaqueChallengeWire,
+    response: Option<Vec<ClearResidueWire>>,
+}
+
+#[cfg(feature = "serde")]
+impl GovernmentProofWire {
+    fn new(proof: &GovernmentProof) -> Self {
+        let response = proof.response.as_ref().map(|answers| {
+            return answers
+                .iter()
+                .map(|answer| ClearResidueWire {
+                    val: wire::residue_to_hex(answer.get_val().get_residue()),
+                    rc: wire::residue_to_hex(answer.get_rc().get_residue()),
+                    witness: wire::residue_to_hex(answer.get_witness().get_residue()),
+                })
+                .collect();
+        });
+        return Self {
+            challenge: OpaqueChallengeWire::new(&proof.challenge),
+            response,
+        };
+    }
+
+    fn into_proof<E: de::Error>(self, pk: &PublicKey) -> Result<GovernmentProof, E> {
+        let challenge = self.challenge.into_challenge(pk)?;
+        let response = match self.response {
+            None => None,
+            Some(answers) => {
+                if answers.len() != challenge.get_challenges().len() {
+                    return Err(E::custom(proofs::ProofError::LengthMismatch));
+                }
+                let n = pk.get_n().params();
+                let r = pk.get_r().params();
+                let mut decoded = vec![];
+                for answer in answers {
+                    decoded.push(ClearResidue::new(
+                        OpaqueResidue::new(wire::residue_from_hex(&answer.val, n)?),
+                        ResidueClass::new(wire::residue_from_hex(&answer.rc, r)?),
+                        crate::arithmetics::Witness::new(wire::residue_from_hex(
+                            &answer.witness,
+                            n,
+                        )?),
+                        pk,
+                    ));
+                }
+                Some(decoded)
+            }
+        };
+        return Ok(GovernmentProof::new(pk.clone(), challenge, response));
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for VoterProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return VoterProofWire::new(self).serialize(serializer);
+    }
+}
+
+/// Deserializing a voter's proof checks the encoding of every residue; verifying the proof is
+/// left to the government
+#[cfg(feature = "serde")]
+impl<'de> DeserializeSeed<'de> for KeySeed<'_, VoterProof> {
+    type Value = VoterProof;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<VoterProof, D::Error> {
+        return VoterProofWire::deserialize(deserializer)?.into_proof(self.get_pk());
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for OpaqueChallenge {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return OpaqueChallengeWire::new(self).serialize(serializer);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> DeserializeSeed<'de> for KeySeed<'_, OpaqueChallenge> {
+    type Value = OpaqueChallenge;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<OpaqueChallenge, D::Error> {
+        return OpaqueChallengeWire::deserialize(deserializer)?.into_challenge(self.get_pk());
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for GovernmentProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return GovernmentProofWire::new(self).serialize(serializer);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> DeserializeSeed<'de> for KeySeed<'_, GovernmentProof> {
+    type Value = GovernmentProof;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<GovernmentProof, D::Error> {
+        return GovernmentProofWire::deserialize(deserializer)?.into_proof(self.get_pk());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{GROUPSIZE, RINGSIZE};
+    use crypto_bigint::modular::runtime_mod::DynResidue;
 
     #[test]
     fn test_verify_proofs() {
@@ -236,6 +712,187 @@ mod tests {
         assert!(opaque_challenge.verify_proofs(&keypair));
     }
 
+    #[test]
+    fn test_verify_proofs_detailed() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let mut opaque_challenge = ClearChallenge::generate(keypair.get_pk(), 8).obscure();
+        assert_eq!(
+            opaque_challenge.verify_proofs_detailed(&keypair),
+            vec![true; 8]
+        );
+        assert!(opaque_challenge.failing_indices(&keypair).is_empty());
+
+        opaque_challenge.proofs[5].response =
+            opaque_challenge.proofs[5].response + ResidueClass::one(r);
+        let detailed = opaque_challenge.verify_proofs_detailed(&keypair);
+        assert_eq!(detailed.len(), 8);
+        assert!(!detailed[5]);
+        assert_eq!(opaque_challenge.failing_indices(&keypair), vec![5]);
+        assert!(!opaque_challenge.verify_proofs(&keypair));
+    }
+
+    /// A challenge ciphertext sent alongside a valid proof about another ciphertext is not
+    /// decrypted, or else the government would be a decryption oracle
+    #[test]
+    fn test_verify_proofs_statement_mismatch() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let mut opaque_challenge = ClearChallenge::generate(keypair.get_pk(), 4).obscure();
+        let target = ClearResidue::random(None, keypair.get_pk());
+        opaque_challenge.challenges[2] = target.clone_val();
+        assert!(opaque_challenge
+            .proofs
+            .iter()
+            .all(|proof| proof.verify(&keypair)));
+        assert!(!opaque_challenge.verify_proofs(&keypair));
+        assert_eq!(opaque_challenge.failing_indices(&keypair), vec![2]);
+        assert!(GovernmentProof::respond(&opaque_challenge, &keypair)
+            .response
+            .is_none());
+
+        let mut missing = ClearChallenge::generate(keypair.get_pk(), 4).obscure();
+        missing.challenges.pop();
+        assert!(!missing.verify_proofs(&keypair));
+    }
+
+    /// The context accepts and rejects exactly the proofs that the stateless verify does
+    #[test]
+    fn test_verifier_context() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let context = VerifierContext::new(&keypair);
+        assert!(core::ptr::eq(context.get_keypair(), &keypair));
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        for _ in 0..4 {
+            let statement = ClearResidue::random(None, keypair.get_pk());
+            let proof = VoterProof::from_statement(&statement, keypair.get_pk());
+            assert!(context.verify(&proof));
+            assert_eq!(context.verify(&proof), proof.verify(&keypair));
+
+            let mut tampered = proof;
+            tampered.response = tampered.response + ResidueClass::one(r);
+            assert!(!context.verify(&tampered));
+            assert_eq!(context.verify(&tampered), tampered.verify(&keypair));
+
+            let mut rechallenged = proof;
+            rechallenged.challenge = rechallenged.challenge + ResidueClass::one(r);
+            assert!(!context.verify(&rechallenged));
+        }
+    }
+
+    #[test]
+    fn test_voter_proof_set() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let proof = VoterProofSet::from_statement(&statement, 8, keypair.get_pk());
+        assert_eq!(proof.rounds(), 8);
+        assert!(proof.verify(&keypair));
+        assert_eq!(
+            proof.soundness_bits(),
+            8 * proofs::ring_soundness_bits(keypair.get_pk().get_r().modulus())
+        );
+
+        // each challenge is expanded from the commitments with SHAKE256, not a reduced digest
+        let mut preimage = Vec::new();
+        for commitment in proof.commitments.iter() {
+            preimage.extend_from_slice(&commitment.to_be_bytes());
+        }
+        preimage.extend_from_slice(&0u32.to_be_bytes());
+        assert_eq!(
+            proofs::challenge_class(SET_DOMAIN, &preimage, keypair.get_pk().get_r()),
+            proof.challenges[0]
+        );
+
+        let mut tampered = proof.clone();
+        tampered.responses[3] = tampered.responses[3] + tampered.challenges[3];
+        assert!(!tampered.verify(&keypair));
+
+        let mut dropped = proof.clone();
+        dropped.commitments.pop();
+        assert!(!dropped.verify(&keypair));
+    }
+
+    /// Send the challenge to the government and the response back as JSON
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let voter_challenge = ClearChallenge::generate(pk, 2);
+
+        let proof = voter_challenge.proofs[0];
+        let json = serde_json::to_string(&proof).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let received = KeySeed::<VoterProof>::new(pk)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(received, proof);
+
+        let opaque = voter_challenge.obscure();
+        let json = serde_json::to_string(&opaque).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let received = KeySeed::<OpaqueChallenge>::new(pk)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(received, opaque);
+
+        let gov_proof = GovernmentProof::respond(&received, &keypair);
+        let json = serde_json::to_string(&gov_proof).unwrap();
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let received = KeySeed::<GovernmentProof>::new(pk)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(received.challenge, gov_proof.challenge);
+        assert!(voter_challenge.verify_gov_proof(&received));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_malformed() {
+        use crypto_bigint::Encoding;
+
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let opaque = ClearChallenge::generate(pk, 2).obscure();
+        let json = serde_json::to_value(&opaque).unwrap();
+
+        let mut dropped = json.clone();
+        dropped["proofs"].as_array_mut().unwrap().pop();
+        assert!(KeySeed::<OpaqueChallenge>::new(pk)
+            .deserialize(dropped)
+            .is_err());
+
+        // a valid ciphertext, but not the statement of the proof at its index
+        let mut swapped = json.clone();
+        swapped["challenges"][0] = json["challenges"][1].clone();
+        let err = KeySeed::<OpaqueChallenge>::new(pk)
+            .deserialize(swapped)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            proofs::ProofError::StatementMismatch(0).to_string()
+        );
+
+        let mut short = json.clone();
+        short["challenges"][0] = serde_json::Value::from("01");
+        assert!(KeySeed::<OpaqueChallenge>::new(pk)
+            .deserialize(short)
+            .is_err());
+
+        // n itself is not reduced under n
+        let mut unreduced = json.clone();
+        let n: String = pk
+            .get_n()
+            .to_uint()
+            .to_be_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        unreduced["challenges"][0] = serde_json::Value::from(n);
+        assert!(KeySeed::<OpaqueChallenge>::new(pk)
+            .deserialize(unreduced)
+            .is_err());
+    }
+
     /// An honest voter should be able to generate a verifiable VoterProof
     #[test]
     fn test_voter_proof_correctness() {
@@ -245,6 +902,91 @@ mod tests {
         assert!(proof.verify(&keypair));
     }
 
+    /// Run the three moves of the proof with a challenge chosen by the verifier
+    #[test]
+    fn test_interactive_voter_proof() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let (commitment, secret) = VoterProof::commit(&statement, keypair.get_pk());
+        let challenge = ResidueClass::new(keypair.get_pk().get_r().sample());
+        let response = VoterProof::respond(secret, &challenge);
+        assert!(VoterProof::verify_interactive(
+            statement.get_val(),
+            &commitment,
+            &challenge,
+            &response,
+            &keypair
+        ));
+    }
+
+    /// A transcript whose statement is not a ciphertext, e.g. one deserialized from untrusted
+    /// input, is rejected instead of panicking
+    #[test]
+    fn test_voter_proof_malformed_statement() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let statement = ClearResidue::random(None, pk);
+        let mut proof = VoterProof::from_statement(&statement, pk);
+        proof.statement = OpaqueResidue::new(DynResidue::new(
+            &BigInt::ZERO,
+            pk.get_n().to_dyn_residue_params(),
+        ));
+        assert!(!proof.verify(&keypair));
+        assert!(!VerifierContext::new(&keypair).verify(&proof));
+        assert_eq!(NonInteractiveProof::verify(&proof, &keypair), Ok(false));
+    }
+
+    /// A response to one challenge should not verify against a different challenge
+    #[test]
+    fn test_interactive_voter_proof_wrong_challenge() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let statement =
+            ClearResidue::random(Some(ResidueClass::one(r).clone_residue()), keypair.get_pk());
+        let (commitment, secret) = VoterProof::commit(&statement, keypair.get_pk());
+        let challenge = ResidueClass::new(keypair.get_pk().get_r().sample());
+        let response = VoterProof::respond(secret, &challenge);
+        let other_challenge = challenge + ResidueClass::one(r);
+        assert!(!VoterProof::verify_interactive(
+            statement.get_val(),
+            &commitment,
+            &other_challenge,
+            &response,
+            &keypair
+        ));
+    }
+
+    /// Expanding the preimage with SHAKE256 reproduces the challenge
+    #[test]
+    fn test_voter_challenge_preimage() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let proof = VoterProof::from_statement(&statement, keypair.get_pk());
+        let challenge =
+            proofs::challenge_class(&[], &proof.challenge_preimage(), keypair.get_pk().get_r());
+        assert_eq!(challenge, proof.challenge);
+    }
+
+    #[test]
+    fn test_voter_soundness_bits() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let proof = VoterProof::from_statement(&statement, keypair.get_pk());
+        // r is a RINGSIZE-bit prime, so floor(log2(r)) is RINGSIZE - 1
+        assert_eq!(proof.soundness_bits(), RINGSIZE - 1);
+    }
+
+    /// A non-interactive proof whose challenge is not the hash of the commitment is rejected
+    #[test]
+    fn test_voter_proof_tampered_challenge() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let mut proof = VoterProof::from_statement(&statement, keypair.get_pk());
+        proof.challenge = proof.challenge + ResidueClass::one(r);
+        assert!(!proof.verify(&keypair));
+    }
+
     /// Test that a pair of honest voter and government can verify each other's proofs
     #[test]
     fn test_gov_proof_correctness() {
@@ -254,4 +996,36 @@ mod tests {
         let gov_proof = GovernmentProof::respond(&opaque_challenge, &keypair);
         assert!(voter_challenge.verify_gov_proof(&gov_proof));
     }
+
+    /// A single misidentified challenge fails the voter's check, no matter how many others are
+    /// right. The government's response is built from the voter's answers directly to avoid
+    /// the cost of decrypting them.
+    #[test]
+    fn test_gov_proof_one_wrong_class() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let voter_challenge = ClearChallenge::generate(pk, 8);
+        let mut answers = voter_challenge.get_answers().to_vec();
+        let honest = GovernmentProof::new(*pk, voter_challenge.obscure(), Some(answers.clone()));
+        assert!(voter_challenge.verify_gov_proof(&honest));
+
+        let one = ResidueClass::new(DynResidue::one(pk.get_r().to_dyn_residue_params()));
+        answers[5] = ClearResidue::new(
+            answers[5].clone_val(),
+            answers[5].clone_rc() + one,
+            answers[5].clone_witness(),
+            pk,
+        );
+        let dishonest = GovernmentProof::new(*pk, voter_challenge.obscure(), Some(answers));
+        assert!(!voter_challenge.verify_gov_proof(&dishonest));
+    }
+
+    /// A government that declines to decrypt the challenges fails the voter's check
+    #[test]
+    fn test_gov_proof_without_response() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let voter_challenge = ClearChallenge::generate(keypair.get_pk(), 4);
+        let gov_proof = GovernmentProof::new(*keypair.get_pk(), voter_challenge.obscure(), None);
+        assert!(!voter_challenge.verify_gov_proof(&gov_proof));
+    }
 }
@@ -0,0 +1,135 @@
+//! This is the proof in which the government convinces a third party, such as an auditor, that
+//! it decrypted a ciphertext w correctly, without revealing the secret key. The ciphertext is in
+//! the announced class c exactly when z = w * y^(-c) is an r-th residue, and the government,
+//! holding phi, can find an r-th root x of z. Each round proves knowledge of x:
+//!
+//! statement: z = x^r, recomputed by the verifier from w and c
+//! commitment: z' = (x')^r
+//! challenge: b <- Z/r
+//! response: x'x^b
+//! verification: response^r = z'z^b
+//!
+//! The rounds are those of proofs::RootProof, with the ciphertext and the announced class hashed
+//! into the challenges, so a proof cannot be carried over to another ciphertext or class.
+use crate::{
+    arithmetics::{OpaqueResidue, ResidueClass},
+    keys::{KeyPair, PublicKey},
+    proofs::RootProof,
+};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+use crypto_bigint::{rand_core::CryptoRngCore, Encoding};
+
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/decryption/v2";
+
+/// Proof that a ciphertext decrypts to the announced residue class
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DecryptionProof {
+    rounds: RootProof,
+}
+
+impl DecryptionProof {
+    /// Decrypt the ciphertext and prove that the decryption is correct. Panics if the ciphertext
+    /// is malformed; see KeyPair::try_decrypt
+    #[cfg(feature = "std")]
+    pub fn prove(ciphertext: &OpaqueResidue, keypair: &KeyPair) -> (ResidueClass, Self) {
+        return Self::prove_with_rng(ciphertext, keypair, &mut OsRng);
+    }
+
+    /// Decrypt and prove the decryption using the supplied source of randomness
+    pub fn prove_with_rng(
+        ciphertext: &OpaqueResidue,
+        keypair: &KeyPair,
+        rng: &mut impl CryptoRngCore,
+    ) -> (ResidueClass, Self) {
+        let pk = keypair.get_pk();
+        let class = keypair.decrypt(ciphertext);
+        let root = keypair
+            .recover_witness(ciphertext, &class)
+            .expect("a ciphertext is in the class it decrypts to");
+        let statement = *ciphertext * pk.invert_y().pow(&class);
+        let context = Self::context(ciphertext, &class);
+        let rounds = RootProof::prove_with_rng(&statement, &root, DOMAIN, &context, pk, rng);
+        return (class, Self { rounds });
+    }
+
+    /// Check, with nothing more than the public key, that the ciphertext decrypts to the class,
+    /// which is the case exactly when z = ciphertext * y^(-class) is an r-th residue
+    pub fn verify(&self, ciphertext: &OpaqueResidue, class: &ResidueClass, pk: &PublicKey) -> bool {
+        if !pk.is_valid_ciphertext(ciphertext) {
+            return false;
+        }
+        let statement = *ciphertext * pk.invert_y().pow(class);
+        let context = Self::context(ciphertext, class);
+        return self.rounds.verify(&statement, DOMAIN, &context, pk);
+    }
+
+    /// The soundness of the proof in bits. Each round has a soundness error of 1/r, so the
+    /// soundness error of the whole proof is at most 2 ** -soundness_bits.
+    pub fn soundness_bits(&self) -> usize {
+        return self.rounds.soundness_bits();
+    }
+
+    /// The ciphertext and the class, which the challenges are bound to
+    fn context(ciphertext: &OpaqueResidue, class: &ResidueClass) -> Vec<u8> {
+        let mut context = ciphertext.to_be_bytes().to_vec();
+        context.extend_from_slice(&class.retrieve().to_be_bytes());
+        return context;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arithmetics::ClearResidue, GROUPSIZE, RINGSIZE};
+
+    #[test]
+    fn test_decryption_proof_correctness() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        for _ in 0..4 {
+            let ciphertext = ClearResidue::random(None, pk);
+            let (class, proof) = DecryptionProof::prove(ciphertext.get_val(), &keypair);
+            assert_eq!(&class, ciphertext.get_rc());
+            assert!(proof.verify(ciphertext.get_val(), &class, pk));
+            assert!(proof.soundness_bits() >= RootProof::SOUNDNESS_BITS);
+        }
+    }
+
+    /// A proof does not pass for another class or another ciphertext, and a prover who does not
+    /// know an r-th root of z cannot answer the challenges
+    #[test]
+    fn test_decryption_proof_soundness() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let ciphertext = ClearResidue::random(None, pk);
+        let (class, proof) = DecryptionProof::prove(ciphertext.get_val(), &keypair);
+        let wrong_class = class + ResidueClass::one(*class.params());
+        assert!(!proof.verify(ciphertext.get_val(), &wrong_class, pk));
+        let other = ClearResidue::random(Some(class.clone_residue()), pk);
+        assert!(!proof.verify(other.get_val(), &class, pk));
+
+        // claim the wrong class, using the witness of the true one as the root
+        let statement = *ciphertext.get_val() * pk.invert_y().pow(&wrong_class);
+        let context = DecryptionProof::context(ciphertext.get_val(), &wrong_class);
+        let rounds = RootProof::prove_with_rng(
+            &statement,
+            ciphertext.get_witness(),
+            DOMAIN,
+            &context,
+            pk,
+            &mut OsRng,
+        );
+        let forged = DecryptionProof { rounds };
+        assert!(!forged.verify(ciphertext.get_val(), &wrong_class, pk));
+
+        // dropping rounds lowers the soundness below the bound, so the proof is rejected
+        let mut truncated = proof.clone();
+        truncated.rounds.commitment.pop();
+        truncated.rounds.challenge.pop();
+        truncated.rounds.response.pop();
+        assert!(!truncated.verify(ciphertext.get_val(), &class, pk));
+    }
+}
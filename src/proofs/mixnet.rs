@@ -0,0 +1,294 @@
+//! Proof that a mix node re-encrypted a list of ciphertexts without adding, dropping, or altering
+//! any of them.
+//!
+//! A mix node takes a list of ciphertexts (e.g. the ballots), permutes them, and rerandomizes each
+//! of them by multiplying it with a random r-th residue. Rerandomization does not change the
+//! residue class, so the output decrypts to the same multiset of residue classes as the input,
+//! while the permutation and the fresh ciphertexts break the link between a voter and the ballot.
+//!
+//! The proof is a cut-and-choose: for each round, the mix node commits to a "shadow" mix of the
+//! input. The challenge bit of the round asks for either the re-encryption that turns the input
+//! into the shadow, or the re-encryption that turns the shadow into the output. Revealing one of
+//! the two links says nothing about the permutation of the real mix, but a mix node whose output
+//! is not a re-encryption of the input cannot answer both, so it survives each round with
+//! probability 1/2.
+use crate::{arithmetics::OpaqueResidue, keys::PublicKey, proofs};
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+#[cfg(feature = "std")]
+use rand::seq::SliceRandom;
+use sha3::Digest;
+
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/mixnet/v1";
+
+/// The challenge bits are the bits of a SHA3-256 digest, so there are 256 shadow mixes
+pub const CONFIDENCE: usize = 256;
+
+/// A re-encryption of a list of ciphertexts: target[i] = source[permutation[i]] * (factors[i] ** r)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Reencryption {
+    permutation: Vec<usize>,
+    factors: Vec<OpaqueResidue>,
+}
+
+impl Reencryption {
+    pub fn new(permutation: Vec<usize>, factors: Vec<OpaqueResidue>) -> Self {
+        return Self {
+            permutation,
+            factors,
+        };
+    }
+
+    pub fn get_permutation(&self) -> &[usize] {
+        return &self.permutation;
+    }
+
+    pub fn get_factors(&self) -> &[OpaqueResidue] {
+        return &self.factors;
+    }
+
+    /// Sample a uniformly random permutation and random invertible factors for a list of the
+    /// given length
+    #[cfg(feature = "std")]
+    fn random(len: usize, pk: &PublicKey) -> Self {
+        let mut permutation = (0..len).collect::<Vec<usize>>();
+        permutation.shuffle(&mut OsRng);
+        let factors = (0..len)
+            .map(|_| OpaqueResidue::new(pk.sample_invertible()))
+            .collect();
+        return Self::new(permutation, factors);
+    }
+
+    /// Re-encrypt the source list
+    fn apply(&self, source: &[OpaqueResidue], pk: &PublicKey) -> Vec<OpaqueResidue> {
+        return self
+            .permutation
+            .iter()
+            .zip(self.factors.iter())
+            .map(|(i, factor)| source[*i].rerandomize(factor, pk))
+            .collect();
+    }
+
+    /// Given the re-encryption from the source to the target (self) and the re-encryption from
+    /// the same source to a shadow, compute the re-encryption from the shadow to the target:
+    /// target[i] = shadow[shadow_index[permutation[i]]] * ((factors[i] / shadow_factor) ** r)
+    #[cfg(feature = "std")]
+    fn relative_to(&self, shadow: &Reencryption) -> Self {
+        let mut shadow_index = vec![0; shadow.permutation.len()];
+        for (j, i) in shadow.permutation.iter().enumerate() {
+            shadow_index[*i] = j;
+        }
+        let permutation: Vec<usize> = self.permutation.iter().map(|i| shadow_index[*i]).collect();
+        let factors = permutation
+            .iter()
+            .zip(self.factors.iter())
+            .map(|(j, factor)| *factor * shadow.factors[*j].invert())
+            .collect();
+        return Self::new(permutation, factors);
+    }
+
+    /// Check that the target is exactly this re-encryption of the source. The permutation must
+    /// be a permutation of the indices of the source, and every factor must be invertible, or
+    /// else the "re-encryption" could drop a ciphertext or change its residue class.
+    fn links(&self, source: &[OpaqueResidue], target: &[OpaqueResidue], pk: &PublicKey) -> bool {
+        if self.permutation.len() != source.len()
+            || self.factors.len() != source.len()
+            || target.len() != source.len()
+        {
+            return false;
+        }
+        let mut seen = vec![false; source.len()];
+        for i in self.permutation.iter() {
+            if *i >= source.len() || seen[*i] {
+                return false;
+            }
+            seen[*i] = true;
+        }
+        if !self.factors.iter().all(|factor| factor.is_invertible()) {
+            return false;
+        }
+        return self.apply(source, pk) == target;
+    }
+}
+
+/// A mix node permutes and rerandomizes a list of ciphertexts
+pub struct MixNode;
+
+impl MixNode {
+    /// Mix the input, returning the re-encrypted list with the proof that it is a re-encryption
+    /// of the input. The output decrypts to the same multiset of residue classes as the input.
+    #[cfg(feature = "std")]
+    pub fn process(input: &[OpaqueResidue], pk: &PublicKey) -> (Vec<OpaqueResidue>, MixProof) {
+        let mix = Reencryption::random(input.len(), pk);
+        let output = mix.apply(input, pk);
+
+        let shadow_mixes = (0..CONFIDENCE)
+            .map(|_| Reencryption::random(input.len(), pk))
+            .collect::<Vec<Reencryption>>();
+        let shadows = shadow_mixes
+            .iter()
+            .map(|shadow_mix| shadow_mix.apply(input, pk))
+            .collect::<Vec<Vec<OpaqueResidue>>>();
+        let challenge = MixProof::generate_challenge(input, &output, &shadows);
+        let response = shadow_mixes
+            .into_iter()
+            .zip(challenge.iter())
+            .map(|(shadow_mix, open_shadow)| {
+                if *open_shadow {
+                    return shadow_mix;
+                }
+                return mix.relative_to(&shadow_mix);
+            })
+            .collect();
+
+        return (output, MixProof::new(shadows, challenge, response));
+    }
+}
+
+/// Proof that the output of a mix node is a re-encryption of its input
+pub struct MixProof {
+    /// The shadow mixes of the input, one per round
+    pub shadows: Vec<Vec<OpaqueResidue>>,
+
+    /// For each round, true if the link from the input to the shadow is revealed, false if the
+    /// link from the shadow to the output is revealed
+    pub challenge: Vec<bool>,
+
+    /// The revealed re-encryption of each round
+    pub response: Vec<Reencryption>,
+}
+
+impl MixProof {
+    pub fn new(
+        shadows: Vec<Vec<OpaqueResidue>>,
+        challenge: Vec<bool>,
+        response: Vec<Reencryption>,
+    ) -> Self {
+        return Self {
+            shadows,
+            challenge,
+            response,
+        };
+    }
+
+    /// The challenge bits are the SHA3-256 digest of the domain tag, the canonical big-endian
+    /// values of the input, of the output, and of every shadow mix, most significant bit first
+    fn generate_challenge(
+        input: &[OpaqueResidue],
+        output: &[OpaqueResidue],
+        shadows: &[Vec<OpaqueResidue>],
+    ) -> Vec<bool> {
+        let mut hasher = proofs::challenge_hasher(DOMAIN);
+        let lists = [input, output]
+            .into_iter()
+            .chain(shadows.iter().map(|shadow| shadow.as_slice()));
+        for list in lists {
+            for residue in list {
+                hasher.update(residue.to_be_bytes());
+            }
+        }
+        return proofs::challenge_bits(&hasher.finalize());
+    }
+
+    /// Verify that the output is a re-encryption of the input
+    pub fn verify(
+        &self,
+        input: &[OpaqueResidue],
+        output: &[OpaqueResidue],
+        pk: &PublicKey,
+    ) -> bool {
+        if input.len() != output.len() {
+            return false;
+        }
+        if self.shadows.len() != CONFIDENCE
+            || self.challenge.len() != CONFIDENCE
+            || self.response.len() != CONFIDENCE
+        {
+            return false;
+        }
+        if self.challenge != Self::generate_challenge(input, output, &self.shadows) {
+            return false;
+        }
+
+        return self
+            .shadows
+            .iter()
+            .zip(self.challenge.iter())
+            .zip(self.response.iter())
+            .all(|((shadow, open_shadow), response)| {
+                if *open_shadow {
+                    return response.links(input, shadow, pk);
+                }
+                return response.links(shadow, output, pk);
+            });
+    }
+
+    /// The soundness of the proof in bits. A mix node whose output is not a re-encryption of
+    /// its input can answer at most one of the two challenges of each round.
+    pub fn soundness_bits(&self) -> usize {
+        return self.challenge.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arithmetics::ClearResidue, keys::KeyPair, BigInt, GROUPSIZE, RINGSIZE};
+    use crypto_bigint::modular::runtime_mod::DynResidue;
+
+    /// Encrypt each of the classes
+    fn encrypt_classes(classes: &[u8], pk: &PublicKey) -> Vec<OpaqueResidue> {
+        return classes
+            .iter()
+            .map(|class| {
+                let class =
+                    DynResidue::new(&BigInt::from_u8(*class), pk.get_r().to_dyn_residue_params());
+                return ClearResidue::random(Some(class), pk).into_opaque();
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_correctness() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let input = encrypt_classes(&[0, 1, 1, 2], pk);
+        let (output, proof) = MixNode::process(&input, pk);
+        assert!(proof.verify(&input, &output, pk));
+        assert_eq!(proof.soundness_bits(), CONFIDENCE);
+
+        let decrypt_sorted = |list: &[OpaqueResidue]| {
+            let mut classes = list
+                .iter()
+                .map(|ct| keypair.decrypt(ct).retrieve())
+                .collect::<Vec<BigInt>>();
+            classes.sort();
+            return classes;
+        };
+        assert_eq!(decrypt_sorted(&input), decrypt_sorted(&output));
+    }
+
+    #[test]
+    fn test_reject_tampered_mix() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let input = encrypt_classes(&[0, 1, 1, 2], pk);
+        let (output, proof) = MixNode::process(&input, pk);
+
+        // replacing a ballot with a fresh one changes the challenge
+        let mut replaced = output.clone();
+        replaced[0] = encrypt_classes(&[2], pk)[0];
+        assert!(!proof.verify(&input, &replaced, pk));
+
+        // dropping a ballot
+        assert!(!proof.verify(&input, &output[1..], pk));
+
+        // a response that maps two ciphertexts onto the same one
+        let mut proof = proof;
+        let response = &mut proof.response[0];
+        response.permutation[1] = response.permutation[0];
+        assert!(!proof.verify(&input, &output, pk));
+    }
+}
@@ -1,6 +1,417 @@
 //! A number of proofs used to show that the government and the voters are acting in accordance to
 //! protocol at various stages of the election. There are three main areas:
+use crate::{
+    arithmetics::{OpaqueResidue, ResidueClass, RingModulus, Witness},
+    keys::PublicKey,
+    BigInt,
+};
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use crypto_bigint::{modular::runtime_mod::DynResidue, rand_core::CryptoRngCore};
+use sha3::{
+    digest::{self, ExtendableOutput, XofReader},
+    Digest, Sha3_256, Shake256,
+};
 
+pub mod audit;
 pub mod ballot;
 pub mod consonance;
+pub mod decryption;
+pub mod mixnet;
+pub mod range;
 pub mod tally;
+#[cfg(feature = "serde")]
+pub mod wire;
+pub mod zero;
+
+/// The common lifecycle of the proofs in this crate: the prover turns a statement into a proof
+/// transcript (with the challenge derived through Fiat-Shamir), and the verifier checks the
+/// transcript. The proofs differ in what the verifier needs to have in hand: some can be checked
+/// by anyone, while others need the secret key.
+pub trait NonInteractiveProof {
+    /// The secret knowledge that the prover holds
+    type Statement;
+
+    /// Whatever the verifier needs in addition to the transcript
+    type Verifier;
+
+    type Error;
+
+    /// Produce a proof of the statement
+    #[cfg(feature = "std")]
+    fn prove(statement: &Self::Statement, pk: &PublicKey) -> Self
+    where
+        Self: Sized;
+
+    /// Check the proof transcript
+    fn verify(&self, verifier: &Self::Verifier) -> Result<bool, Self::Error>;
+}
+
+/// The hash function that turns a commitment into a Fiat-Shamir challenge. A serialized proof
+/// records the hash it was produced with, so it remains verifiable after the default changes.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha3_256,
+
+    /// SHAKE256 read out to 256 bits, the same length as a SHA3-256 digest
+    Shake256,
+}
+
+impl HashAlgorithm {
+    /// The byte that identifies the hash in a serialized proof
+    pub fn id(&self) -> u8 {
+        return match self {
+            Self::Sha3_256 => 1,
+            Self::Shake256 => 2,
+        };
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        return match id {
+            1 => Some(Self::Sha3_256),
+            2 => Some(Self::Shake256),
+            _ => None,
+        };
+    }
+
+    /// Hash the preimage into a 32-byte digest
+    pub fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha3_256 => return Sha3_256::digest(preimage).to_vec(),
+            Self::Shake256 => {
+                let mut shake = Shake256::default();
+                digest::Update::update(&mut shake, preimage);
+                let mut digest = vec![0u8; 32];
+                shake.finalize_xof().read(&mut digest);
+                return digest;
+            }
+        }
+    }
+}
+
+/// Errors that can occur when deserializing a proof
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ProofError {
+    /// The input ended before the proof was complete
+    Truncated,
+
+    /// The input continues after the end of the proof
+    TrailingBytes,
+
+    /// The hash identifier byte does not name a known hash algorithm
+    UnknownHashAlgorithm(u8),
+
+    /// A response tag byte is neither the opened nor the consumed capsule tag
+    InvalidTag(u8),
+
+    /// A residue is not reduced under its modulus
+    OutOfRange,
+
+    /// The commitment, the challenge, and the response do not have the same length
+    LengthMismatch,
+
+    /// The proof at this index is about a different statement than the one it accompanies
+    StatementMismatch(usize),
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "the proof is truncated"),
+            Self::TrailingBytes => write!(f, "unexpected bytes after the end of the proof"),
+            Self::UnknownHashAlgorithm(id) => write!(f, "unknown hash algorithm {id}"),
+            Self::InvalidTag(tag) => write!(f, "invalid response tag {tag}"),
+            Self::OutOfRange => write!(f, "a residue is not reduced under its modulus"),
+            Self::LengthMismatch => {
+                write!(f, "commitment, challenge, and response differ in length")
+            }
+            Self::StatementMismatch(i) => {
+                write!(f, "proof {i} is not about the statement it accompanies")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ProofError {}
+
+/// Start the Fiat-Shamir hash of a proof with the proof's domain tag, so that a commitment from
+/// one type of proof hashes to an unrelated challenge in any other type of proof
+pub(crate) fn challenge_hasher(domain: &[u8]) -> Sha3_256 {
+    let mut hasher = Sha3_256::new();
+    hasher.update(domain);
+    return hasher;
+}
+
+/// Hash the preimage into a challenge that is uniform over Z/r, whatever the size of r. The
+/// domain tag and the preimage are expanded with SHAKE256 into a stream of candidates that are
+/// exactly as many bits long as r, and the first candidate below r is the challenge. Each
+/// candidate is accepted with probability more than 1/2, and unlike reducing a fixed-size
+/// digest (mod r), rejecting the rest leaves no bias toward the small classes.
+pub(crate) fn challenge_class(domain: &[u8], preimage: &[u8], r: &RingModulus) -> ResidueClass {
+    let mut shake = Shake256::default();
+    digest::Update::update(&mut shake, domain);
+    digest::Update::update(&mut shake, preimage);
+    let mut reader = shake.finalize_xof();
+    let shift = BigInt::BITS - r.modulus_ref().bits();
+    let mut bytes = [0u8; BigInt::BYTES];
+    loop {
+        reader.read(&mut bytes);
+        let candidate = BigInt::from_be_slice(&bytes).shr_vartime(shift);
+        if &candidate < r.modulus_ref() {
+            return ResidueClass::new(DynResidue::new(&candidate, *r.params()));
+        }
+    }
+}
+
+/// Expand a digest into one challenge bit per bit of the digest, most significant bit first
+pub(crate) fn challenge_bits(digest: &[u8]) -> Vec<bool> {
+    return digest
+        .iter()
+        .flat_map(|byte| (0..u8::BITS).map(move |j| (0b1000_0000u8 >> j) & byte != 0))
+        .collect();
+}
+
+/// The soundness of a single round whose challenge is drawn uniformly from Z/r. A cheating prover
+/// survives such a round with probability 1/r, which is at most 2 ** -floor(log2(r)).
+pub(crate) fn ring_soundness_bits(r: &BigInt) -> usize {
+    return r.bits() - 1;
+}
+
+/// The rounds of a proof of knowledge of an r-th root x of the statement z, which the tally, the
+/// decryption, and the zero proofs are all built on:
+///
+/// statement: z = x^r
+/// commitment: z' = (x')^r
+/// challenge: b <- Z/r
+/// response: x'x^b
+/// verification: response^r = z'z^b
+///
+/// The challenges of all rounds are derived from a single hash of the public key, the statement,
+/// the context that the proof is about, and every commitment, so a forger cannot grind a
+/// commitment whose challenge is 0 one round at a time, and a proof cannot be carried over to
+/// another key, statement, or context. The number of rounds is fixed by the ring size so that the
+/// proof is always at least SOUNDNESS_BITS sound.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub(crate) struct RootProof {
+    pub(crate) commitment: Vec<OpaqueResidue>,
+    pub(crate) challenge: Vec<ResidueClass>,
+
+    /// The blinded roots x'x^b, which reveal nothing about x because each x' is uniform
+    pub(crate) response: Vec<Witness>,
+}
+
+impl RootProof {
+    /// The least soundness of the proof in bits
+    pub(crate) const SOUNDNESS_BITS: usize = 128;
+
+    pub(crate) fn new(
+        commitment: Vec<OpaqueResidue>,
+        challenge: Vec<ResidueClass>,
+        response: Vec<Witness>,
+    ) -> Self {
+        return Self {
+            commitment,
+            challenge,
+            response,
+        };
+    }
+
+    /// Prove knowledge of the root of the statement. The domain tag names the proof that is
+    /// built on these rounds, and the context is whatever else the proof is about.
+    pub(crate) fn prove_with_rng(
+        statement: &OpaqueResidue,
+        root: &Witness,
+        domain: &[u8],
+        context: &[u8],
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let blinds = (0..Self::rounds(pk))
+            .map(|_| Witness::new(pk.sample_invertible_with_rng(rng)))
+            .collect::<Vec<Witness>>();
+        let commitment = blinds
+            .iter()
+            .map(|blind| blind.rth_power(pk.get_r()))
+            .collect::<Vec<OpaqueResidue>>();
+        let challenge = Self::generate_challenge(statement, domain, context, &commitment, pk);
+        let response = blinds
+            .into_iter()
+            .zip(challenge.iter())
+            .map(|(blind, b)| blind * root.pow(b))
+            .collect();
+        return Self::new(commitment, challenge, response);
+    }
+
+    /// Check that the proof has as many rounds as the ring size calls for, that the challenges
+    /// are the hash of the transcript, and that every response is an invertible r-th root of
+    /// z'z^b
+    pub(crate) fn verify(
+        &self,
+        statement: &OpaqueResidue,
+        domain: &[u8],
+        context: &[u8],
+        pk: &PublicKey,
+    ) -> bool {
+        if self.commitment.len() != Self::rounds(pk)
+            || self.challenge.len() != self.commitment.len()
+            || self.response.len() != self.commitment.len()
+        {
+            return false;
+        }
+        let challenge = Self::generate_challenge(statement, domain, context, &self.commitment, pk);
+        if challenge != self.challenge {
+            return false;
+        }
+        return self
+            .commitment
+            .iter()
+            .zip(self.challenge.iter())
+            .zip(self.response.iter())
+            .all(|((commitment, b), response)| {
+                let rhs = *commitment * statement.pow(b);
+                return response.is_invertible() && response.is_rth_root_of(&rhs, pk.get_r());
+            });
+    }
+
+    /// The soundness of the proof in bits. Each round has a soundness error of 1/r and the
+    /// rounds are independent, so the soundness error of the whole proof is at most
+    /// 2 ** -soundness_bits.
+    pub(crate) fn soundness_bits(&self) -> usize {
+        return self
+            .challenge
+            .iter()
+            .map(|challenge| ring_soundness_bits(challenge.params().modulus()))
+            .sum();
+    }
+
+    /// The number of rounds for at least SOUNDNESS_BITS of soundness under the ring of the key
+    pub(crate) fn rounds(pk: &PublicKey) -> usize {
+        return Self::SOUNDNESS_BITS.div_ceil(ring_soundness_bits(pk.get_r().modulus()));
+    }
+
+    /// Hash the public key, the statement, the context, and all of the commitments, then expand
+    /// the hash into one challenge per commitment
+    pub(crate) fn generate_challenge(
+        statement: &OpaqueResidue,
+        domain: &[u8],
+        context: &[u8],
+        commitment: &[OpaqueResidue],
+        pk: &PublicKey,
+    ) -> Vec<ResidueClass> {
+        let mut preimage = pk.component_bytes();
+        preimage.extend_from_slice(&statement.to_be_bytes());
+        preimage.extend_from_slice(&(context.len() as u32).to_be_bytes());
+        preimage.extend_from_slice(context);
+        for residue in commitment {
+            preimage.extend_from_slice(&residue.to_be_bytes());
+        }
+        return (0..commitment.len())
+            .map(|round| {
+                let mut round_preimage = preimage.clone();
+                round_preimage.extend_from_slice(&(round as u32).to_be_bytes());
+                return challenge_class(domain, &round_preimage, pk.get_r());
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::{ballot, consonance, decryption, tally, zero};
+    use crate::{
+        arithmetics::ClearResidue,
+        keys::KeyPair,
+        proofs::{ballot::BallotProof, consonance::VoterProof, tally::TallyProof},
+        BigInt, GROUPSIZE, RINGSIZE,
+    };
+    use crypto_bigint::{modular::runtime_mod::DynResidue, Encoding};
+    use std::convert::Infallible;
+
+    type PublicProof =
+        dyn NonInteractiveProof<Statement = ClearResidue, Verifier = PublicKey, Error = Infallible>;
+
+    fn prove_and_verify<P: NonInteractiveProof>(
+        statement: &P::Statement,
+        pk: &PublicKey,
+        verifier: &P::Verifier,
+    ) -> Result<bool, P::Error> {
+        return P::prove(statement, pk).verify(verifier);
+    }
+
+    /// Identical commitments hash to different challenges under different proofs
+    #[test]
+    fn test_domain_separation() {
+        let domains = [
+            ballot::DOMAIN,
+            consonance::DOMAIN,
+            consonance::SET_DOMAIN,
+            decryption::DOMAIN,
+            tally::DOMAIN,
+            zero::DOMAIN,
+        ];
+        let message = BigInt::from_u8(42).to_be_bytes();
+        let digests: Vec<Vec<u8>> = domains
+            .iter()
+            .map(|domain| {
+                let mut hasher = challenge_hasher(domain);
+                hasher.update(message);
+                return hasher.finalize().to_vec();
+            })
+            .collect();
+        for i in 0..domains.len() {
+            for j in (i + 1)..domains.len() {
+                assert_ne!(domains[i], domains[j]);
+                assert_ne!(digests[i], digests[j]);
+            }
+        }
+    }
+
+    /// The challenges are spread evenly over a ring that rejects almost half of the candidates:
+    /// a chi-squared statistic over the 257 classes stays well within its expected range
+    #[test]
+    fn test_challenge_class_uniform() {
+        let r = RingModulus::from_hex("0101").unwrap();
+        let per_class = 100;
+        let mut counts = [0usize; 257];
+        for i in 0..(257 * per_class as u32) {
+            let class = challenge_class(b"test", &i.to_be_bytes(), &r);
+            counts[class.retrieve().as_words()[0] as usize] += 1;
+        }
+        let chi_squared: usize = counts
+            .iter()
+            .map(|count| count.abs_diff(per_class).pow(2))
+            .sum::<usize>()
+            / per_class;
+        // 256 degrees of freedom: mean 256, standard deviation about 23
+        assert!(chi_squared < 400, "chi-squared is {chi_squared}");
+        assert!(counts.iter().all(|count| *count > 0));
+    }
+
+    /// Publicly verifiable proofs of different types can be handled uniformly as trait objects
+    #[test]
+    fn test_trait_objects() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let ballot = ClearResidue::random(Some(DynResidue::new(&BigInt::ONE, r)), keypair.get_pk());
+        let rth_residue =
+            ClearResidue::random(Some(DynResidue::new(&BigInt::ZERO, r)), keypair.get_pk());
+
+        let proofs: Vec<Box<PublicProof>> = vec![
+            Box::new(BallotProof::prove(&ballot, keypair.get_pk())),
+            Box::new(TallyProof::prove(&rth_residue, keypair.get_pk())),
+        ];
+        for proof in proofs.iter() {
+            assert_eq!(proof.verify(keypair.get_pk()), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_generic_prove_and_verify() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let statement = ClearResidue::random(None, keypair.get_pk());
+        let verified = prove_and_verify::<VoterProof>(&statement, keypair.get_pk(), &keypair);
+        assert_eq!(verified, Ok(true));
+    }
+}
@@ -0,0 +1,159 @@
+//! This is the proof in which the voter convinces anyone holding the public key that a ballot
+//! encrypts an integer in the range lo..=hi, such as a score from 0 to 10, without revealing
+//! which one.
+//!
+//! The range is expanded into the class set {lo, lo + 1, ..., hi}, and the proof is the ballot
+//! proof of membership in that set. Every capsule holds one element per class, so the size of the
+//! proof and the time it takes to prove and to verify grow linearly with hi - lo + 1: a range of
+//! 11 scores costs about 5.5 times as much as a yes/no ballot.
+use crate::{
+    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass, RingModulus},
+    keys::PublicKey,
+    proofs::ballot::BallotProof,
+    BigInt,
+};
+use alloc::vec::Vec;
+use crypto_bigint::rand_core::CryptoRngCore;
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+
+/// The largest number of integers that a range may cover. Checking an opened capsule compares
+/// every element against every class, so verifying a range of m integers takes time quadratic in
+/// m; the cap keeps a prover from making the verifier do an unbounded amount of work.
+pub const MAX_RANGE_SIZE: u64 = 64;
+
+/// Proof that the statement encrypts one of lo, lo + 1, ..., hi
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RangeProof {
+    lo: u64,
+    hi: u64,
+    proof: BallotProof,
+}
+
+impl RangeProof {
+    /// Prove that the statement encrypts an integer in lo..=hi. Panics if the range is empty,
+    /// covers more than MAX_RANGE_SIZE integers, or does not fit below r, or if the statement is
+    /// outside of the range
+    #[cfg(feature = "std")]
+    pub fn prove(statement: &ClearResidue, lo: u64, hi: u64, pk: &PublicKey) -> Self {
+        return Self::prove_with_rng(statement, lo, hi, pk, &mut OsRng);
+    }
+
+    /// Produce the proof using the supplied source of randomness
+    pub fn prove_with_rng(
+        statement: &ClearResidue,
+        lo: u64,
+        hi: u64,
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let classes = range_classes(lo, hi, pk.get_r()).expect("the range is not a valid range");
+        if !classes.contains(statement.get_rc()) {
+            panic!("the statement is not in the range");
+        }
+        let proof = BallotProof::from_statement_with_rng(statement, &classes, pk, rng);
+        return Self { lo, hi, proof };
+    }
+
+    pub fn get_statement(&self) -> &OpaqueResidue {
+        return &self.proof.statement;
+    }
+
+    /// The range lo..=hi that the proof claims the statement is in
+    pub fn get_range(&self) -> (u64, u64) {
+        return (self.lo, self.hi);
+    }
+
+    /// Check that the proof is about the range lo..=hi that the verifier expects, and check the
+    /// ballot proof against the classes of that range. Only the public key is needed.
+    pub fn verify(&self, lo: u64, hi: u64, pk: &PublicKey) -> bool {
+        if (self.lo, self.hi) != (lo, hi) {
+            return false;
+        }
+        return match range_classes(lo, hi, pk.get_r()) {
+            Some(classes) => self.proof.verify(&classes, pk),
+            None => false,
+        };
+    }
+
+    /// The soundness of the proof in bits, which is that of the underlying ballot proof
+    pub fn soundness_bits(&self) -> usize {
+        return self.proof.soundness_bits();
+    }
+}
+
+/// The residue classes lo, lo + 1, ..., hi, or None if the range is empty, covers more than
+/// MAX_RANGE_SIZE integers, or hi is not less than r, in which case distinct integers of the
+/// range would share a class
+pub fn range_classes(lo: u64, hi: u64, r: &RingModulus) -> Option<Vec<ResidueClass>> {
+    if lo > hi || hi - lo >= MAX_RANGE_SIZE || BigInt::from_u64(hi) >= *r.modulus_ref() {
+        return None;
+    }
+    return Some(
+        (lo..=hi)
+            .map(|val| ResidueClass::from_u64(val, r))
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+
+    #[test]
+    fn test_range_proof_correctness() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        for (lo, hi, score) in [(0, 10, 7), (0, 10, 0), (3, 5, 5)] {
+            let class = ResidueClass::from_u64(score, pk.get_r());
+            let statement = ClearResidue::random(Some(class.clone_residue()), pk);
+            let proof = RangeProof::prove(&statement, lo, hi, pk);
+            assert!(proof.verify(lo, hi, pk));
+            assert_eq!(proof.get_range(), (lo, hi));
+            assert_eq!(proof.get_statement(), statement.get_val());
+        }
+    }
+
+    /// A proof over a wider range does not pass for a narrower one that excludes the statement
+    #[test]
+    fn test_range_proof_soundness() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let eleven = ResidueClass::from_u64(11, pk.get_r());
+        let statement = ClearResidue::random(Some(eleven.clone_residue()), pk);
+        let mut proof = RangeProof::prove(&statement, 0, 11, pk);
+        assert!(proof.verify(0, 11, pk));
+
+        // the verifier's range decides, not the one inside the proof
+        assert!(!proof.verify(0, 10, pk));
+        proof.hi = 10;
+        assert!(!proof.verify(0, 10, pk));
+        assert!(!proof.verify(0, 11, pk));
+
+        // a range that does not fit below r is never accepted
+        proof.hi = u64::MAX;
+        assert!(!proof.verify(0, u64::MAX, pk));
+        assert_eq!(range_classes(5, 4, pk.get_r()), None);
+    }
+
+    #[test]
+    fn test_range_size_cap() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let r = keypair.get_pk().get_r();
+        let widest = range_classes(100, 100 + MAX_RANGE_SIZE - 1, r).unwrap();
+        assert_eq!(widest.len() as u64, MAX_RANGE_SIZE);
+        assert_eq!(range_classes(100, 100 + MAX_RANGE_SIZE, r), None);
+        assert_eq!(range_classes(0, u64::MAX, r), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_range_proof_out_of_range_statement() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let eleven = ResidueClass::from_u64(11, pk.get_r());
+        let statement = ClearResidue::random(Some(eleven.clone_residue()), pk);
+        RangeProof::prove(&statement, 0, 10, pk);
+    }
+}
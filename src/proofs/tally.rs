@@ -7,30 +7,28 @@
 //! response x'x^b
 //! verification: response^r = z'z^b
 //!
-//! In addition, a Fiat-Shamir transformation is applied so the challenge is generated by hasing
-//! the commitment, so the entire proof can be performed offline
+//! The rounds are those of proofs::RootProof, with the aggregate and the claimed tally hashed into
+//! the challenges, so a proof cannot be carried over to another aggregate or tally.
 use crate::{
-    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass},
-    keys::PublicKey,
-    BigInt,
+    arithmetics::{ClearResidue, OpaqueResidue, ResidueClass, Witness},
+    keys::{KeyPair, PublicKey},
+    proofs::{NonInteractiveProof, RootProof},
 };
-use crypto_bigint::{modular::runtime_mod::DynResidue, Encoding};
-use sha3::{Digest, Sha3_256};
+use alloc::vec::Vec;
+use core::convert::Infallible;
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+use crypto_bigint::{rand_core::CryptoRngCore, Encoding};
+
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/tally/v3";
 
 /// Proof that the tally is correct by showing that wy^(-c) is an r-th residue, including the
 /// Fiat-Shamir transformation so that it is non-interactive
-/// statement: z = x ** r
-/// commitment: z' = x' ** r
-/// challenge: b <- Z/r
-/// response: x'x ** b
-/// verify: response ** r = z'z ** b
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TallyProof {
     pub statement: OpaqueResidue,
-    pub commitment: Vec<OpaqueResidue>,
-    pub challenge: Vec<ResidueClass>,
-
-    /// The response will actually be (x'x ** b) ** r for easier verification
-    pub response: Vec<ClearResidue>,
+    rounds: RootProof,
 }
 
 impl TallyProof {
@@ -38,93 +36,153 @@ impl TallyProof {
         statement: OpaqueResidue,
         commitment: Vec<OpaqueResidue>,
         challenge: Vec<ResidueClass>,
-        response: Vec<ClearResidue>,
+        response: Vec<Witness>,
     ) -> Self {
         return Self {
             statement,
-            commitment,
-            challenge,
-            response,
+            rounds: RootProof::new(commitment, challenge, response),
         };
     }
 
-    pub fn from_statement(statement: ClearResidue, confidence: usize, pk: &PublicKey) -> Self {
-        let commitment = Self::generate_commitment(confidence, pk);
-        let challenge = Self::generate_challenge(&commitment, pk);
-        let response = Self::respond(&statement, &commitment, &challenge);
+    /// Prove that the statement is an r-th residue. This is the tally proof of the statement
+    /// itself as the aggregate with a tally of 0; use from_aggregate to prove an announced tally.
+    #[cfg(feature = "std")]
+    pub fn from_statement(statement: ClearResidue, pk: &PublicKey) -> Self {
+        return Self::from_statement_with_rng(statement, pk, &mut OsRng);
+    }
+
+    /// Produce the proof using the supplied source of randomness, so that the proof is
+    /// reproducible under a seeded RNG
+    pub fn from_statement_with_rng(
+        statement: ClearResidue,
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let aggregate = statement.clone_val();
+        let tally = ResidueClass::zero(*pk.get_r().params());
+        return Self::prove(statement, &aggregate, &tally, pk, rng);
+    }
+
+    /// Decrypt the aggregate ciphertext and prove that it decrypts to the returned tally. Panics
+    /// if the aggregate is malformed; see KeyPair::try_decrypt
+    #[cfg(feature = "std")]
+    pub fn from_aggregate(aggregate: &OpaqueResidue, keypair: &KeyPair) -> (ResidueClass, Self) {
+        return Self::from_aggregate_with_rng(aggregate, keypair, &mut OsRng);
+    }
+
+    /// Decrypt and prove the tally using the supplied source of randomness
+    pub fn from_aggregate_with_rng(
+        aggregate: &OpaqueResidue,
+        keypair: &KeyPair,
+        rng: &mut impl CryptoRngCore,
+    ) -> (ResidueClass, Self) {
+        let pk = keypair.get_pk();
+        let tally = keypair.decrypt(aggregate);
+        let statement = *aggregate * pk.invert_y().pow(&tally);
+        let statement = ClearResidue::decompose(statement.clone_residue(), keypair);
+        let proof = Self::prove(statement, aggregate, &tally, pk, rng);
+        return (tally, proof);
+    }
+
+    /// Prove that the statement, which is aggregate * (y ** -tally), is an r-th residue
+    fn prove(
+        statement: ClearResidue,
+        aggregate: &OpaqueResidue,
+        tally: &ResidueClass,
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        let context = Self::context(aggregate, tally);
+        let rounds = RootProof::prove_with_rng(
+            statement.get_val(),
+            statement.get_witness(),
+            DOMAIN,
+            &context,
+            pk,
+            rng,
+        );
+        return Self {
+            statement: statement.into_opaque(),
+            rounds,
+        };
+    }
 
-        // Need to obscure the statement and the commiment for zero-knowledge property
-        let statement = statement.clone_val();
-        let commitment: Vec<OpaqueResidue> =
-            commitment.iter().map(|clear| clear.clone_val()).collect();
-        return Self::new(statement, commitment, challenge, response);
+    /// The soundness of the proof in bits. Each round has a soundness error of 1/r and the
+    /// rounds are independent, so the soundness error of the whole proof is at most
+    /// 2 ** -soundness_bits.
+    pub fn soundness_bits(&self) -> usize {
+        return self.rounds.soundness_bits();
     }
 
-    /// Verify that (x' * (x ** b)) ** r is indeed z'z**b
-    pub fn verify(&self) -> bool {
-        if self.commitment.len() != self.response.len() {
+    /// Check, with nothing more than the public key, that the announced tally is the residue
+    /// class of the aggregate ciphertext. The statement must be aggregate * (y ** -claimed_tally),
+    /// and the proof must show that the statement is an r-th residue, which is the case exactly
+    /// when the aggregate belongs to the claimed residue class.
+    pub fn verify_public(
+        &self,
+        aggregate: &OpaqueResidue,
+        claimed_tally: &ResidueClass,
+        pk: &PublicKey,
+    ) -> bool {
+        let expected = *aggregate * pk.invert_y().pow(claimed_tally);
+        if self.statement != expected {
             return false;
         }
-        // Unfortunately not easily made into functional due to needing three iterators
-        let mut verified = true;
-        for (i, response) in self.response.iter().enumerate() {
-            let lhs = response.clone_val();
-            let commitment = self.commitment.get(i).unwrap().clone();
-            let challenge = self.challenge.get(i).unwrap();
-            let rhs = commitment * self.statement.pow(challenge);
-            if lhs != rhs {
-                verified = false;
-            }
-        }
-        return verified;
+        let context = Self::context(aggregate, claimed_tally);
+        return self.rounds.verify(&self.statement, DOMAIN, &context, pk);
     }
 
-    /// Generate random r-th residues
-    fn generate_commitment(confidence: usize, pk: &PublicKey) -> Vec<ClearResidue> {
-        return (0..confidence)
-            .map(|_| {
-                let zero = DynResidue::new(&BigInt::ZERO, pk.get_r().to_dyn_residue_params());
-                return ClearResidue::random(Some(zero), pk);
-            })
-            .collect::<Vec<ClearResidue>>();
+    /// The aggregate and the tally, which the challenges are bound to
+    fn context(aggregate: &OpaqueResidue, tally: &ResidueClass) -> Vec<u8> {
+        let mut context = aggregate.to_be_bytes().to_vec();
+        context.extend_from_slice(&tally.retrieve().to_be_bytes());
+        return context;
     }
+}
 
-    /// Hash the commitment (element of Z/n) into a residue class (element of Z/r)
-    fn hash_commitment(commitment: &ClearResidue, pk: &PublicKey) -> ResidueClass {
-        let mut hasher = Sha3_256::new();
-        hasher.update(commitment.get_val().retrieve().to_be_bytes());
-        let hash: Vec<u8> = hasher.finalize().to_vec();
-        let class = ResidueClass::from_be_bytes(&hash, pk.get_r());
-        return class;
+impl NonInteractiveProof for TallyProof {
+    type Statement = ClearResidue;
+    type Verifier = PublicKey;
+    type Error = Infallible;
+
+    #[cfg(feature = "std")]
+    fn prove(statement: &ClearResidue, pk: &PublicKey) -> Self {
+        return Self::from_statement(statement.clone(), pk);
     }
 
-    /// Hash each residue into a residue class
-    fn generate_challenge(commitment: &[ClearResidue], pk: &PublicKey) -> Vec<ResidueClass> {
-        return commitment
-            .iter()
-            .map(|z_prime| Self::hash_commitment(z_prime, pk))
-            .collect();
-    }
-
-    /// For each challenge, return (x'x ** b) ** r. However since we need to raise the response
-    /// to the r-th power anyways, we actually return z'z**b but with its decomposition
-    fn respond(
-        statement: &ClearResidue,
-        commitment: &[ClearResidue],
-        challenge: &[ResidueClass],
-    ) -> Vec<ClearResidue> {
-        return commitment
-            .iter()
-            .zip(challenge.iter())
-            .map(|(z_prime, b)| statement.pow(b).clone() * z_prime.clone())
-            .collect();
+    fn verify(&self, pk: &PublicKey) -> Result<bool, Infallible> {
+        let tally = ResidueClass::zero(*pk.get_r().params());
+        let context = Self::context(&self.statement, &tally);
+        return Ok(self.rounds.verify(&self.statement, DOMAIN, &context, pk));
     }
 }
 
+/// Forge a proof that the aggregate decrypts to the claimed tally by answering every round as if
+/// its challenge were 0, which is what grinding the commitments one at a time would achieve
+#[cfg(test)]
+pub(crate) fn forge(
+    aggregate: &OpaqueResidue,
+    claimed_tally: &ResidueClass,
+    pk: &PublicKey,
+) -> TallyProof {
+    let statement = *aggregate * pk.invert_y().pow(claimed_tally);
+    let zero = ResidueClass::zero(*pk.get_r().params());
+    let response: Vec<Witness> = (0..RootProof::rounds(pk))
+        .map(|_| Witness::new(pk.sample_invertible()))
+        .collect();
+    let commitment = response
+        .iter()
+        .map(|witness| witness.rth_power(pk.get_r()))
+        .collect();
+    let challenge = alloc::vec![zero; response.len()];
+    return TallyProof::new(statement, commitment, challenge, response);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::keys::KeyPair;
+    use crate::BigInt;
+    use crypto_bigint::modular::runtime_mod::DynResidue;
 
     #[test]
     fn test_tally_proof_correctness() {
@@ -134,7 +192,101 @@ mod tests {
             keypair.get_pk().get_r().to_dyn_residue_params(),
         );
         let statement = ClearResidue::random(Some(zero), keypair.get_pk());
-        let proof = TallyProof::from_statement(statement, 16, keypair.get_pk());
-        assert!(proof.verify());
+        let proof = TallyProof::from_statement(statement, keypair.get_pk());
+        assert_eq!(
+            NonInteractiveProof::verify(&proof, keypair.get_pk()),
+            Ok(true)
+        );
+    }
+
+    /// Aggregate a few ballots, then prove the tally to someone who only has the public key
+    fn prove_tally(keypair: &KeyPair, votes: &[u8]) -> (OpaqueResidue, ResidueClass, TallyProof) {
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let aggregate = votes
+            .iter()
+            .map(|vote| {
+                let vote = DynResidue::new(&BigInt::from_u8(*vote), r);
+                return ClearResidue::random(Some(vote), keypair.get_pk()).clone_val();
+            })
+            .reduce(|acc, ballot| acc * ballot)
+            .unwrap();
+        let (tally, proof) = TallyProof::from_aggregate(&aggregate, keypair);
+        return (aggregate, tally, proof);
+    }
+
+    #[test]
+    fn test_verify_public() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let (aggregate, tally, proof) = prove_tally(&keypair, &[1, 0, 1, 1]);
+        assert_eq!(tally.retrieve(), BigInt::from_u8(3));
+        assert!(proof.verify_public(&aggregate, &tally, keypair.get_pk()));
+    }
+
+    #[test]
+    fn test_verify_public_wrong_tally() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let (aggregate, tally, proof) = prove_tally(&keypair, &[1, 0, 1, 1]);
+        let r = keypair.get_pk().get_r().to_dyn_residue_params();
+        let off_by_one = tally + ResidueClass::one(r);
+        assert!(!proof.verify_public(&aggregate, &off_by_one, keypair.get_pk()));
+    }
+
+    /// A response that is not an r-th root of z'z^b is rejected
+    #[test]
+    fn test_verify_public_forged_response() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let (aggregate, tally, mut proof) = prove_tally(&keypair, &[1, 0, 1, 1]);
+        let forged = proof.rounds.response[0].clone();
+        proof.rounds.response[0] = forged.clone() * forged;
+        assert!(!proof.verify_public(&aggregate, &tally, keypair.get_pk()));
+    }
+
+    /// A proof without any rounds is rejected instead of passing vacuously
+    #[test]
+    fn test_verify_public_empty_proof() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let (aggregate, _, _) = prove_tally(&keypair, &[1, 0, 1]);
+        let claimed = ResidueClass::from_u64(999, pk.get_r());
+        let statement = aggregate * pk.invert_y().pow(&claimed);
+        let empty = TallyProof::new(statement, vec![], vec![], vec![]);
+        assert!(!empty.verify_public(&aggregate, &claimed, pk));
+    }
+
+    /// A round whose challenge is 0 passes for any statement, so a forger who could pick the
+    /// commitments until every challenge comes out 0 could claim any tally. The challenges are
+    /// recomputed from the whole transcript, so such a proof is rejected.
+    #[test]
+    fn test_verify_public_ground_challenges() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let (aggregate, _, _) = prove_tally(&keypair, &[1, 0, 1]);
+        let claimed = ResidueClass::from_u64(1000, pk.get_r());
+        let forged = forge(&aggregate, &claimed, pk);
+        assert!(!forged.verify_public(&aggregate, &claimed, pk));
+    }
+
+    /// A proof of the statement alone does not pass as the proof of a tally
+    #[test]
+    fn test_statement_proof_is_not_tally_proof() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let pk = keypair.get_pk();
+        let (aggregate, tally, _) = prove_tally(&keypair, &[1, 1]);
+        let statement = aggregate * pk.invert_y().pow(&tally);
+        let statement = ClearResidue::decompose(statement.clone_residue(), &keypair);
+        let proof = TallyProof::from_statement(statement, pk);
+        assert!(!proof.verify_public(&aggregate, &tally, pk));
+    }
+
+    #[test]
+    fn test_tally_soundness_bits() {
+        let keypair = KeyPair::keygen(16, 64, false);
+        let (_, _, proof) = prove_tally(&keypair, &[1, 0]);
+        // r is a 16-bit prime, so each round contributes 15 bits
+        assert_eq!(
+            proof.rounds.commitment.len(),
+            RootProof::SOUNDNESS_BITS.div_ceil(15)
+        );
+        assert!(proof.soundness_bits() >= RootProof::SOUNDNESS_BITS);
     }
 }
@@ -0,0 +1,58 @@
+//! Serde support for sending proof transcripts across a network. Every residue is written as the
+//! fixed-width big-endian hex string of its canonical value. The moduli are not part of the
+//! transcript: the public key is transmitted separately, and the transcript is deserialized
+//! under it through a KeySeed.
+use crate::{arithmetics, keys::PublicKey, proofs::ProofError, BigInt, LIMBS};
+use alloc::string::String;
+use core::{fmt::Write, marker::PhantomData};
+use crypto_bigint::{
+    modular::runtime_mod::{DynResidue, DynResidueParams},
+    Encoding,
+};
+use serde::de;
+
+/// Deserialize a transcript of type T under the public key, e.g.
+/// `KeySeed::<VoterProof>::new(&pk).deserialize(&mut deserializer)`. Deserializing checks that
+/// the transcript is well-formed, but does not verify the proof.
+pub struct KeySeed<'a, T> {
+    pk: &'a PublicKey,
+    transcript: PhantomData<T>,
+}
+
+impl<'a, T> KeySeed<'a, T> {
+    pub fn new(pk: &'a PublicKey) -> Self {
+        return Self {
+            pk,
+            transcript: PhantomData,
+        };
+    }
+
+    pub fn get_pk(&self) -> &'a PublicKey {
+        return self.pk;
+    }
+}
+
+/// The canonical value of the residue as 2 * LIMBS * 8 lowercase hex digits
+pub(crate) fn residue_to_hex(residue: &DynResidue<LIMBS>) -> String {
+    let mut hex = String::with_capacity(2 * BigInt::BYTES);
+    for byte in residue.retrieve().to_be_bytes() {
+        write!(hex, "{byte:02x}").expect("writing to a string cannot fail");
+    }
+    return hex;
+}
+
+/// Read a residue written by residue_to_hex. The string must be exactly as wide, and the value
+/// must be reduced under the modulus.
+pub(crate) fn residue_from_hex<E: de::Error>(
+    hex: &str,
+    params: &DynResidueParams<LIMBS>,
+) -> Result<DynResidue<LIMBS>, E> {
+    if hex.len() != 2 * BigInt::BYTES {
+        return Err(E::invalid_length(hex.len(), &"a fixed-width hex string"));
+    }
+    let val = arithmetics::parse_hex(hex).map_err(E::custom)?;
+    if &val >= params.modulus() {
+        return Err(E::custom(ProofError::OutOfRange));
+    }
+    return Ok(DynResidue::new(&val, *params));
+}
@@ -0,0 +1,196 @@
+//! This is the proof in which the prover convinces the verifier that a ciphertext encrypts zero,
+//! i.e. that the statement is an r-th residue, without revealing its witness. Each round is:
+//!
+//! statement: z = x^r
+//! commitment: z' = (x')^r
+//! challenge: b <- Z/r
+//! response: x'x^b
+//! verification: response^r = z'z^b
+//!
+//! These are the rounds of proofs::RootProof with nothing hashed in beyond the key and the
+//! statement, which makes it lighter than a ballot proof over the two classes {0, 1} when only
+//! "is zero" is needed. The proof is non-interactive and can be checked by anyone holding the
+//! public key.
+use crate::{
+    arithmetics::{ClearResidue, OpaqueResidue},
+    keys::PublicKey,
+    proofs::{NonInteractiveProof, RootProof},
+};
+use core::convert::Infallible;
+use crypto_bigint::rand_core::CryptoRngCore;
+#[cfg(feature = "std")]
+use crypto_bigint::rand_core::OsRng;
+
+/// The domain tag that the Fiat-Shamir hash of this proof starts with
+pub(crate) const DOMAIN: &[u8] = b"benaloh/zero/v3";
+
+/// Proof that the statement encrypts the residue class 0
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ZeroProof {
+    statement: OpaqueResidue,
+    rounds: RootProof,
+}
+
+impl ZeroProof {
+    /// Prove that the statement encrypts zero. Panics if the statement is in any other class
+    #[cfg(feature = "std")]
+    pub fn prove(statement: &ClearResidue, pk: &PublicKey) -> Self {
+        return Self::prove_with_rng(statement, pk, &mut OsRng);
+    }
+
+    /// Produce the proof using the supplied source of randomness, so that the proof is
+    /// reproducible under a seeded RNG
+    pub fn prove_with_rng(
+        statement: &ClearResidue,
+        pk: &PublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> Self {
+        if !statement.is_exact_residue() {
+            panic!("the statement does not encrypt zero");
+        }
+        let rounds = RootProof::prove_with_rng(
+            statement.get_val(),
+            statement.get_witness(),
+            DOMAIN,
+            &[],
+            pk,
+            rng,
+        );
+        return Self {
+            statement: *statement.as_opaque(),
+            rounds,
+        };
+    }
+
+    pub fn get_statement(&self) -> &OpaqueResidue {
+        return &self.statement;
+    }
+
+    /// Check that the statement is an r-th residue. Only the public key is needed.
+    pub fn verify(&self, pk: &PublicKey) -> bool {
+        return self.rounds.verify(&self.statement, DOMAIN, &[], pk);
+    }
+
+    /// The soundness of the proof in bits. Each round has a soundness error of 1/r, so the
+    /// soundness error of the whole proof is at most 2 ** -soundness_bits.
+    pub fn soundness_bits(&self) -> usize {
+        return self.rounds.soundness_bits();
+    }
+}
+
+impl NonInteractiveProof for ZeroProof {
+    type Statement = ClearResidue;
+    type Verifier = PublicKey;
+    type Error = Infallible;
+
+    #[cfg(feature = "std")]
+    fn prove(statement: &ClearResidue, pk: &PublicKey) -> Self {
+        return ZeroProof::prove(statement, pk);
+    }
+
+    fn verify(&self, pk: &PublicKey) -> Result<bool, Infallible> {
+        return Ok(ZeroProof::verify(self, pk));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arithmetics::{ResidueClass, Witness},
+        keys::KeyPair,
+        GROUPSIZE, RINGSIZE,
+    };
+
+    #[test]
+    fn test_zero_proof_correctness() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let zero = ResidueClass::zero(*pk.get_r().params());
+        for _ in 0..4 {
+            let statement = ClearResidue::random(Some(zero.clone_residue()), pk);
+            let proof = ZeroProof::prove(&statement, pk);
+            assert!(proof.verify(pk));
+            assert_eq!(proof.get_statement(), statement.get_val());
+            assert!(proof.soundness_bits() >= RootProof::SOUNDNESS_BITS);
+            assert_eq!(
+                proof.rounds.commitment.len(),
+                RootProof::SOUNDNESS_BITS.div_ceil(RINGSIZE - 1)
+            );
+        }
+    }
+
+    /// A prover who follows the protocol with a ciphertext of a non-zero class, using its true
+    /// witness, is caught: the response is off by y ** (cb)
+    #[test]
+    fn test_zero_proof_soundness() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let one = ResidueClass::from_u64(1, pk.get_r());
+        let zero = ResidueClass::zero(*one.params());
+        let statement = ClearResidue::random(Some(one.clone_residue()), pk);
+        let rounds = RootProof::prove_with_rng(
+            statement.get_val(),
+            statement.get_witness(),
+            DOMAIN,
+            &[],
+            pk,
+            &mut OsRng,
+        );
+        let forged = ZeroProof {
+            statement: *statement.as_opaque(),
+            rounds,
+        };
+        assert!(!forged.verify(pk));
+
+        // an honest proof does not carry over to another statement
+        let zero = ClearResidue::random(Some(zero.clone_residue()), pk);
+        let mut proof = ZeroProof::prove(&zero, pk);
+        proof.statement = statement.clone_val();
+        assert!(!proof.verify(pk));
+    }
+
+    /// A round whose challenge is 0 passes for any statement. Answering every round that way, as
+    /// grinding the commitments one at a time would allow, does not survive the challenges being
+    /// recomputed from the statement and all of the commitments, and neither does an empty proof.
+    #[test]
+    fn test_zero_proof_ground_challenges() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let one = ResidueClass::from_u64(1, pk.get_r());
+        let zero = ResidueClass::zero(*one.params());
+        let statement = ClearResidue::random(Some(one.clone_residue()), pk).clone_val();
+        let blinds = (0..RootProof::rounds(pk))
+            .map(|_| Witness::new(pk.sample_invertible()))
+            .collect::<Vec<Witness>>();
+        let mut forged = ZeroProof {
+            statement,
+            rounds: RootProof::new(
+                blinds
+                    .iter()
+                    .map(|blind| blind.rth_power(pk.get_r()))
+                    .collect(),
+                alloc::vec![zero; blinds.len()],
+                blinds,
+            ),
+        };
+        assert!(!forged.verify(pk));
+
+        forged.rounds.commitment.clear();
+        forged.rounds.challenge.clear();
+        forged.rounds.response.clear();
+        assert!(!forged.verify(pk));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_proof_nonzero_statement() {
+        let keypair = KeyPair::keygen(RINGSIZE, GROUPSIZE, false);
+        let pk = keypair.get_pk();
+        let statement = ClearResidue::random(
+            Some(ResidueClass::from_u64(1, pk.get_r()).clone_residue()),
+            pk,
+        );
+        ZeroProof::prove(&statement, pk);
+    }
+}
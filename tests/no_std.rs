@@ -0,0 +1,31 @@
+//! The residue arithmetic and the keys without the standard library. This test crate is itself
+//! no_std, so building it without default features shows that the core types need neither std
+//! nor an allocator:
+//!
+//! cargo test --no-default-features --test no_std
+#![no_std]
+
+use benaloh_cryptosystem::{
+    arithmetics::{ClearResidue, ResidueClass},
+    keys::KeyPair,
+    BigInt,
+};
+use crypto_bigint::modular::runtime_mod::DynResidue;
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+#[test]
+fn test_encrypt_decrypt() {
+    let mut rng = ChaCha20Rng::seed_from_u64(876);
+    let keypair = KeyPair::keygen_with_rng(16, 64, false, &mut rng);
+    let pk = keypair.get_pk();
+    assert!(keypair.check_perfect_consonance());
+
+    let class = DynResidue::new(&BigInt::from_u8(3), *pk.get_r().params());
+    let witness = pk.sample_invertible_with_rng(&mut rng);
+    let ciphertext = ClearResidue::compose(class, witness, pk);
+    assert!(ciphertext.verify_spoiled(pk));
+    assert_eq!(
+        keypair.decrypt(&ciphertext.clone_val()),
+        ResidueClass::new(class)
+    );
+}